@@ -4,7 +4,7 @@ use std::path::PathBuf;
 
 use crate::{
     cli::Cli,
-    models::{BACKUP_DIR, DB_FILE, KaguyaError, VAULT_CONFIG_FILE},
+    models::{BACKUP_DIR, DB_FILE, KaguyaError, OBJECTS_DIR, VAULT_CONFIG_FILE},
     utils::path::{get_global_config_path, get_vault_dir},
 };
 
@@ -15,6 +15,8 @@ pub struct AppContext {
     pub vault_dir: PathBuf,
     pub vault_config_path: PathBuf,
     pub backup_dir: PathBuf,
+    /// Content-addressed object store shared by all games
+    pub objects_dir: PathBuf,
     pub db_path: PathBuf,
     pub dry_run: bool,
 }
@@ -25,6 +27,7 @@ impl AppContext {
         let vault_dir = get_vault_dir(&cli.vault)?;
         let vault_config_path = vault_dir.join(VAULT_CONFIG_FILE);
         let backup_dir = vault_dir.join(BACKUP_DIR);
+        let objects_dir = vault_dir.join(OBJECTS_DIR);
         let db_path = vault_dir.join(DB_FILE);
 
         Ok(Self {
@@ -32,6 +35,7 @@ impl AppContext {
             vault_config_path,
             global_config_path,
             backup_dir,
+            objects_dir,
             db_path,
             dry_run: cli.dry_run,
         })
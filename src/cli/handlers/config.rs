@@ -3,8 +3,10 @@
 use crate::{
     cli::{AppContext, ConfigSubcommands},
     core::ConfigService,
-    db_manager::DbManager,
-    models::{AddGameRequest, KaguyaError, RmGameRequest, requests::ListGameRequest},
+    models::{
+        AddGameRequest, EditConfigRequest, IssueLevel, KaguyaError, RmGameRequest,
+        requests::ListGameRequest,
+    },
     utils::path::{to_absolute_path, transform_paths_option},
 };
 
@@ -13,8 +15,7 @@ pub fn handle_config(
     subcommand: ConfigSubcommands,
     context: &AppContext,
 ) -> Result<(), KaguyaError> {
-    let db = DbManager::new(&context.db_path, &context.vault_config_path)?;
-    let mut config_service = ConfigService::new(context.clone(), db);
+    let mut config_service = ConfigService::new(context.clone());
 
     match subcommand {
         ConfigSubcommands::Add {
@@ -43,7 +44,40 @@ pub fn handle_config(
             let request = RmGameRequest { id, purge };
             config_service.rm_game(&request)?
         }
+
+        ConfigSubcommands::Check => handle_check(&config_service)?,
+
+        ConfigSubcommands::Edit { id } => {
+            let request = EditConfigRequest { id };
+            config_service.edit(&request)?
+        }
+    }
+    Ok(())
+}
+
+/// Handles the logic for printing problems of the vault config.
+fn handle_check(service: &ConfigService) -> Result<(), KaguyaError> {
+    let report = service.check()?;
+
+    for issue in &report.issues {
+        let level = match issue.level {
+            IssueLevel::Warning => "WARNING",
+            IssueLevel::Error => "ERROR",
+        };
+        match &issue.game_id {
+            Some(id) => println!("\t[{}] {}: {}", id, level, issue.message),
+            None => println!("\t{}: {}", level, issue.message),
+        }
+    }
+
+    if report.errors() > 0 {
+        return Err(KaguyaError::ConfigCheckFailed(report.errors()));
     }
+    println!(
+        "Checked {} game(s), {} warning(s), no errors found.",
+        report.games,
+        report.warnings()
+    );
     Ok(())
 }
 
@@ -0,0 +1,78 @@
+//! Handlers for all subcommands under the `kaguya db` command.
+
+use crate::{
+    cli::{AppContext, DbSubcommands},
+    db_manager::{
+        DbManager,
+        sqlite::{DbManagerMigrationExt, migration::SCHEMA_VERSION},
+    },
+    fs_utils::lock::{LockMode, VaultLock},
+    models::KaguyaError,
+};
+
+/// Handles all `kaguya db` subcommands.
+pub fn handle_db(subcommand: DbSubcommands, context: &AppContext) -> Result<(), KaguyaError> {
+    match subcommand {
+        DbSubcommands::Migrate => handle_migrate(context)?,
+    }
+
+    Ok(())
+}
+
+/// Handles the logic for applying, or listing with '--dry-run', pending migrations.
+fn handle_migrate(context: &AppContext) -> Result<(), KaguyaError> {
+    // Opening the database would create it
+    if context.dry_run && !context.db_path.exists() {
+        println!(
+            "No database at '{}', it would be created with schema version {}.",
+            context.db_path.display(),
+            SCHEMA_VERSION
+        );
+        return Ok(());
+    }
+
+    let mode = if context.dry_run {
+        LockMode::Shared
+    } else {
+        LockMode::Exclusive
+    };
+    // Opening the database creates the vault directory holding the lock
+    let mut db = DbManager::open(&context.db_path)?;
+    let _lock = VaultLock::acquire(&context.vault_dir, mode)?;
+    let report = db.migrate(context.dry_run)?;
+    if report.applied.is_empty() {
+        println!(
+            "Database schema is up to date (version {}).",
+            SCHEMA_VERSION
+        );
+        return Ok(());
+    }
+
+    let status = if context.dry_run {
+        "PENDING"
+    } else {
+        "APPLIED"
+    };
+    for migration in &report.applied {
+        println!("\t{} V{}__{}", status, migration.version, migration.name);
+    }
+
+    let from = match report.from {
+        Some(version) => format!("schema version {}", version),
+        None => "a new database".to_string(),
+    };
+    if context.dry_run {
+        println!(
+            "{} migration(s) would migrate {} to version {}.",
+            report.applied.len(),
+            from,
+            SCHEMA_VERSION
+        );
+    } else {
+        println!("Migrated {} to schema version {}.", from, SCHEMA_VERSION);
+    }
+    if let Some(backup_path) = &report.backup_path {
+        println!("Previous database saved to '{}'.", backup_path.display());
+    }
+    Ok(())
+}
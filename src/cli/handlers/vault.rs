@@ -4,19 +4,38 @@ use crate::{
     cli::{AppContext, parser::VaultSubcommands},
     core::VaultService,
     db_manager::DbManager,
-    models::{BackupRequest, KaguyaError, requests::RestoreRequest},
-    utils::path::{to_absolute_path, transform_paths_option},
+    models::{
+        BackupRequest, KaguyaError, RepairOutcome, StatusRequest,
+        requests::{
+            CheckRequest, ExportRequest, PruneRequest, ReindexRequest, RekeyRequest, RepairRequest,
+            RestoreRequest,
+        },
+    },
+    utils::{
+        path::{to_absolute_path, transform_paths_option},
+        time::format_time_ago,
+    },
 };
 
 pub fn handle_vault(subcommand: VaultSubcommands, context: &AppContext) -> Result<(), KaguyaError> {
+    // Opening the database locks the vault, exclusively if it migrates or syncs it
     let db = DbManager::new(&context.db_path, &context.vault_config_path)?;
     let mut vault_service = VaultService::new(context.clone(), db);
 
     match subcommand {
-        VaultSubcommands::Backup { id, paths } => {
+        VaultSubcommands::Backup {
+            id,
+            paths,
+            force,
+            paranoid,
+            jobs,
+        } => {
             let request = BackupRequest {
                 id,
                 paths: transform_paths_option(paths, to_absolute_path)?,
+                force,
+                paranoid,
+                jobs,
             };
             vault_service.backup(request)?
         }
@@ -30,8 +49,238 @@ pub fn handle_vault(subcommand: VaultSubcommands, context: &AppContext) -> Resul
             vault_service.restore(&request)?
         }
 
+        VaultSubcommands::Export {
+            id,
+            version,
+            format,
+            output,
+        } => {
+            let request = ExportRequest {
+                id,
+                version,
+                format,
+                output: output.map(|p| to_absolute_path(&p)).transpose()?,
+            };
+            vault_service.export(&request)?
+        }
+
+        VaultSubcommands::Prune { id, version, purge } => {
+            let request = PruneRequest { id, version, purge };
+            vault_service.prune(&request)?
+        }
+
+        VaultSubcommands::Status { id, paranoid } => {
+            let request = StatusRequest { id, paranoid };
+            handle_status(&request, &mut vault_service)?;
+        }
+
+        VaultSubcommands::Stats => handle_stats(&vault_service)?,
+
+        VaultSubcommands::Check {
+            id,
+            verify_signatures,
+        } => {
+            let request = CheckRequest {
+                id,
+                verify_signatures,
+            };
+            handle_check(&request, &vault_service)?;
+        }
+
+        VaultSubcommands::Repair { id } => {
+            let request = RepairRequest { id };
+            handle_repair(&request, &vault_service)?;
+        }
+
+        VaultSubcommands::Reindex { id } => {
+            let request = ReindexRequest { id };
+            handle_reindex(&request, &mut vault_service)?;
+        }
+
+        VaultSubcommands::Rekey { new_key_file } => {
+            let request = RekeyRequest {
+                new_key_file: new_key_file.map(|p| to_absolute_path(&p)).transpose()?,
+            };
+            vault_service.rekey(&request)?
+        }
+
         _ => todo!(),
     }
 
     Ok(())
 }
+
+/// Handles the logic for printing backup status.
+fn handle_status(request: &StatusRequest, service: &mut VaultService) -> Result<(), KaguyaError> {
+    let statuses = service.status(request)?;
+
+    if statuses.is_empty() {
+        println!("Games list is empty, use 'kaguya config add' to add some games.");
+        return Ok(());
+    }
+
+    for game in &statuses {
+        match &game.last_backup {
+            Some(backup) => println!(
+                "{} ({}): last backup {} ({})",
+                game.name,
+                game.id,
+                backup.version,
+                format_time_ago(&backup.timestamp)
+            ),
+            None => println!("{} ({}): never backed up", game.name, game.id),
+        }
+
+        for path in &game.paths {
+            println!("\t- {}: {}", path.kind, path.path.display());
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Handles the logic for printing vault statistics.
+fn handle_stats(service: &VaultService) -> Result<(), KaguyaError> {
+    let stats = service.stats()?;
+
+    for game in &stats.games {
+        println!(
+            "{} ({}): {} version(s), {} file(s), {} bytes stored for {} bytes of saves",
+            game.name, game.id, game.versions, game.files, game.stored_bytes, game.original_bytes
+        );
+    }
+    println!();
+
+    println!("Archives and mirrors: {} bytes", stats.archive_bytes);
+    println!(
+        "Object store: {} object(s), {} bytes on disk",
+        stats.objects, stats.object_store_bytes
+    );
+    println!(
+        "\t{} manifest(s) referencing {} bytes, {} unique bytes",
+        stats.manifests, stats.logical_bytes, stats.unique_bytes
+    );
+    if let (Some(dedup), Some(storage)) = (stats.dedup_ratio(), stats.storage_ratio()) {
+        println!(
+            "\tDedup ratio: {:.2}x, with compression: {:.2}x",
+            dedup, storage
+        );
+    }
+
+    Ok(())
+}
+
+/// Handles the logic for printing integrity check results.
+fn handle_check(request: &CheckRequest, service: &VaultService) -> Result<(), KaguyaError> {
+    let checks = service.check(request)?;
+
+    let mut problems = 0;
+    for check in &checks {
+        match &check.problem {
+            None => println!(
+                "\t[{}] OK {}: {}",
+                check.game_id, check.version, check.original_path
+            ),
+            Some(problem) => {
+                problems += 1;
+                println!(
+                    "\t[{}] FAILED {}: {} ('{}'): {}",
+                    check.game_id, check.version, check.original_path, check.archive_path, problem
+                );
+            }
+        }
+    }
+
+    if problems > 0 {
+        return Err(KaguyaError::CheckFailed(problems));
+    }
+    println!(
+        "Checked {} backup file(s), no problems found.",
+        checks.len()
+    );
+    Ok(())
+}
+
+/// Handles the logic for printing repair results.
+fn handle_repair(request: &RepairRequest, service: &VaultService) -> Result<(), KaguyaError> {
+    let repairs = service.repair(request)?;
+
+    let mut failed = 0;
+    for repair in &repairs {
+        match &repair.outcome {
+            RepairOutcome::Repaired { blocks } => println!(
+                "\t[{}] REPAIRED {}: {} ('{}'), {} damaged block(s) rebuilt",
+                repair.game_id, repair.version, repair.original_path, repair.archive_path, blocks
+            ),
+            RepairOutcome::ParityRewritten => println!(
+                "\t[{}] PARITY REWRITTEN {}: {} ('{}')",
+                repair.game_id, repair.version, repair.original_path, repair.archive_path
+            ),
+            RepairOutcome::Failed(reason) => {
+                failed += 1;
+                println!(
+                    "\t[{}] FAILED {}: {} ('{}'): {}",
+                    repair.game_id,
+                    repair.version,
+                    repair.original_path,
+                    repair.archive_path,
+                    reason
+                );
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(KaguyaError::RepairFailed(failed));
+    }
+    if repairs.is_empty() {
+        println!("No damage found, nothing to repair.");
+    } else {
+        println!("Repaired {} backup file(s).", repairs.len());
+    }
+    Ok(())
+}
+
+/// Handles the logic for printing reindex results.
+fn handle_reindex(request: &ReindexRequest, service: &mut VaultService) -> Result<(), KaguyaError> {
+    let report = service.reindex(request)?;
+
+    for game in &report.games {
+        println!(
+            "\t[{}] {} version(s), {} backup file(s) indexed ({} from manifests, {} inferred), {} version(s) before",
+            game.id,
+            game.versions,
+            game.files,
+            game.from_manifests,
+            game.versions - game.from_manifests,
+            game.previous_versions
+        );
+    }
+    for issue in &report.mismatched {
+        println!("\tMISMATCHED '{}': {}", issue.path.display(), issue.reason);
+    }
+    for issue in &report.unattributed {
+        println!(
+            "\tUNATTRIBUTED '{}': {}",
+            issue.path.display(),
+            issue.reason
+        );
+    }
+
+    let versions: usize = report.games.iter().map(|g| g.versions).sum();
+    println!("Reindexed {} version(s).", versions);
+    if !report.mismatched.is_empty() {
+        println!(
+            "{} backup file(s) don't match their manifest, run 'kaguya vault check' for details.",
+            report.mismatched.len()
+        );
+    }
+    if !report.unattributed.is_empty() {
+        println!(
+            "{} file(s) could not be attributed and were left out of the index.",
+            report.unattributed.len()
+        );
+    }
+    Ok(())
+}
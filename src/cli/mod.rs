@@ -3,8 +3,9 @@
 // pub use handlers::config::handle_config;
 pub use context::AppContext;
 pub use handlers::config::handle_config;
+pub use handlers::db::handle_db;
 pub use handlers::vault::handle_vault;
-pub use parser::{Cli, Commands, ConfigSubcommands};
+pub use parser::{Cli, Commands, ConfigSubcommands, DbSubcommands};
 
 pub mod context;
 pub mod handlers;
@@ -48,6 +48,10 @@ pub enum Commands {
     /// Manage vault of kaguya
     #[command(subcommand)]
     Vault(VaultSubcommands),
+
+    /// Maintain the vault database
+    #[command(subcommand)]
+    Db(DbSubcommands),
 }
 
 #[derive(Debug, Subcommand)]
@@ -88,6 +92,16 @@ pub enum ConfigSubcommands {
         #[arg(short = 'r', long)]
         purge: bool,
     },
+
+    /// Check the vault config for mistakes, e.g. overlapping or missing paths
+    Check,
+
+    /// Edit the vault config, or a single game, in '$EDITOR', checking it before saving
+    Edit {
+        /// Game ID, edit only the config of this game
+        #[arg(short = 'i', long)]
+        id: Option<String>,
+    },
 }
 
 #[derive(Debug, Subcommand)]
@@ -103,6 +117,20 @@ pub enum VaultSubcommands {
         /// Leave empty to backup all paths in the config
         #[arg(short, long, action = clap::ArgAction::Append, requires = "id")]
         paths: Option<Vec<PathBuf>>,
+
+        /// Create a new version even if saves have not changed since the latest backup
+        #[arg(short, long)]
+        force: bool,
+
+        /// Number of paths to hash and compress concurrently
+        /// (default: 'jobs' in the vault config, or 1)
+        #[arg(short, long, value_name = "N")]
+        jobs: Option<usize>,
+
+        /// Rehash every file instead of trusting cached hashes of files
+        /// whose size and modification time are unchanged
+        #[arg(long)]
+        paranoid: bool,
     },
 
     /// Restore saves and configurations from backups in vault
@@ -121,6 +149,25 @@ pub enum VaultSubcommands {
         paths: Option<Vec<PathBuf>>,
     },
 
+    /// Export a backup version as a single archive for sharing
+    Export {
+        /// Game ID
+        #[arg(short, long)]
+        id: String,
+
+        /// Backup version (default: latest)
+        #[arg(short, long)]
+        version: Option<String>,
+
+        /// Archive format: 'zip', 'tar.gz', 'tar.zst', 'tar.xz' or 'tar'
+        #[arg(short, long, default_value = "zip")]
+        format: String,
+
+        /// Output file (default: '<ID>-<VERSION>.<FORMAT>' in the current directory)
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
     /// Prune old backups based on retention policy,
     /// or delete specific backups
     Prune {
@@ -137,6 +184,21 @@ pub enum VaultSubcommands {
         purge: bool,
     },
 
+    /// Show which games have changed since their latest backup
+    Status {
+        /// Game ID (leave empty for all games)
+        #[arg(short, long)]
+        id: Option<String>,
+
+        /// Rehash every file instead of trusting cached hashes of files
+        /// whose size and modification time are unchanged
+        #[arg(long)]
+        paranoid: bool,
+    },
+
+    /// Show space usage of each game and deduplication of the object store
+    Stats,
+
     /// Print backup, restore and prune history
     History {
         /// Game ID (leave empty for all games)
@@ -146,5 +208,44 @@ pub enum VaultSubcommands {
 
     /// Check integrity of all backups
     /// (verify file existence and hash consistency and metadata validity)
-    Check,
+    Check {
+        /// Game ID (leave empty for all games)
+        #[arg(short, long)]
+        id: Option<String>,
+
+        /// Also verify signed manifests with 'signing_key', detecting edited,
+        /// replaced or reordered archives and versions
+        #[arg(long)]
+        verify_signatures: bool,
+    },
+
+    /// Repair damaged backups in place from their parity files
+    Repair {
+        /// Game ID (leave empty for all games)
+        #[arg(short, long)]
+        id: Option<String>,
+    },
+
+    /// Rebuild backup records from the vault directory,
+    /// from version manifests or inferred from the layout of older versions
+    Reindex {
+        /// Game ID (leave empty for all games)
+        #[arg(short, long)]
+        id: Option<String>,
+    },
+
+    /// Change the passphrase of the vault encryption key
+    Rekey {
+        /// File holding the new passphrase
+        /// (default: 'KAGUYA_NEW_PASSPHRASE' environment variable, or a prompt)
+        #[arg(long)]
+        new_key_file: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DbSubcommands {
+    /// Apply pending schema migrations, backing up the database first
+    /// (use '--dry-run' to only list them)
+    Migrate,
 }
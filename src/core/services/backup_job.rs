@@ -0,0 +1,426 @@
+//! Backup job of a single path, executed by the worker pool of 'vault backup'.
+//!
+//! A [`PathJob`] never touches the database: everything it needs is loaded by
+//! [`VaultService`](crate::core::VaultService) beforehand, and the returned
+//! [`PathJobResult`] is persisted by it afterwards, so DB writes stay serialized.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    fs_utils::{
+        archive::{ArchiveInfo, compress_archive, tar_bytes},
+        codec::{ArchiveCodec, CompressionSettings},
+        compressibility::{STORE_ONLY_THRESHOLD, incompressible_share},
+        crypto::DataKey,
+        delta::{DeltaFile, MAX_DELTA_SPAN, reconstruct, write_delta},
+        hash::{FileIndex, calculate_entry_checksum_cached},
+        metadata::collect_entry_metadata,
+        mirror::{PreviousMirror, mirror_tree},
+        objects::{ObjectStore, store_tree},
+        parity::write_parity,
+        storage::StorageMode,
+    },
+    models::{
+        KaguyaError,
+        db::{BackupFile, BackupFileEntry, DeltaLink},
+    },
+    utils::path::get_file_name,
+};
+
+/// Everything needed to back up a single configured path.
+#[derive(Debug)]
+pub struct PathJob {
+    /// Index of the game in the list of games being backed up
+    pub game_index: usize,
+    pub path: PathBuf,
+    /// Version directory the archive is written to, created by the service beforehand
+    pub version_dir: PathBuf,
+    /// Archive format and level of new archives
+    pub compression: CompressionSettings,
+    /// Vault key new archives are encrypted with, if encryption is enabled
+    pub encryption: Option<DataKey>,
+    /// Whether the path is archived, stored in `objects` as whole files or chunks, mirrored,
+    /// or stored as a delta
+    pub storage: StorageMode,
+    pub objects: ObjectStore,
+    /// Length of delta chains before a new keyframe
+    pub keyframe_interval: u32,
+    /// Largest path stored as a delta in bytes, larger paths are archived instead
+    pub delta_max_size: u64,
+    /// Parity written next to the stored file, in percent of its size (0 disables)
+    pub parity: u32,
+    /// Cached file hashes of the path
+    pub file_index: FileIndex,
+    /// Latest backup of the path
+    pub previous: Option<PreviousBackup>,
+    pub force: bool,
+    pub paranoid: bool,
+}
+
+/// Latest backup of a path, loaded by the service for its [`PathJob`].
+#[derive(Debug)]
+pub struct PreviousBackup {
+    pub version: String,
+    pub source_checksum: Option<String>,
+    /// Archive format of the backup, or the storage mode it is not an archive of
+    pub codec: Option<String>,
+    /// Whether the backup is an archive encrypted with the vault key
+    pub encrypted: bool,
+    /// The backup itself if it is a mirror, for hard-linking unchanged files
+    pub mirror: Option<PreviousMirror>,
+    /// The backup itself if it is a delta file, to store the next delta against
+    pub delta: Option<PreviousDelta>,
+}
+
+/// Latest backup of a path stored as a delta file, with the chain it is part of.
+#[derive(Debug)]
+pub struct PreviousDelta {
+    pub file_id: i64,
+    pub link: DeltaLink,
+    /// Delta files from the keyframe up to the backup itself
+    pub chain: Vec<DeltaFile>,
+}
+
+/// What happened to a single path.
+#[derive(Debug)]
+pub enum PathOutcome {
+    /// The path is identical to the given version and was not archived.
+    Skipped { version: String },
+
+    /// The path was archived; `backup_id` of the record is not yet assigned.
+    /// Real metadata of its files comes along, since the archive only holds normalized headers,
+    /// and so does its chain link if it was stored as a delta.
+    BackedUp(BackupFile, Vec<BackupFileEntry>, Option<DeltaLink>),
+}
+
+/// A backed up path as persisted by the service: its record, the metadata of its files,
+/// and its chain link if it was stored as a delta.
+pub type BackupFileRecord = (BackupFile, Vec<BackupFileEntry>, Option<DeltaLink>);
+
+/// Result of a [`PathJob`], sent back to the service.
+#[derive(Debug)]
+pub struct PathJobResult {
+    pub game_index: usize,
+    pub path: PathBuf,
+    /// Refreshed file index, `None` if the path could not be hashed
+    pub file_index: Option<FileIndex>,
+    pub outcome: Result<PathOutcome, KaguyaError>,
+}
+
+impl PathJob {
+    /// Hash the path, and archive it unless it is unchanged since the latest backup.
+    pub fn run(self) -> PathJobResult {
+        let (source_checksum, file_index) =
+            match calculate_entry_checksum_cached(&self.path, &self.file_index, self.paranoid) {
+                Ok(result) => result,
+                Err(e) => {
+                    return PathJobResult {
+                        game_index: self.game_index,
+                        path: self.path,
+                        file_index: None,
+                        outcome: Err(e),
+                    };
+                }
+            };
+
+        let outcome = match &self.previous {
+            Some(
+                previous @ PreviousBackup {
+                    version,
+                    source_checksum: Some(previous_checksum),
+                    ..
+                },
+            ) if !self.force
+                && *previous_checksum == source_checksum
+                && self.stores_like(previous, &file_index) =>
+            {
+                Ok(PathOutcome::Skipped {
+                    version: version.clone(),
+                })
+            }
+            _ => self
+                .perform_backup_and_collect_meta(source_checksum, &file_index)
+                .map(|(record, entries, delta)| PathOutcome::BackedUp(record, entries, delta)),
+        };
+
+        PathJobResult {
+            game_index: self.game_index,
+            path: self.path,
+            file_index: Some(file_index),
+            outcome,
+        }
+    }
+
+    // Performs the backup of the path and collects metadata.
+    fn perform_backup_and_collect_meta(
+        &self,
+        source_checksum: String,
+        file_index: &FileIndex,
+    ) -> Result<BackupFileRecord, KaguyaError> {
+        let mut entries = collect_entry_metadata(&self.path)?;
+        for entry in &mut entries {
+            entry.hash = file_index.get(&entry.rel_path).map(|e| e.hash.clone());
+        }
+
+        let original_size_bytes = original_size(file_index);
+        let storage = self.effective_storage(file_index);
+        if storage != self.storage {
+            println!(
+                "\t'{}' is larger than 'delta_max_size_mb', storing a full archive.",
+                self.path.display()
+            );
+        }
+
+        let mut delta = None;
+        let (archive_path, info, codec) = match storage {
+            StorageMode::Archive => {
+                let compression = self.choose_compression(file_index)?;
+
+                // Perform the actual file system backup, size and checksum come out of the same pass
+                let (archive_path, info) = backup_single_path(
+                    &self.path,
+                    &self.version_dir,
+                    &compression,
+                    self.encryption.as_ref(),
+                )?;
+                (archive_path, info, compression.codec.to_string())
+            }
+            StorageMode::Objects | StorageMode::Chunks => {
+                let (manifest_path, info) = store_single_path(
+                    &self.path,
+                    &self.version_dir,
+                    file_index,
+                    &self.objects,
+                    storage == StorageMode::Chunks,
+                )?;
+                (manifest_path, info, storage.to_string())
+            }
+            StorageMode::Mirror => {
+                let previous = self.previous.as_ref().and_then(|p| p.mirror.as_ref());
+                let (mirror_path, info) = mirror_single_path(
+                    &self.path,
+                    &self.version_dir,
+                    file_index,
+                    previous,
+                    &source_checksum,
+                )?;
+                (mirror_path, info, storage.to_string())
+            }
+            StorageMode::Delta => {
+                let previous = self.previous.as_ref().and_then(|p| p.delta.as_ref());
+                let (delta_path, info, link) = delta_single_path(
+                    &self.path,
+                    &self.version_dir,
+                    previous,
+                    self.keyframe_interval,
+                )?;
+                delta = Some(link);
+                (delta_path, info, storage.to_string())
+            }
+        };
+        // Mirrors are directories, every other storage mode writes a single file
+        if self.parity > 0 && archive_path.is_file() {
+            write_parity(&archive_path, self.parity)?;
+        }
+
+        // Collect metadata
+        let record = BackupFile {
+            id: 0,
+            backup_id: 0,
+            original_path: self.path.to_string_lossy().to_string(),
+            archive_path: archive_path.to_string_lossy().to_string(),
+            size_bytes: info.size_bytes,
+            checksum: info.checksum,
+            source_checksum: Some(source_checksum),
+            codec: Some(codec),
+            original_size_bytes: Some(original_size_bytes),
+        };
+        Ok((record, entries, delta))
+    }
+
+    // Deltas are built in memory, a path too large for that is archived as a whole
+    fn effective_storage(&self, file_index: &FileIndex) -> StorageMode {
+        match self.storage {
+            StorageMode::Delta if original_size(file_index) as u64 > self.delta_max_size => {
+                StorageMode::Archive
+            }
+            storage => storage,
+        }
+    }
+
+    // Whether the previous backup is stored the way this job would store the path.
+    // An unchanged path is backed up again once its storage mode, archive format or
+    // encryption changes.
+    fn stores_like(&self, previous: &PreviousBackup, file_index: &FileIndex) -> bool {
+        if previous.encrypted != self.encryption.is_some() {
+            return false;
+        }
+        let storage = self.effective_storage(file_index);
+        // Backups recorded before their codec was are archives
+        let Some(codec) = previous.codec.as_deref() else {
+            return storage == StorageMode::Archive;
+        };
+        match storage {
+            // Adaptive compression may have stored the path in a plain tar
+            StorageMode::Archive => {
+                codec == self.compression.codec.to_string()
+                    || (self.compression.adaptive
+                        && codec == self.compression.store_only().codec.to_string())
+            }
+            storage => codec == storage.to_string(),
+        }
+    }
+
+    // A tar stream is compressed as a whole, so a path that is mostly already
+    // compressed content (screenshots, replays...) is stored in a plain tar instead.
+    // Zip archives decide per file, see `fs_utils::archive`.
+    fn choose_compression(
+        &self,
+        file_index: &FileIndex,
+    ) -> Result<CompressionSettings, KaguyaError> {
+        let compression = self.compression;
+        if !compression.adaptive
+            || !compression.is_compressed()
+            || compression.codec == ArchiveCodec::Zip
+        {
+            return Ok(compression);
+        }
+
+        if incompressible_share(&self.path, file_index)? >= STORE_ONLY_THRESHOLD {
+            Ok(compression.store_only())
+        } else {
+            Ok(compression)
+        }
+    }
+}
+
+// Total size of the files of a path
+fn original_size(file_index: &FileIndex) -> i64 {
+    file_index.values().map(|entry| entry.size_bytes).sum()
+}
+
+// Backup single path to target directory, get file name for targer archive file.
+// Return archive file path with its size and checksum.
+//
+// e.g., '~/Games/game-a/saves/' -> '~/.local/bin/kaguya/vault/<ID>/<VERSION>/saves.tar.gz'
+fn backup_single_path(
+    src: &impl AsRef<Path>,
+    dst: &impl AsRef<Path>,
+    compression: &CompressionSettings,
+    key: Option<&DataKey>,
+) -> Result<(PathBuf, ArchiveInfo), KaguyaError> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+
+    let file_name = get_file_name(src).unwrap_or_default();
+    let backup_file = dst.join(format!("{}.{}", file_name, compression.codec.extension()));
+
+    let info = compress_archive(&src, &backup_file, compression, key)?;
+
+    Ok((backup_file, info))
+}
+
+// Store single path in the object store, with its manifest in the target directory.
+// Return manifest path with the size of the manifest and of the new objects, and the manifest checksum.
+//
+// e.g., '~/Games/game-a/saves/' -> '~/.local/bin/kaguya/vault/<ID>/<VERSION>/saves.manifest.toml'
+fn store_single_path(
+    src: &impl AsRef<Path>,
+    dst: &impl AsRef<Path>,
+    file_index: &FileIndex,
+    objects: &ObjectStore,
+    chunked: bool,
+) -> Result<(PathBuf, ArchiveInfo), KaguyaError> {
+    let src = src.as_ref();
+
+    let file_name = get_file_name(src).unwrap_or_default();
+    let manifest_path = dst.as_ref().join(format!("{}.manifest.toml", file_name));
+
+    let stored = store_tree(&src, file_index, objects, &manifest_path, chunked)?;
+
+    Ok((
+        manifest_path,
+        ArchiveInfo {
+            size_bytes: stored.manifest.size_bytes + stored.new_bytes as i64,
+            checksum: stored.manifest.checksum,
+        },
+    ))
+}
+
+// Mirror single path into target directory, hard-linking files unchanged since `previous`.
+// Return mirror path with the size of the copied files, and the content checksum of the path.
+//
+// e.g., '~/Games/game-a/saves/' -> '~/.local/bin/kaguya/vault/<ID>/<VERSION>/saves/'
+fn mirror_single_path(
+    src: &impl AsRef<Path>,
+    dst: &impl AsRef<Path>,
+    file_index: &FileIndex,
+    previous: Option<&PreviousMirror>,
+    source_checksum: &str,
+) -> Result<(PathBuf, ArchiveInfo), KaguyaError> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+
+    let file_name = get_file_name(src).unwrap_or_default();
+    let info = mirror_tree(&src, &dst, file_index, previous)?;
+
+    Ok((
+        dst.join(file_name),
+        ArchiveInfo {
+            size_bytes: info.copied_bytes as i64,
+            checksum: source_checksum.to_string(),
+        },
+    ))
+}
+
+// Store single path as a delta against the `previous` one, or as a keyframe when there is
+// none, its chain is `keyframe_interval` versions long already, or the two would not fit
+// the zstd window together.
+// Return delta file path with its size and checksum, and its link in the chain.
+//
+// e.g., '~/Games/game-a/saves/' -> '~/.local/bin/kaguya/vault/<ID>/<VERSION>/saves.delta.zst'
+fn delta_single_path(
+    src: &impl AsRef<Path>,
+    dst: &impl AsRef<Path>,
+    previous: Option<&PreviousDelta>,
+    keyframe_interval: u32,
+) -> Result<(PathBuf, ArchiveInfo, DeltaLink), KaguyaError> {
+    let src = src.as_ref();
+
+    let file_name = get_file_name(src).unwrap_or_default();
+    let delta_path = dst.as_ref().join(format!("{}.delta.zst", file_name));
+
+    // A previous version that no longer reconstructs starts a new chain,
+    // 'vault check' reports the broken one
+    let base = match previous {
+        Some(previous) if previous.link.depth + 1 < keyframe_interval => {
+            reconstruct(&previous.chain)
+                .ok()
+                .flatten()
+                .map(|data| (previous, data))
+        }
+        _ => None,
+    };
+
+    let data = tar_bytes(&src)?;
+    let base = base.filter(|(_, base)| base.len() + data.len() <= MAX_DELTA_SPAN);
+    let info = write_delta(
+        &data,
+        base.as_ref().map(|(_, base)| base.as_slice()),
+        &delta_path,
+    )?;
+
+    let link = match base {
+        Some((previous, _)) => DeltaLink {
+            base_file_id: Some(previous.file_id),
+            depth: previous.link.depth + 1,
+            keyframe_interval,
+        },
+        None => DeltaLink {
+            base_file_id: None,
+            depth: 0,
+            keyframe_interval,
+        },
+    };
+    Ok((delta_path, info, link))
+}
@@ -1,46 +1,404 @@
 use crate::cli::AppContext;
+use crate::core::VaultService;
 use crate::db_manager::DbManager;
 use crate::db_manager::sqlite::DbManagerSyncExt;
 use crate::db_manager::toml::{
-    add_or_update_game_to_file, read_toml_file, rm_game_in_vault_config,
+    EditableConfig, add_or_update_game_to_file, check_edited_config, duplicate_game_ids,
+    editable_config, read_readable_config_files, read_vault_config, rm_game_in_vault_config,
+    save_edited_config,
 };
-use crate::models::{AddGameRequest, GameConfig, KaguyaError, RmGameRequest, VaultConfig};
+use crate::fs_utils::lock::{LockMode, VaultLock};
+use crate::fs_utils::restore::generate_unique_temp_name;
+use crate::models::{
+    AddGameRequest, ConfigCheckReport, ConfigIssue, EditConfigRequest, GameConfig, IssueLevel,
+    KaguyaError, RmGameRequest, requests::PruneRequest,
+};
+use crate::utils::editor::{add_edit_notes, open_in_editor, strip_edit_notes};
+use crate::utils::path::{expand_path, find_game_ref, is_valid_game_id, to_absolute_path};
+use crate::utils::prompt::confirm;
+use std::env::temp_dir;
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_file, write};
+use std::path::{Path, PathBuf};
+
+/// A path holding more bytes than this is reported by `config check`
+const LARGE_PATH_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+/// A path holding more files than this is reported by `config check`
+const LARGE_PATH_FILES: usize = 100_000;
 
 /// Managing actions for 'kaguya config' command
 pub struct ConfigService {
     config: AppContext,
-    db: DbManager,
+    /// Opened once the vault config is changed, so a broken config can still be checked
+    db: Option<DbManager>,
 }
 
 impl ConfigService {
-    pub fn new(config: AppContext, db: DbManager) -> Self {
-        Self { config, db }
+    pub fn new(config: AppContext) -> Self {
+        Self { config, db: None }
     }
 
     /// Receive a [`AddGameRequest`] and add a new game to the vault config
     pub fn add_or_update_game(&mut self, request: AddGameRequest) -> Result<(), KaguyaError> {
-        std::fs::create_dir_all(&self.config.vault_dir)?;
+        let _lock = self.lock(LockMode::Exclusive)?;
 
         add_or_update_game_to_file(&self.config.vault_config_path, request)?;
-        self.db.sync(&self.config.vault_config_path, true)
+        self.sync()
     }
 
-    /// Read original game list from vault config
+    /// Read game list from every file of the vault config
     pub fn get_game_list(&self) -> Result<Vec<GameConfig>, KaguyaError> {
-        Ok(read_toml_file::<VaultConfig>(&self.config.vault_config_path)?.games)
+        let _lock = self.lock(LockMode::Shared)?;
+        Ok(read_vault_config(&self.config.vault_config_path)?.games)
     }
 
-    /// Remove a game config by ID in the vault config
-    /// If 'purge' flag is true, backups of the game will NOT retain!
+    /// Remove a game config by ID in the vault config.
+    /// Its backups are kept, unless 'purge' flag is true: then they are deleted,
+    /// along with the records of the game in the database.
     pub fn rm_game(&mut self, request: &RmGameRequest) -> Result<(), KaguyaError> {
-        if request.purge {
+        {
+            let _lock = self.lock(LockMode::Exclusive)?;
             rm_game_in_vault_config(&self.config.vault_config_path, &request.id)?;
+            self.sync()?;
+        }
 
-            todo!("Todo: Remove game backups action")
-        } else {
-            rm_game_in_vault_config(&self.config.vault_config_path, &request.id)?;
+        // The game is marked removed by now, pruning it takes the lock again
+        if request.purge
+            && let Some(db) = self.db.take()
+        {
+            VaultService::new(self.config.clone(), db).prune(&PruneRequest {
+                id: Some(request.id.clone()),
+                version: None,
+                purge: true,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Check every game of the vault config for mistakes the TOML syntax doesn't catch:
+    /// duplicate or unusable IDs, paths that are missing, overlap each other or the vault,
+    /// have no file name, or are unreasonably large. Files of the vault config that can't be
+    /// read are reported too, the games of the others are still checked.
+    pub fn check(&self) -> Result<ConfigCheckReport, KaguyaError> {
+        let _lock = self.lock(LockMode::Shared)?;
+        let (files, failures) = read_readable_config_files(&self.config.vault_config_path);
+        let mut report = ConfigCheckReport::default();
+
+        // The games of the other files are still checked
+        for (path, e) in failures {
+            report.issues.push(ConfigIssue {
+                level: IssueLevel::Error,
+                game_id: None,
+                message: format!("Can't read '{}': {}", path.display(), e),
+            });
+        }
+
+        for (id, first, other) in duplicate_game_ids(&files) {
+            report.issues.push(ConfigIssue {
+                level: IssueLevel::Error,
+                game_id: Some(id),
+                message: if first == other {
+                    format!(
+                        "Defined twice in '{}', remove or rename one of them",
+                        first.display()
+                    )
+                } else {
+                    format!(
+                        "Defined in both '{}' and '{}', remove or rename one of them",
+                        first.display(),
+                        other.display()
+                    )
+                },
+            });
+        }
+
+        let vault_dir = to_absolute_path(&self.config.vault_dir)?;
+        let mut checked_paths: Vec<(&str, PathBuf)> = Vec::new();
+        for game in files.iter().flat_map(|(_, games)| games) {
+            report.games += 1;
+            let mut issue = |level, message| {
+                report.issues.push(ConfigIssue {
+                    level,
+                    game_id: Some(game.id.clone()),
+                    message,
+                })
+            };
+
+            if !is_valid_game_id(&game.id) {
+                issue(
+                    IssueLevel::Error,
+                    "ID is used as a directory name under 'backups/', use only letters, digits, '-', '_' and '.', not starting with '.'".to_string(),
+                );
+            }
+            if game.paths.is_empty() {
+                issue(
+                    IssueLevel::Warning,
+                    "No paths configured, add some with 'kaguya config add'".to_string(),
+                );
+            }
+
+            for path in &game.paths {
+                let path = to_absolute_path(&expand_path(path)?)?;
+                for problem in path_problems(&path, &vault_dir) {
+                    issue(problem.0, problem.1);
+                }
+
+                for (other_id, other) in &checked_paths {
+                    if let Some(problem) = overlap(&game.id, &path, other_id, other) {
+                        issue(problem.0, problem.1);
+                    }
+                }
+                checked_paths.push((&game.id, path));
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Edit the vault config, or only the game `id`, in the user's editor.
+    ///
+    /// The edit is checked like the vault config is read by every command. An invalid edit is
+    /// opened again with the error noted on top, until it is fixed or saved unchanged.
+    /// An edit removing games is only saved once the user confirms it.
+    /// The real file is only replaced, and the database synced, once the edit is valid.
+    pub fn edit(&mut self, request: &EditConfigRequest) -> Result<(), KaguyaError> {
+        let (editable, game_ids) = {
+            let _lock = self.lock(LockMode::Shared)?;
+            // A broken vault config has no games an edit could remove
+            let game_ids: Vec<String> = read_vault_config(&self.config.vault_config_path)
+                .map(|vault_config| vault_config.games.into_iter().map(|game| game.id).collect())
+                .unwrap_or_default();
+            (
+                editable_config(&self.config.vault_config_path, request.id.as_deref())?,
+                game_ids,
+            )
+        };
+        // The lock isn't held while the editor is open, changes made meanwhile are detected on save
+        let temp_path = temp_dir().join(format!(
+            "{}.toml",
+            generate_unique_temp_name("kaguya-config", 8)
+        ));
+        write(&temp_path, &editable.content)?;
+
+        let mut rejected: Option<String> = None;
+        loop {
+            if let Err(e) = open_in_editor(&temp_path) {
+                println!("The edit is kept in '{}'.", temp_path.display());
+                return Err(e);
+            }
+            let edited = strip_edit_notes(&read_to_string(&temp_path)?);
+
+            if edited.trim().is_empty() {
+                remove_file(&temp_path).ok();
+                println!("Edit is empty, the vault config is left unchanged.");
+                return Ok(());
+            }
+            if edited == editable.content {
+                remove_file(&temp_path).ok();
+                println!("No changes made to the vault config.");
+                return Ok(());
+            }
+            if rejected.as_ref() == Some(&edited) {
+                return Err(KaguyaError::InvalidInput(format!(
+                    "The edit is still invalid, the vault config is left unchanged, the edit is kept in '{}'",
+                    temp_path.display()
+                )));
+            }
+
+            match check_edited_config(&self.config.vault_config_path, &editable, &edited) {
+                Ok((content, vault_config)) => {
+                    let removed: Vec<&str> = game_ids
+                        .iter()
+                        .filter(|id| find_game_ref(&vault_config.games, id).is_none())
+                        .map(String::as_str)
+                        .collect();
+                    if !removed.is_empty() {
+                        println!(
+                            "The edit removes game(s) '{}', their backups are kept but they won't be backed up anymore.",
+                            removed.join("', '")
+                        );
+                        if !confirm("Save the edit?")? {
+                            println!(
+                                "The vault config is left unchanged, the edit is kept in '{}'.",
+                                temp_path.display()
+                            );
+                            return Ok(());
+                        }
+                    }
+                    if let Err(e) = self.save_edit(&editable, &content) {
+                        println!("The edit is kept in '{}'.", temp_path.display());
+                        return Err(e);
+                    }
+                    break;
+                }
+                Err(e) => {
+                    println!("Invalid vault config: {}", e);
+                    let notes = [
+                        "The vault config is invalid, fix it and save, or save it unchanged to give up:",
+                        &e.to_string(),
+                    ];
+                    write(&temp_path, add_edit_notes(&edited, &notes))?;
+                    rejected = Some(edited);
+                }
+            }
+        }
+
+        remove_file(&temp_path).ok();
+        println!("Saved '{}'.", editable.path.display());
+        Ok(())
+    }
+
+    // Replace the edited file with its checked new content and sync the database
+    fn save_edit(&mut self, editable: &EditableConfig, content: &str) -> Result<(), KaguyaError> {
+        let _lock = self.lock(LockMode::Exclusive)?;
+        save_edited_config(editable, content)?;
+        self.sync()
+    }
+
+    // Sync the database with the changed vault config, opening it first if needed.
+    // Called with the exclusive lock held.
+    fn sync(&mut self) -> Result<(), KaguyaError> {
+        match &mut self.db {
+            Some(db) => db.sync(&self.config.vault_config_path, true),
+            // Opening the database syncs it
+            None => {
+                self.db = Some(DbManager::new_locked(
+                    &self.config.db_path,
+                    &self.config.vault_config_path,
+                )?);
+                Ok(())
+            }
         }
+    }
 
-        self.db.sync(&self.config.vault_config_path, true)
+    // Lock the vault for an operation, until the returned lock is dropped
+    fn lock(&self, mode: LockMode) -> Result<VaultLock, KaguyaError> {
+        create_dir_all(&self.config.vault_dir)?;
+        VaultLock::acquire(&self.config.vault_dir, mode)
+    }
+}
+
+// Problems of a single path of a game
+fn path_problems(path: &Path, vault_dir: &Path) -> Vec<(IssueLevel, String)> {
+    let mut problems = Vec::new();
+
+    if path.file_name().is_none() {
+        problems.push((
+            IssueLevel::Error,
+            format!(
+                "Path '{}' has no file name to name its archive after, add the directories inside it instead",
+                path.display()
+            ),
+        ));
+    }
+    if path.starts_with(vault_dir) {
+        problems.push((
+            IssueLevel::Error,
+            format!(
+                "Path '{}' is inside the vault, remove it from the game",
+                path.display()
+            ),
+        ));
+    } else if vault_dir.starts_with(path) {
+        problems.push((
+            IssueLevel::Error,
+            format!(
+                "Path '{}' contains the vault, backups would back up the vault itself, add the directories next to it instead",
+                path.display()
+            ),
+        ));
+    }
+
+    if !path.exists() {
+        problems.push((
+            IssueLevel::Warning,
+            format!(
+                "Path '{}' doesn't exist, fix it or remove it from the game",
+                path.display()
+            ),
+        ));
+    } else if path.is_dir() && is_large_dir(path) {
+        problems.push((
+            IssueLevel::Warning,
+            format!(
+                "Path '{}' holds more than {} GiB or {} files, make sure it only holds saves and configuration",
+                path.display(),
+                LARGE_PATH_BYTES / 1024 / 1024 / 1024,
+                LARGE_PATH_FILES
+            ),
+        ));
+    }
+
+    problems
+}
+
+// How `path` of game `id` overlaps `other` of game `other_id`, `None` if they don't
+fn overlap(id: &str, path: &Path, other_id: &str, other: &Path) -> Option<(IssueLevel, String)> {
+    let owner = if id == other_id {
+        "another path of this game".to_string()
+    } else {
+        format!("a path of game '{}'", other_id)
+    };
+
+    if path == other {
+        Some((
+            IssueLevel::Error,
+            format!(
+                "Path '{}' is also {}, remove one of them",
+                path.display(),
+                owner
+            ),
+        ))
+    } else if path.starts_with(other) || other.starts_with(path) {
+        Some((
+            IssueLevel::Warning,
+            format!(
+                "Path '{}' overlaps '{}', {}, its files are backed up twice",
+                path.display(),
+                other.display(),
+                owner
+            ),
+        ))
+    } else if id == other_id && path.file_name().is_some() && path.file_name() == other.file_name()
+    {
+        // Archives of a version are named after the file names of their paths
+        Some((
+            IssueLevel::Error,
+            format!(
+                "Path '{}' has the same file name as '{}', their archives would overwrite each other",
+                path.display(),
+                other.display()
+            ),
+        ))
+    } else {
+        None
+    }
+}
+
+// Whether a directory holds more than `LARGE_PATH_BYTES` or `LARGE_PATH_FILES`,
+// stopping as soon as it does
+fn is_large_dir(dir: &Path) -> bool {
+    let mut dirs = vec![dir.to_path_buf()];
+    let (mut bytes, mut files) = (0, 0);
+
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            // Symbolic links are not followed
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                bytes += metadata.len();
+                files += 1;
+            }
+            if bytes > LARGE_PATH_BYTES || files > LARGE_PATH_FILES {
+                return true;
+            }
+        }
     }
+    false
 }
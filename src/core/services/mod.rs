@@ -1,2 +1,4 @@
+pub mod backup_job;
 pub mod config;
+pub mod reindex;
 pub mod vault;
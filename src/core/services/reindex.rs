@@ -0,0 +1,433 @@
+//! Scanning the vault directory for 'vault reindex'.
+//!
+//! Like a [`PathJob`](super::backup_job::PathJob), scanning never touches the database:
+//! [`VaultService`](crate::core::VaultService) replaces the records of a game with the
+//! scanned versions afterwards.
+//!
+//! Versions with a [manifest](crate::fs_utils::version_manifest) are read from it. Older
+//! versions are inferred from the layout of the vault: the name of the version directory
+//! gives the time of the backup, and the name of every backup file its storage mode and the
+//! configured path it was made of, e.g. '<ID>/<VERSION>/saves.tar.zst' is an archive of the
+//! path named 'saves'.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, read_dir},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    fs_utils::{
+        codec::ArchiveCodec,
+        delta::{DeltaFile, reconstruct},
+        hash::{FileIndex, calculate_entry_checksum, calculate_entry_checksum_cached},
+        objects::ObjectManifest,
+        parity::parity_path,
+        signature::SIGNATURE_FILE,
+        storage::{CHUNKS_CODEC, DELTA_CODEC, MIRROR_CODEC, OBJECTS_CODEC},
+        version_manifest::{
+            ManifestDelta, ManifestEntry, VERSION_MANIFEST_FILE, VersionManifest,
+            read_version_manifest,
+        },
+    },
+    models::{GameConfig, KaguyaError, ReindexReport, db::BackupFile},
+    utils::{path::get_file_name, time::version_timestamp},
+};
+
+/// A version found in the directory of a game
+#[derive(Debug)]
+pub struct ScannedVersion {
+    pub version: String,
+    pub timestamp: String,
+    /// Whether the version was read from its manifest rather than inferred
+    pub from_manifest: bool,
+    pub files: Vec<ScannedFile>,
+}
+
+/// A backup file of a [`ScannedVersion`]; `id` and `backup_id` of the record are not assigned.
+#[derive(Debug)]
+pub struct ScannedFile {
+    pub file: BackupFile,
+    /// Chain position of a delta file, its base named as '<version>/<file name>'
+    pub delta: Option<ManifestDelta>,
+}
+
+// Delta files of a path stored so far in the scanned versions, from the latest keyframe on,
+// each with its '<version>/<file name>'
+type DeltaChains = HashMap<String, Vec<(String, DeltaFile)>>;
+
+const UNDECODABLE_DELTA: &str =
+    "delta file doesn't decode on its own, nor on top of the previous version";
+
+/// Report directories of `backup_dir` that belong to none of the configured `games`,
+/// and stray files next to them.
+pub fn scan_unknown_games(
+    backup_dir: &Path,
+    games: &[GameConfig],
+    report: &mut ReindexReport,
+) -> Result<(), KaguyaError> {
+    if !backup_dir.is_dir() {
+        return Ok(());
+    }
+
+    for path in sorted_entries(backup_dir)? {
+        if !path.is_dir() {
+            report.unattributed(path, "not a game directory");
+        } else if !games
+            .iter()
+            .any(|game| Some(&game.id) == get_file_name(&path).as_ref())
+        {
+            report.unattributed(path, "no game with this ID in the vault config");
+        }
+    }
+    Ok(())
+}
+
+/// Scan every version in `game_dir`, oldest first.
+/// Anything that can't be attributed to the game is left out and reported.
+pub fn scan_game_dir(
+    game: &GameConfig,
+    game_dir: &Path,
+    keyframe_interval: u32,
+    report: &mut ReindexReport,
+) -> Result<Vec<ScannedVersion>, KaguyaError> {
+    if !game_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut versions = Vec::new();
+    let mut chains = DeltaChains::new();
+    for path in sorted_entries(game_dir)? {
+        let version = get_file_name(&path).unwrap_or_default();
+        if !path.is_dir() {
+            report.unattributed(path, "not a version directory");
+            continue;
+        }
+
+        let manifest = match read_version_manifest(&path) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                report.unattributed(
+                    path.join(VERSION_MANIFEST_FILE),
+                    format!("unreadable manifest, version inferred instead: {}", e),
+                );
+                None
+            }
+        };
+        let scanned = match manifest {
+            Some(manifest) => scan_manifest_version(
+                game,
+                &path,
+                manifest,
+                keyframe_interval,
+                &mut chains,
+                report,
+            )?,
+            None => infer_version(
+                game,
+                &path,
+                &version,
+                keyframe_interval,
+                &mut chains,
+                report,
+            )?,
+        };
+        versions.extend(scanned);
+    }
+
+    Ok(versions)
+}
+
+// Read a version from its manifest, verifying the listed files against it
+fn scan_manifest_version(
+    game: &GameConfig,
+    version_dir: &Path,
+    manifest: VersionManifest,
+    keyframe_interval: u32,
+    chains: &mut DeltaChains,
+    report: &mut ReindexReport,
+) -> Result<Option<ScannedVersion>, KaguyaError> {
+    let version = get_file_name(version_dir).unwrap_or_default();
+    if manifest.game_id != game.id {
+        report.unattributed(
+            version_dir,
+            format!("the manifest belongs to game '{}'", manifest.game_id),
+        );
+        return Ok(None);
+    }
+    if manifest.version != version {
+        report.unattributed(
+            version_dir,
+            format!("the manifest names version '{}'", manifest.version),
+        );
+        return Ok(None);
+    }
+
+    let mut listed = HashSet::new();
+    let mut files = Vec::new();
+    for entry in manifest.files {
+        let path = version_dir.join(&entry.archive);
+        listed.insert(path.clone());
+        if !path.exists() {
+            report.mismatched(&path, "listed in the manifest, but missing");
+            continue;
+        }
+        if calculate_entry_checksum(&path)? != entry.checksum {
+            report.mismatched(&path, "checksum doesn't match the manifest");
+        }
+
+        let mut file = scanned_file(&path, entry);
+        if file.file.codec.as_deref() == Some(DELTA_CODEC) {
+            let name = get_file_name(&path).unwrap_or_default();
+            match &file.delta {
+                // Manifests written before chains were recorded in them
+                None => {
+                    match infer_delta_link(&file.file, &version, &name, keyframe_interval, chains) {
+                        Some(link) => file.delta = Some(link),
+                        None => {
+                            report.mismatched(&path, UNDECODABLE_DELTA);
+                            continue;
+                        }
+                    }
+                }
+                Some(delta) => {
+                    let chain = chains.entry(file.file.original_path.clone()).or_default();
+                    if delta.base.is_none() {
+                        chain.clear();
+                    }
+                    chain.push((
+                        format!("{}/{}", version, name),
+                        DeltaFile {
+                            path: path.clone(),
+                            checksum: file.file.checksum.clone(),
+                        },
+                    ));
+                }
+            }
+        }
+        files.push(file);
+    }
+
+    for path in sorted_entries(version_dir)? {
+        let name = get_file_name(&path).unwrap_or_default();
+        let is_parity = listed.iter().any(|file| parity_path(file) == path);
+        if !listed.contains(&path)
+            && !is_parity
+            && name != VERSION_MANIFEST_FILE
+            && name != SIGNATURE_FILE
+        {
+            report.unattributed(path, "not listed in the version manifest");
+        }
+    }
+
+    Ok(Some(ScannedVersion {
+        version,
+        timestamp: manifest.timestamp,
+        from_manifest: true,
+        files,
+    }))
+}
+
+// Backup file record of a manifest entry
+fn scanned_file(path: &Path, entry: ManifestEntry) -> ScannedFile {
+    ScannedFile {
+        file: BackupFile {
+            id: 0,
+            backup_id: 0,
+            original_path: entry.original_path,
+            archive_path: path.to_string_lossy().to_string(),
+            size_bytes: entry.size_bytes,
+            checksum: entry.checksum,
+            source_checksum: entry.source_checksum,
+            codec: entry.codec,
+            original_size_bytes: entry.original_size_bytes,
+        },
+        delta: entry.delta,
+    }
+}
+
+// Infer a version without a manifest from its directory name and the names of its files
+fn infer_version(
+    game: &GameConfig,
+    version_dir: &Path,
+    version: &str,
+    keyframe_interval: u32,
+    chains: &mut DeltaChains,
+    report: &mut ReindexReport,
+) -> Result<Option<ScannedVersion>, KaguyaError> {
+    let Some(timestamp) = version_timestamp(version) else {
+        report.unattributed(version_dir, "not a version directory");
+        return Ok(None);
+    };
+
+    let mut files: Vec<ScannedFile> = Vec::new();
+    for path in sorted_entries(version_dir)? {
+        let name = get_file_name(&path).unwrap_or_default();
+        if name.ends_with(".par") && path.with_extension("").exists() {
+            continue;
+        }
+        let (stem, codec) = match infer_storage(&path, &name) {
+            Ok(storage) => storage,
+            Err(reason) => {
+                report.unattributed(path, reason);
+                continue;
+            }
+        };
+
+        let candidates: Vec<&PathBuf> = game
+            .paths
+            .iter()
+            .filter(|p| get_file_name(p).as_deref() == Some(stem))
+            .collect();
+        let original_path = match candidates.as_slice() {
+            [original_path] => original_path.to_string_lossy().to_string(),
+            [] => {
+                let reason = format!("no configured path of the game is named '{}'", stem);
+                report.unattributed(path, reason);
+                continue;
+            }
+            _ => {
+                let reason = format!("several configured paths are named '{}'", stem);
+                report.unattributed(path, reason);
+                continue;
+            }
+        };
+        if files.iter().any(|f| f.file.original_path == original_path) {
+            let reason = format!(
+                "another backup file of '{}' is in the version",
+                original_path
+            );
+            report.unattributed(path, reason);
+            continue;
+        }
+
+        let mut file = BackupFile {
+            id: 0,
+            backup_id: 0,
+            original_path,
+            archive_path: path.to_string_lossy().to_string(),
+            size_bytes: 0,
+            checksum: String::new(),
+            source_checksum: None,
+            codec: Some(codec.clone()),
+            original_size_bytes: None,
+        };
+        let mut delta = None;
+        match codec.as_str() {
+            MIRROR_CODEC => {
+                // A mirror is a copy of the path, its checksum is the one of the path itself
+                let (checksum, index) =
+                    calculate_entry_checksum_cached(&path, &FileIndex::new(), true)?;
+                let size = index.values().map(|entry| entry.size_bytes).sum();
+                file.size_bytes = size;
+                file.original_size_bytes = Some(size);
+                file.source_checksum = Some(checksum.clone());
+                file.checksum = checksum;
+            }
+            OBJECTS_CODEC | CHUNKS_CODEC => {
+                let manifest = ObjectManifest::read(&path, None)?;
+                file.size_bytes = fs::metadata(&path)?.len() as i64;
+                file.original_size_bytes = Some(manifest.files.iter().map(|f| f.size as i64).sum());
+                file.checksum = calculate_entry_checksum(&path)?;
+            }
+            _ => {
+                file.size_bytes = fs::metadata(&path)?.len() as i64;
+                file.checksum = calculate_entry_checksum(&path)?;
+            }
+        }
+
+        if codec == DELTA_CODEC {
+            let Some(link) = infer_delta_link(&file, version, &name, keyframe_interval, chains)
+            else {
+                report.unattributed(path, UNDECODABLE_DELTA);
+                continue;
+            };
+            delta = Some(link);
+        }
+        files.push(ScannedFile { file, delta });
+    }
+
+    if files.is_empty() {
+        report.unattributed(version_dir, "no backup file of the game found in it");
+        return Ok(None);
+    }
+    Ok(Some(ScannedVersion {
+        version: version.to_string(),
+        timestamp,
+        from_manifest: false,
+        files,
+    }))
+}
+
+// Name of the path a backup file was made of, and its codec, from the name of the file
+fn infer_storage<'a>(path: &Path, name: &'a str) -> Result<(&'a str, String), String> {
+    if path.is_dir() {
+        return Ok((name, MIRROR_CODEC.to_string()));
+    }
+    if let Some(stem) = name.strip_suffix(".manifest.toml") {
+        let manifest = ObjectManifest::read(&path, None)
+            .map_err(|e| format!("unreadable object manifest: {}", e))?;
+        let chunked = manifest.files.iter().any(|f| !f.chunks.is_empty());
+        let codec = if chunked { CHUNKS_CODEC } else { OBJECTS_CODEC };
+        return Ok((stem, codec.to_string()));
+    }
+    if let Some(stem) = name.strip_suffix(".delta.zst") {
+        return Ok((stem, DELTA_CODEC.to_string()));
+    }
+
+    ArchiveCodec::from_archive_path(&path)
+        .and_then(|codec| {
+            let stem = name.strip_suffix(&format!(".{}", codec.extension()))?;
+            Some((stem, codec.to_string()))
+        })
+        .ok_or_else(|| "unknown backup file format".to_string())
+}
+
+// Chain position of a delta file: a keyframe if it decodes on its own,
+// otherwise a delta against the previous delta file of its path.
+// The recorded interval is the configured one, or longer if the chain is.
+fn infer_delta_link(
+    file: &BackupFile,
+    version: &str,
+    name: &str,
+    keyframe_interval: u32,
+    chains: &mut DeltaChains,
+) -> Option<ManifestDelta> {
+    let delta_file = DeltaFile {
+        path: PathBuf::from(&file.archive_path),
+        checksum: file.checksum.clone(),
+    };
+    let key = format!("{}/{}", version, name);
+    let chain = chains.entry(file.original_path.clone()).or_default();
+
+    if reconstruct(std::slice::from_ref(&delta_file)).is_ok() {
+        *chain = vec![(key, delta_file)];
+        return Some(ManifestDelta {
+            base: None,
+            depth: 0,
+            keyframe_interval,
+        });
+    }
+
+    let base = chain.last()?.0.clone();
+    let mut files: Vec<DeltaFile> = chain.iter().map(|(_, f)| f.clone()).collect();
+    files.push(delta_file.clone());
+    reconstruct(&files).ok()?;
+
+    chain.push((key, delta_file));
+    let depth = chain.len() as u32 - 1;
+    Some(ManifestDelta {
+        base: Some(base),
+        depth,
+        keyframe_interval: keyframe_interval.max(depth + 1),
+    })
+}
+
+// Entries of a directory sorted by name, versions come out oldest first
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>, KaguyaError> {
+    let mut entries = read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    Ok(entries)
+}
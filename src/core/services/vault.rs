@@ -1,39 +1,89 @@
 use crate::{
     cli::AppContext,
+    core::services::{
+        backup_job::{
+            BackupFileRecord, PathJob, PathJobResult, PathOutcome, PreviousBackup, PreviousDelta,
+        },
+        reindex::{ScannedFile, ScannedVersion, scan_game_dir, scan_unknown_games},
+    },
     db_manager::{
         DbManager,
-        sqlite::{DbManagerBackupExt, DbManagerGameExt},
+        sqlite::{DbManagerBackupExt, DbManagerFileIndexExt, DbManagerGameExt, DbManagerMetaExt},
         toml::read_vault_config,
     },
     fs_utils::{
-        archive::{calculate_file_bytes, compress_to_tar_gz},
-        hash::calculate_entry_checksum,
-        restore::restore_archive,
+        archive::compress_archive,
+        codec::CompressionSettings,
+        crypto::{DataKey, KeyEnvelope, is_encrypted},
+        delta::{DeltaFile, MAX_DELTA_SPAN, rebase_delta},
+        hash::calculate_entry_checksum_cached,
+        lock::{LockMode, VaultLock},
+        mirror::PreviousMirror,
+        objects::{GarbageReport, ObjectManifest, ObjectStore},
+        parity::{parity_path, refresh_parity, repair_with_parity, scan_parity, write_parity},
+        restore::{generate_unique_temp_name, restore_archive},
+        signature::{
+            SigningRecord, VaultSigning, create_signing_key, is_signed, load_signing_key,
+            public_key_hex, read_signed_manifest,
+        },
+        storage::{
+            BackupFormat, CHUNKS_CODEC, DELTA_CODEC, MIRROR_CODEC, OBJECTS_CODEC, StorageMode,
+            unpack_backup, verify_backup,
+        },
+        version_manifest::{
+            ManifestDelta, ManifestEntry, VERSION_MANIFEST_FILE, VersionManifest, manifest_hash,
+            read_version_manifest, write_version_manifest,
+        },
     },
     models::{
-        BackupRequest, GameConfig, KaguyaError,
-        db::{Backup, BackupFile},
-        requests::RestoreRequest,
+        BackupCheck, BackupRepair, BackupRequest, BackupSettings, GameConfig, GameReindex,
+        GameStats, GameStatus, KEY_ENCRYPTION, KEY_ENVELOPE_FILE, KaguyaError, PathStatus,
+        PathStatusKind, ReindexReport, RepairOutcome, SIGNING_RECORD_FILE, StatusRequest,
+        VaultStats,
+        db::{Backup, BackupFile, DeltaLink},
+        events::BackupEvent,
+        requests::{
+            CheckRequest, ExportRequest, PruneRequest, ReindexRequest, RekeyRequest, RepairRequest,
+            RestoreRequest,
+        },
     },
     utils::{
-        path::{find_game_ref, get_file_name},
+        passphrase::{read_new_passphrase, read_passphrase},
+        path::{expand_path, find_game_ref, to_absolute_path},
         time::{get_time_string, get_timestamp},
     },
 };
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use rayon::{ThreadPoolBuilder, prelude::*};
+use scopeguard::defer;
 use std::{
-    fs::create_dir_all,
+    cell::OnceCell,
+    collections::{HashMap, HashSet},
+    env::current_dir,
+    fs::{create_dir, create_dir_all, read_dir, remove_dir, remove_dir_all, remove_file, rename},
+    io,
     path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc,
+    thread,
+    time::Duration,
 };
 
 /// Managing actions for 'kaguya vault' command
 pub struct VaultService {
     config: AppContext,
     db: DbManager,
+    /// Vault encryption key, unlocked at most once per run
+    data_key: OnceCell<DataKey>,
 }
 
 impl VaultService {
     pub fn new(config: AppContext, db: DbManager) -> Self {
-        Self { config, db }
+        Self {
+            config,
+            db,
+            data_key: OnceCell::new(),
+        }
     }
 
     /// Backup game saves and configuration.
@@ -41,68 +91,397 @@ impl VaultService {
     /// If no arguments are given, backup all games.
     /// If '--id' is given, backup specific game.
     /// If '--id' and '--paths' are given, backup specific paths
+    ///
+    /// Paths are hashed and archived concurrently by a pool of '--jobs' workers,
+    /// while all database writes happen on the calling thread.
     pub fn backup(&mut self, request: BackupRequest) -> Result<(), KaguyaError> {
-        let games = read_vault_config(&self.config.vault_config_path)?.games;
-
-        match request.id {
+        let _lock = self.lock(LockMode::Exclusive)?;
+        let vault_config = read_vault_config(&self.config.vault_config_path)?;
+        let games: Vec<&GameConfig> = match &request.id {
             // '--id' is given.
-            Some(id) => match find_game_ref(&games, &id) {
-                // '--paths' is given or is None.
-                Some(game) => {
-                    self.backup_single_game(game, request.paths.as_ref())?;
-                    println!("Backup finished!");
-                    Ok(())
-                }
-                None => Err(KaguyaError::GameNotFound(id)),
-            },
+            Some(id) => vec![
+                find_game_ref(&vault_config.games, id)
+                    .ok_or_else(|| KaguyaError::GameNotFound(id.clone()))?,
+            ],
             // No arguments are given, Backup all games
-            None => {
-                for game in &games {
-                    self.backup_single_game(game, None)?;
+            None => vault_config.games.iter().collect(),
+        };
+        let jobs = request
+            .jobs
+            .or(vault_config.backup.jobs)
+            .unwrap_or(1)
+            .max(1);
+
+        // Load everything the workers need from the DB
+        let version = self.new_version(&games)?;
+        let encryption = self.backup_encryption_key(&vault_config.backup)?;
+        let signing_key = self.backup_signing_key(&vault_config.backup)?;
+        let mut path_jobs = Vec::new();
+        let mut pending = vec![0; games.len()];
+        for (game_index, game) in games.iter().enumerate() {
+            // '--paths' is given or is None.
+            let paths = self.plan_game_backup(
+                game_index,
+                game,
+                &version,
+                (&vault_config.backup, encryption.as_ref()),
+                &request,
+            )?;
+            pending[game_index] = paths.len();
+            path_jobs.extend(paths);
+        }
+        self.claim_version_dirs(&games, &pending, &version)?;
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .map_err(|e| KaguyaError::InvalidInput(format!("Could not start workers: {}", e)))?;
+
+        let mut records: Vec<Vec<BackupFileRecord>> = games.iter().map(|_| Vec::new()).collect();
+        let mut failed = vec![false; games.len()];
+        let mut first_error = None;
+
+        thread::scope(|scope| {
+            let (tx, rx) = mpsc::channel();
+            scope.spawn(move || {
+                pool.install(|| {
+                    path_jobs.into_par_iter().for_each_with(tx, |tx, job| {
+                        tx.send(job.run()).ok();
+                    })
+                })
+            });
+
+            // Results arrive as soon as each path is done
+            for result in rx {
+                let game_index = result.game_index;
+                let game = games[game_index];
+
+                if let Err(e) = self.handle_path_result(game, result, &mut records[game_index]) {
+                    failed[game_index] = true;
+                    first_error.get_or_insert(e);
                 }
-                println!("Backup finished!");
-                Ok(())
+
+                pending[game_index] -= 1;
+                if pending[game_index] == 0 {
+                    let files = std::mem::take(&mut records[game_index]);
+                    if let Err(e) = self.finish_game_backup(
+                        game,
+                        &version,
+                        files,
+                        (failed[game_index], signing_key.as_ref()),
+                    ) {
+                        first_error.get_or_insert(e);
+                    }
+                }
+            }
+        });
+
+        if let Some(e) = first_error {
+            return Err(e);
+        }
+        println!("Backup finished!");
+
+        if vault_config.backup.auto_prune {
+            let keep = vault_config.backup.keep_versions as i64;
+            for game in &games {
+                let game_id = self.db.get_game_id_with_external_id(&game.id)?;
+                let backups = self.db.get_backups(game_id)?;
+                let expired = retention_expired(&backups, game.keep_versions.unwrap_or(keep));
+                self.prune_backups(game, &backups, &expired)?;
             }
+            self.collect_garbage()?;
         }
+
+        Ok(())
     }
 
-    // Backup all saves and configuration of single game
-    fn backup_single_game(
-        &mut self,
-        game: &GameConfig,
-        paths: Option<&Vec<PathBuf>>,
+    // Name of a new version, not taken by any of `games` yet.
+    // Versions are named by the second, a run right after another one waits for the next.
+    fn new_version(&self, games: &[&GameConfig]) -> Result<String, KaguyaError> {
+        loop {
+            let version = get_time_string();
+            let mut taken = false;
+            for game in games {
+                let game_id = self.db.get_game_id_with_external_id(&game.id)?;
+                taken |= self
+                    .config
+                    .backup_dir
+                    .join(&game.id)
+                    .join(&version)
+                    .exists()
+                    || self
+                        .db
+                        .get_backups(game_id)?
+                        .iter()
+                        .any(|backup| backup.version == version);
+            }
+            if !taken {
+                return Ok(version);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    // Create the version directory of every game with paths to back up, before any job
+    // writes to it. A directory that exists already belongs to another version, nothing is
+    // written into it.
+    fn claim_version_dirs(
+        &self,
+        games: &[&GameConfig],
+        pending: &[usize],
+        version: &str,
     ) -> Result<(), KaguyaError> {
+        let mut claimed = Vec::new();
+        for (game, _) in games.iter().zip(pending).filter(|(_, paths)| **paths > 0) {
+            let game_dir = self.config.backup_dir.join(&game.id);
+            let version_dir = game_dir.join(version);
+            let result = create_dir_all(&game_dir).and_then(|_| create_dir(&version_dir));
+            if let Err(e) = result {
+                for dir in &claimed {
+                    remove_dir(dir).ok();
+                }
+                return Err(match e.kind() {
+                    io::ErrorKind::AlreadyExists => KaguyaError::InvalidInput(format!(
+                        "Version {} of '{}' already exists",
+                        version, game.id
+                    )),
+                    _ => e.into(),
+                });
+            }
+            claimed.push(version_dir);
+        }
+        Ok(())
+    }
+
+    // Resolves paths of a single game and prepares a job for each of them
+    fn plan_game_backup(
+        &self,
+        game_index: usize,
+        game: &GameConfig,
+        version: &str,
+        (backup_settings, encryption): (&BackupSettings, Option<&DataKey>),
+        request: &BackupRequest,
+    ) -> Result<Vec<PathJob>, KaguyaError> {
+        let compression = compression_settings(game, backup_settings)?;
+        let storage = storage_mode(game, backup_settings)?;
+        if encryption.is_some() && storage != StorageMode::Archive {
+            return Err(KaguyaError::InvalidInput(format!(
+                "Encryption is only supported with 'archive' storage, game '{}' uses '{}'",
+                game.id, storage
+            )));
+        }
+        let delta_max_size = delta_max_size(backup_settings)?;
+
         // Resolve and validate paths
-        let paths_to_backup = self.resolve_backup_paths(game, paths)?;
+        let paths_to_backup = self.resolve_backup_paths(game, request.paths.as_ref())?;
         if paths_to_backup.is_empty() {
             println!(
                 "No paths specified for game '{}' with ID '{}', skipping backup.",
                 game.name, game.id
             );
+            return Ok(Vec::new());
+        }
+
+        let game_id = self.db.get_game_id_with_external_id(&game.id)?;
+        let version_dir = self.config.backup_dir.join(&game.id).join(version);
+
+        paths_to_backup
+            .iter()
+            .map(|path| {
+                let previous = match self.db.get_latest_backup_file(game_id, path)? {
+                    Some((backup, file)) => Some(self.previous_backup(backup, file, storage)?),
+                    None => None,
+                };
+
+                Ok(PathJob {
+                    game_index,
+                    path: path.clone(),
+                    version_dir: version_dir.clone(),
+                    compression,
+                    encryption: encryption.cloned(),
+                    storage,
+                    objects: self.object_store(),
+                    keyframe_interval: backup_settings.delta_keyframe_interval,
+                    delta_max_size,
+                    parity: backup_settings.parity,
+                    file_index: self.db.get_file_index(path)?,
+                    previous,
+                    force: request.force,
+                    paranoid: request.paranoid,
+                })
+            })
+            .collect()
+    }
+
+    // Latest backup of a path for its job, with the file hashes of a mirror to link against,
+    // or the chain of a delta file to store the next delta against
+    fn previous_backup(
+        &self,
+        backup: Backup,
+        file: BackupFile,
+        storage: StorageMode,
+    ) -> Result<PreviousBackup, KaguyaError> {
+        let mirror =
+            if storage == StorageMode::Mirror && file.codec.as_deref() == Some(MIRROR_CODEC) {
+                let hashes = self
+                    .db
+                    .get_backup_file_entries(file.id)?
+                    .into_iter()
+                    .filter_map(|entry| Some((entry.rel_path, entry.hash?)))
+                    .collect();
+                Some(PreviousMirror {
+                    path: expand_path(&file.archive_path)?,
+                    hashes,
+                })
+            } else {
+                None
+            };
+
+        // A broken chain can't be extended, the job starts a new one instead
+        let delta = if storage == StorageMode::Delta && file.codec.as_deref() == Some(DELTA_CODEC) {
+            self.delta_chain(&file).ok().flatten()
+        } else {
+            None
+        };
+
+        Ok(PreviousBackup {
+            version: backup.version,
+            source_checksum: file.source_checksum,
+            encrypted: is_encrypted(&file.archive_path)?,
+            codec: file.codec,
+            mirror,
+            delta,
+        })
+    }
+
+    // Persists the refreshed file index of a finished path and reports it
+    fn handle_path_result(
+        &mut self,
+        game: &GameConfig,
+        result: PathJobResult,
+        records: &mut Vec<BackupFileRecord>,
+    ) -> Result<(), KaguyaError> {
+        if let Some(file_index) = &result.file_index {
+            self.db.replace_file_index(&result.path, file_index)?;
+        }
+
+        let event = match result.outcome {
+            Ok(PathOutcome::Skipped { version }) => BackupEvent::FileSkipped {
+                original_path: result.path,
+                reason: format!("unchanged since {}", version),
+            },
+            Ok(PathOutcome::BackedUp(record, entries, delta)) => {
+                let event = BackupEvent::FileBackedUp {
+                    original_path: result.path,
+                    archive_path: PathBuf::from(&record.archive_path),
+                    size_bytes: record.size_bytes as u64,
+                    codec: record.codec.clone().unwrap_or_default(),
+                    compression_ratio: record.compression_ratio(),
+                };
+                records.push((record, entries, delta));
+                event
+            }
+            Err(e) => {
+                println!(
+                    "\t[{}] {}",
+                    game.id,
+                    BackupEvent::Error {
+                        original_path: result.path,
+                        error_string: e.to_string(),
+                    }
+                );
+                return Err(e);
+            }
+        };
+
+        println!("\t[{}] {}", game.id, event);
+        Ok(())
+    }
+
+    // Records the backup of a game once all of its paths are done, writing its manifest first,
+    // signed if a signing key is given. Nothing is recorded if the game is unchanged, or if
+    // any of its paths failed, and the version directory claimed for it is removed.
+    fn finish_game_backup(
+        &mut self,
+        game: &GameConfig,
+        version: &str,
+        files: Vec<BackupFileRecord>,
+        (failed, signing_key): (bool, Option<&SigningKey>),
+    ) -> Result<(), KaguyaError> {
+        let version_dir = self.config.backup_dir.join(&game.id).join(version);
+
+        if failed {
+            if version_dir.exists() {
+                remove_dir_all(&version_dir)?;
+            }
+            println!("Backup '{} ({})' failed.\n", game.name, game.id);
+            return Ok(());
+        }
+
+        if files.is_empty() {
+            if version_dir.exists() {
+                remove_dir_all(&version_dir)?;
+            }
+            println!(
+                "'{} ({})' has not changed since the latest backup, skipping.\n",
+                game.name, game.id
+            );
             return Ok(());
         }
 
-        // Prepare backup directory and DB record
-        let time_string = get_time_string();
-        let backup_version_dir = &self.config.backup_dir.join(&game.id).join(&time_string);
-        create_dir_all(backup_version_dir)?;
+        // The version directory was claimed by this run, a manifest in it belongs to
+        // another version and is never replaced
+        if version_dir.join(VERSION_MANIFEST_FILE).exists() || is_signed(&version_dir) {
+            return Err(KaguyaError::InvalidInput(format!(
+                "Version {} of '{}' already has a manifest, leaving it as it is",
+                version, game.id
+            )));
+        }
 
         let backup_record = Backup {
             id: 0,
             game_id: self.db.get_game_id_with_external_id(&game.id)?,
-            version: time_string.clone(),
+            version: version.to_string(),
             timestamp: get_timestamp(),
         };
-        let backup_id = self.db.insert_backup(&backup_record)?;
 
-        // Execute backup and collect metadata
-        println!("Backing up '{} ({})'...", game.name, game.id);
-        let backup_file_records =
-            self.perform_backup_and_collect_meta(paths_to_backup, backup_version_dir, backup_id)?;
+        let manifest_result = (|| -> Result<String, KaguyaError> {
+            let previous = match signing_key {
+                Some(_) => self.latest_signed_manifest_hash(game, backup_record.game_id)?,
+                None => None,
+            };
+            let entries = files
+                .iter()
+                .map(|(file, _, delta)| {
+                    let delta = delta
+                        .as_ref()
+                        .map(|link| self.manifest_delta(link))
+                        .transpose()?;
+                    ManifestEntry::new(file, delta)
+                })
+                .collect::<Result<_, KaguyaError>>()?;
+            self.write_manifest(game, &backup_record, entries, previous, signing_key)
+        })();
+        let backup_id = match manifest_result.and_then(|_| self.db.insert_backup(&backup_record)) {
+            Ok(backup_id) => backup_id,
+            Err(e) => {
+                remove_dir_all(&version_dir)?;
+                return Err(e);
+            }
+        };
+
+        let event = BackupEvent::Created {
+            external_id: game.id.clone(),
+            total_files: files.len(),
+            total_size_bytes: files.iter().map(|(f, _, _)| f.size_bytes as u64).sum(),
+        };
 
         // Persist metadata
-        self.db.insert_backup_file(backup_id, backup_file_records)?;
-        println!("Backup '{} ({})' completed.\n", game.name, game.id);
+        self.db.insert_backup_file(backup_id, files)?;
+        if let Some(key) = signing_key {
+            self.record_signed_version(game, backup_record.game_id, key)?;
+        }
+        println!("{}\n", event);
 
         Ok(())
     }
@@ -128,57 +507,8 @@ impl VaultService {
         }
     }
 
-    // Iterates through paths, performs the backup, and collects metadata.
-    fn perform_backup_and_collect_meta(
-        &self,
-        paths: &Vec<PathBuf>,
-        target_dir: &impl AsRef<Path>,
-        backup_id: i64,
-    ) -> Result<Vec<BackupFile>, KaguyaError> {
-        let mut records = Vec::new();
-
-        for path in paths {
-            // Perform the actual file system backup
-            let archive_path = Self::backup_single_path(path, target_dir)?;
-
-            // Collect metadata
-            let record = BackupFile {
-                id: 0,
-                backup_id,
-                original_path: path.to_string_lossy().to_string(),
-                archive_path: archive_path.to_string_lossy().to_string(),
-                size_bytes: calculate_file_bytes(&archive_path)?,
-                checksum: calculate_entry_checksum(&archive_path)?,
-            };
-
-            records.push(record);
-        }
-
-        Ok(records)
-    }
-
-    // Backup single path to target directory, get file name for targer archive file.
-    // Return archive file path.
-    //
-    // e.g., '~/Games/game-a/saves/' -> '~/.local/bin/kaguya/vault/<ID>/<VERSION>/saves.tar.gz'
-    fn backup_single_path(
-        src: &impl AsRef<Path>,
-        dst: &impl AsRef<Path>,
-    ) -> Result<PathBuf, KaguyaError> {
-        let src = src.as_ref();
-        let dst = dst.as_ref();
-
-        let file_name = get_file_name(src).unwrap_or_default();
-        let backup_file = dst.join(file_name + ".tar.gz");
-
-        println!("\tCompressing '{}'...", src.display());
-        compress_to_tar_gz(&src, &backup_file)?;
-        println!("\tCompressed to '{}' completed.\n", backup_file.display());
-
-        Ok(backup_file)
-    }
-
     pub fn restore(&mut self, request: &RestoreRequest) -> Result<(), KaguyaError> {
+        let _lock = self.lock(LockMode::Shared)?;
         let games = self.get_game_list()?;
         // '--id'
         match find_game_ref(&games, &request.id) {
@@ -191,13 +521,22 @@ impl VaultService {
                 let game_id = self.db.get_game_id_with_external_id(&game.id)?;
 
                 for path in restore_paths {
-                    let archive_path =
+                    let backup_file =
                         self.db
-                            .get_archive_file_path(game_id, request.version.clone(), path)?; // '--version'
+                            .get_restore_backup_file(game_id, request.version.clone(), path)?; // '--version'
 
-                    println!("Restoring from '{}'...", archive_path.display());
+                    println!("Restoring from '{}'...", backup_file.archive_path);
 
-                    restore_archive(&archive_path, &path)?;
+                    // The archive is verified against its recorded checksum while unpacking
+                    let format = self.backup_format(&backup_file)?;
+                    let entries = self.db.get_backup_file_entries(backup_file.id)?;
+                    restore_archive(
+                        &backup_file.archive_path,
+                        &path,
+                        &format,
+                        Some(&backup_file.checksum),
+                        &entries,
+                    )?;
 
                     println!("Restore to '{}' succeeded.\n", path.display());
                 }
@@ -208,7 +547,1442 @@ impl VaultService {
         }
     }
 
+    /// Export a backup version of a game as a single archive, e.g. a zip file
+    /// for friends on other platforms. Paths unchanged in that version are taken
+    /// from the earlier backup they were skipped in favor of.
+    pub fn export(&mut self, request: &ExportRequest) -> Result<(), KaguyaError> {
+        let _lock = self.lock(LockMode::Shared)?;
+        let games = self.get_game_list()?;
+        let game = find_game_ref(&games, &request.id)
+            .ok_or_else(|| KaguyaError::GameNotFound(request.id.clone()))?;
+        let game_id = self.db.get_game_id_with_external_id(&game.id)?;
+        let settings = CompressionSettings::new(&request.format, None)?;
+
+        let version = match &request.version {
+            Some(v) => v.clone(),
+            None => {
+                self.db
+                    .get_latest_backup(game_id)?
+                    .ok_or_else(|| {
+                        KaguyaError::InvalidInput(format!(
+                            "No backups found for game '{}'",
+                            game.id
+                        ))
+                    })?
+                    .version
+            }
+        };
+
+        let bundle_name = format!("{}-{}", game.id, version);
+        let output = match &request.output {
+            Some(p) => p.clone(),
+            None => current_dir()?.join(format!("{}.{}", bundle_name, settings.codec.extension())),
+        };
+
+        // Unpack every path of the version into a staging directory, then pack it as one archive
+        let staging_dir = self
+            .config
+            .vault_dir
+            .join(generate_unique_temp_name(".kaguya-export", 8));
+        let bundle_dir = staging_dir.join(&bundle_name);
+        create_dir_all(&bundle_dir)?;
+        defer! {
+            remove_dir_all(&staging_dir).ok();
+        }
+
+        for path in &game.paths {
+            let backup_file =
+                match self
+                    .db
+                    .get_restore_backup_file(game_id, Some(version.clone()), path)
+                {
+                    Ok(file) => file,
+                    Err(KaguyaError::Database(rusqlite::Error::QueryReturnedNoRows)) => {
+                        println!(
+                            "\tNo backup of '{}' in this version, skipping.",
+                            path.display()
+                        );
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+
+            let format = self.backup_format(&backup_file)?;
+            unpack_backup(
+                &backup_file.archive_path,
+                &bundle_dir,
+                &format,
+                Some(&backup_file.checksum),
+            )?;
+            println!("\tAdded '{}'", path.display());
+        }
+
+        compress_archive(&bundle_dir, &output, &settings, None)?;
+        println!(
+            "Exported '{} ({})' version {} to '{}'",
+            game.name,
+            game.id,
+            version,
+            output.display()
+        );
+        Ok(())
+    }
+
+    /// Delete backups of games, and objects no longer referenced by any backup.
+    ///
+    /// If '--version' is given, delete that backup of the game.
+    /// If '--purge' is given, delete all backups of the game.
+    /// Otherwise keep the latest 'keep_versions' backups of each game (0 keeps all).
+    ///
+    /// Backups of a game removed from the vault config are only pruned with '--id',
+    /// and purging them drops the game from the database as well.
+    pub fn prune(&mut self, request: &PruneRequest) -> Result<(), KaguyaError> {
+        let _lock = self.lock(LockMode::Exclusive)?;
+        let vault_config = read_vault_config(&self.config.vault_config_path)?;
+        let removed_game;
+        let games: Vec<&GameConfig> = match &request.id {
+            Some(id) => match find_game_ref(&vault_config.games, id) {
+                Some(game) => vec![game],
+                None => {
+                    removed_game = self.removed_game(id)?;
+                    if request.version.is_none() && !request.purge {
+                        return Err(KaguyaError::InvalidInput(format!(
+                            "'{}' was removed from the vault config, give '--version' or '--purge' to prune its backups",
+                            id
+                        )));
+                    }
+                    vec![&removed_game]
+                }
+            },
+            None => vault_config.games.iter().collect(),
+        };
+
+        for game in games {
+            let game_id = self.db.get_game_id_with_external_id(&game.id)?;
+            let backups = self.db.get_backups(game_id)?;
+
+            let expired: Vec<i64> = if let Some(version) = &request.version {
+                let backup = backups
+                    .iter()
+                    .find(|b| &b.version == version)
+                    .ok_or_else(|| {
+                        KaguyaError::InvalidInput(format!(
+                            "No backup version '{}' found for game '{}'",
+                            version, game.id
+                        ))
+                    })?;
+                vec![backup.id]
+            } else if request.purge {
+                backups.iter().map(|b| b.id).collect()
+            } else {
+                let keep = game
+                    .keep_versions
+                    .unwrap_or(vault_config.backup.keep_versions as i64);
+                retention_expired(&backups, keep)
+            };
+
+            if expired.is_empty() {
+                println!("Nothing to prune for '{} ({})'.", game.name, game.id);
+            } else {
+                self.prune_backups(game, &backups, &expired)?;
+            }
+
+            if request.purge && find_game_ref(&vault_config.games, &game.id).is_none() {
+                self.db.delete_game(game_id)?;
+                println!("\t[{}] Removed '{}' from the database.", game.id, game.name);
+            }
+        }
+
+        self.collect_garbage()?;
+        println!("Prune finished!");
+        Ok(())
+    }
+
+    // Delete the `expired` backups of a game, oldest first.
+    //
+    // Later backups skip unchanged paths and restore them from the backup they were
+    // skipped in favor of, so a file still needed by the next backup is moved into it
+    // instead of being deleted. A delta file another one is stored against is
+    // deleted only once that one is rebased onto its own base.
+    //
+    // Signed manifests are verified before anything is deleted. The manifests of the
+    // remaining versions are written again afterwards, since their files changed, and
+    // signed ones are signed again and re-chained.
+    fn prune_backups(
+        &mut self,
+        game: &GameConfig,
+        backups: &[Backup],
+        expired: &[i64],
+    ) -> Result<(), KaguyaError> {
+        let game_dir = self.config.backup_dir.join(&game.id);
+        let signed = !expired.is_empty()
+            && backups
+                .iter()
+                .any(|b| is_signed(&game_dir.join(&b.version)));
+        let signing_key = if signed { self.signing_key()? } else { None };
+        if let Some(key) = &signing_key {
+            let problems = self.signature_problems(game, backups, &key.verifying_key())?;
+            if !problems.is_empty() {
+                return Err(KaguyaError::Signature(format!(
+                    "{} signature problem(s) in the backups of '{}', refusing to sign them again, see 'kaguya vault check --verify-signatures'",
+                    problems.len(),
+                    game.id
+                )));
+            }
+        } else if signed {
+            println!(
+                "\t[{}] 'signing_key' is not set, signed manifests will not be updated.",
+                game.id
+            );
+        }
+
+        for (index, backup) in backups.iter().enumerate() {
+            if !expired.contains(&backup.id) {
+                continue;
+            }
+            let version_dir = self.config.backup_dir.join(&game.id).join(&backup.version);
+            let next = backups.get(index + 1);
+            let next_paths: HashSet<String> = match next {
+                Some(next) => self
+                    .db
+                    .get_backup_files(next.id)?
+                    .into_iter()
+                    .map(|file| file.original_path)
+                    .collect(),
+                None => HashSet::new(),
+            };
+
+            for file in self.db.get_backup_files(backup.id)? {
+                let archive_path = PathBuf::from(&file.archive_path);
+                match next {
+                    Some(next) if !next_paths.contains(&file.original_path) => {
+                        let next_dir = self.config.backup_dir.join(&game.id).join(&next.version);
+                        create_dir_all(&next_dir)?;
+                        let moved_path =
+                            next_dir.join(archive_path.file_name().ok_or_else(|| {
+                                KaguyaError::FileNameError(file.archive_path.clone())
+                            })?);
+                        if moved_path.exists() {
+                            return Err(KaguyaError::InvalidInput(format!(
+                                "Cannot move '{}' into version {}, '{}' already exists",
+                                file.archive_path,
+                                next.version,
+                                moved_path.display()
+                            )));
+                        }
+                        rename(&archive_path, &moved_path)?;
+                        if parity_path(&archive_path).is_file() {
+                            rename(parity_path(&archive_path), parity_path(&moved_path))?;
+                        }
+                        self.db.move_backup_file(file.id, next.id, &moved_path)?;
+                    }
+                    _ => {
+                        if file.codec.as_deref() == Some(DELTA_CODEC) {
+                            self.rebase_delta_successor(&file)?;
+                        }
+                        if archive_path.is_file() {
+                            remove_file(&archive_path)?;
+                        }
+                        if parity_path(&archive_path).is_file() {
+                            remove_file(parity_path(&archive_path))?;
+                        }
+                        self.db.delete_backup_file(file.id)?;
+                    }
+                }
+            }
+
+            if version_dir.exists() {
+                remove_dir_all(&version_dir)?;
+            }
+            self.db.delete_backup(backup.id)?;
+            println!(
+                "\t[{}] Pruned version {} of '{}'",
+                game.id, backup.version, game.name
+            );
+        }
+
+        if !expired.is_empty() {
+            self.rewrite_manifests(game, signing_key.as_ref())?;
+        }
+
+        // Drop the game directory once it has no versions left
+        if game_dir.is_dir() && read_dir(&game_dir)?.next().is_none() {
+            remove_dir(&game_dir)?;
+        }
+        Ok(())
+    }
+
+    // Re-encode the delta file stored against `file`, if any, against the base of `file`.
+    // It takes over the position of `file` in the chain, and the files after it move up.
+    fn rebase_delta_successor(&mut self, file: &BackupFile) -> Result<(), KaguyaError> {
+        let Some(successor_id) = self.db.get_delta_successor(file.id)? else {
+            return Ok(());
+        };
+        let successor = self.db.get_backup_file(successor_id)?;
+        let link = self.db.get_delta_link(file.id)?.ok_or_else(|| {
+            KaguyaError::InvalidInput(format!(
+                "No delta chain recorded for '{}'",
+                file.archive_path
+            ))
+        })?;
+        let bases = self.delta_bases(file)?;
+
+        let info = rebase_delta(
+            &DeltaFile {
+                path: PathBuf::from(&file.archive_path),
+                checksum: file.checksum.clone(),
+            },
+            &DeltaFile {
+                path: PathBuf::from(&successor.archive_path),
+                checksum: successor.checksum.clone(),
+            },
+            &bases,
+        )?;
+        self.db
+            .rebase_delta(successor_id, &link, info.size_bytes, &info.checksum)?;
+        refresh_parity(&successor.archive_path)?;
+        Ok(())
+    }
+
+    // Remove objects that are not referenced by the manifest of any backup.
+    // Manifests found in the vault count even if the DB doesn't record them,
+    // e.g. after a lost database, so their backups can still be reindexed.
+    fn collect_garbage(&mut self) -> Result<GarbageReport, KaguyaError> {
+        let store = self.object_store();
+
+        let mut referenced = HashSet::new();
+        for (_, manifest) in self.read_manifests()? {
+            referenced.extend(manifest.objects().map(|(object, _)| object.to_string()));
+        }
+        for manifest in self.vault_manifests()? {
+            referenced.extend(manifest.objects().map(|(object, _)| object.to_string()));
+        }
+
+        let report = store.collect_garbage(&referenced)?;
+        if report.removed_objects > 0 || report.removed_temp_files > 0 {
+            println!(
+                "Removed {} unreferenced object(s) and {} leftover temporary file(s), {} bytes freed.",
+                report.removed_objects, report.removed_temp_files, report.freed_bytes
+            );
+        }
+        Ok(report)
+    }
+
+    // Every object and chunk manifest recorded in the DB.
+    // A missing manifest would make its objects look unreferenced, so it is an error.
+    fn read_manifests(&self) -> Result<Vec<(BackupFile, ObjectManifest)>, KaguyaError> {
+        let mut manifests = Vec::new();
+        for codec in [OBJECTS_CODEC, CHUNKS_CODEC] {
+            for file in self.db.get_backup_files_by_codec(codec)? {
+                let manifest = ObjectManifest::read(&file.archive_path, Some(&file.checksum))?;
+                manifests.push((file, manifest));
+            }
+        }
+        Ok(manifests)
+    }
+
+    // Every object and chunk manifest in the version directories of the vault, recorded or not.
+    // Only files the version manifest lists as such are read, a mirrored save may have the
+    // same name. Versions written before version manifests are recognized by name, and a file
+    // that isn't an object manifest is skipped.
+    fn vault_manifests(&self) -> Result<Vec<ObjectManifest>, KaguyaError> {
+        let mut manifests = Vec::new();
+        for version_dir in self.version_dirs()? {
+            match read_version_manifest(&version_dir)? {
+                Some(version) => {
+                    for entry in version.files.iter().filter(|entry| {
+                        matches!(entry.codec.as_deref(), Some(OBJECTS_CODEC | CHUNKS_CODEC))
+                    }) {
+                        manifests.push(ObjectManifest::read(
+                            &version_dir.join(&entry.archive),
+                            Some(&entry.checksum),
+                        )?);
+                    }
+                }
+                None => manifests.extend(
+                    files_in(&version_dir)?
+                        .into_iter()
+                        .filter(|path| {
+                            path.file_name().is_some_and(|name| {
+                                name.to_string_lossy().ends_with(".manifest.toml")
+                            })
+                        })
+                        .filter_map(|path| ObjectManifest::read(&path, None).ok()),
+                ),
+            }
+        }
+        Ok(manifests)
+    }
+
+    // Every file directly inside a version directory of the vault, recorded or not
+    fn version_files(&self) -> Result<Vec<PathBuf>, KaguyaError> {
+        let mut files = Vec::new();
+        for version_dir in self.version_dirs()? {
+            files.extend(files_in(&version_dir)?);
+        }
+        Ok(files)
+    }
+
+    // Every version directory of the vault, recorded or not
+    fn version_dirs(&self) -> Result<Vec<PathBuf>, KaguyaError> {
+        let mut dirs = Vec::new();
+        if !self.config.backup_dir.is_dir() {
+            return Ok(dirs);
+        }
+        for game_dir in read_dir(&self.config.backup_dir)? {
+            let game_dir = game_dir?.path();
+            if !game_dir.is_dir() {
+                continue;
+            }
+            for version_dir in read_dir(&game_dir)? {
+                let version_dir = version_dir?.path();
+                if version_dir.is_dir() {
+                    dirs.push(version_dir);
+                }
+            }
+        }
+        Ok(dirs)
+    }
+
+    /// Space usage of every game, and deduplication of the object store.
+    pub fn stats(&self) -> Result<VaultStats, KaguyaError> {
+        let _lock = self.lock(LockMode::Shared)?;
+        let mut stats = VaultStats::default();
+
+        for game in self.get_game_list()? {
+            let game_id = self.db.get_game_id_with_external_id(&game.id)?;
+            let backups = self.db.get_backups(game_id)?;
+
+            let mut game_stats = GameStats {
+                id: game.id.clone(),
+                name: game.name.clone(),
+                versions: backups.len(),
+                files: 0,
+                stored_bytes: 0,
+                original_bytes: 0,
+            };
+            for backup in &backups {
+                for file in self.db.get_backup_files(backup.id)? {
+                    game_stats.files += 1;
+                    game_stats.stored_bytes += file.size_bytes as u64;
+                    game_stats.original_bytes += file.original_size_bytes.unwrap_or(0) as u64;
+                    if !matches!(file.codec.as_deref(), Some(OBJECTS_CODEC | CHUNKS_CODEC)) {
+                        stats.archive_bytes += file.size_bytes as u64;
+                    }
+                }
+            }
+            stats.games.push(game_stats);
+        }
+
+        let mut unique = HashMap::new();
+        for (_, manifest) in self.read_manifests()? {
+            stats.manifests += 1;
+            stats.logical_bytes += manifest.files.iter().map(|f| f.size).sum::<u64>();
+            unique.extend(
+                manifest
+                    .objects()
+                    .map(|(object, size)| (object.to_string(), size)),
+            );
+        }
+        stats.unique_bytes = unique.values().sum();
+
+        let store = self.object_store();
+        for object in store.list_objects()? {
+            stats.objects += 1;
+            stats.object_store_bytes += store.object_size(&object)?;
+        }
+
+        Ok(stats)
+    }
+
+    /// Verify every backup file of games against its recorded checksum:
+    /// archives and mirrors are hashed, manifests and all of their objects are verified,
+    /// and delta files are checked to have a consistent chain that still reconstructs.
+    ///
+    /// If '--id' is given, only check the specific game.
+    /// If '--verify-signatures' is given, every file must also match the signed manifest of
+    /// its version, and signed versions must chain to each other in order.
+    pub fn check(&self, request: &CheckRequest) -> Result<Vec<BackupCheck>, KaguyaError> {
+        let _lock = self.lock(LockMode::Shared)?;
+        let games = self.get_game_list()?;
+        let games: Vec<&GameConfig> = match &request.id {
+            Some(id) => vec![
+                find_game_ref(&games, id).ok_or_else(|| KaguyaError::GameNotFound(id.clone()))?,
+            ],
+            None => games.iter().collect(),
+        };
+
+        let verifying_key = if request.verify_signatures {
+            let key = self.signing_key()?.ok_or_else(|| {
+                KaguyaError::InvalidInput(
+                    "'--verify-signatures' needs 'signing_key' in the backup settings".to_string(),
+                )
+            })?;
+            Some(key.verifying_key())
+        } else {
+            None
+        };
+
+        let mut checks = Vec::new();
+        for game in games {
+            let game_id = self.db.get_game_id_with_external_id(&game.id)?;
+            let backups = self.db.get_backups(game_id)?;
+            let mut signature_problems = match &verifying_key {
+                Some(key) => self.signature_problems(game, &backups, key)?,
+                None => Vec::new(),
+            };
+
+            for backup in backups {
+                for file in self.db.get_backup_files(backup.id)? {
+                    let signature_problem = signature_problems
+                        .iter()
+                        .position(|c| {
+                            c.version == backup.version && c.original_path == file.original_path
+                        })
+                        .and_then(|index| signature_problems.remove(index).problem);
+                    let problem = self
+                        .backup_format(&file)
+                        .and_then(|format| {
+                            verify_backup(&file.archive_path, &format, &file.checksum)
+                        })
+                        .err()
+                        .map(|e| e.to_string())
+                        .map(|problem| parity_hint(&file, problem))
+                        .or_else(|| parity_problem(&file))
+                        .or(signature_problem);
+
+                    checks.push(BackupCheck {
+                        game_id: game.id.clone(),
+                        version: backup.version.clone(),
+                        original_path: file.original_path,
+                        archive_path: file.archive_path,
+                        problem,
+                    });
+                }
+            }
+            // Files the signed manifests list, but the vault doesn't have
+            checks.extend(signature_problems);
+        }
+
+        Ok(checks)
+    }
+
+    /// Repair damaged backup files in place from their parity files.
+    ///
+    /// Damaged blocks are rebuilt, and the repaired file is verified against its recorded
+    /// checksum. Damaged parity of an intact file is written again.
+    /// If '--id' is given, only repair the specific game.
+    pub fn repair(&self, request: &RepairRequest) -> Result<Vec<BackupRepair>, KaguyaError> {
+        let _lock = self.lock(LockMode::Exclusive)?;
+        let games = self.get_game_list()?;
+        let games: Vec<&GameConfig> = match &request.id {
+            Some(id) => vec![
+                find_game_ref(&games, id).ok_or_else(|| KaguyaError::GameNotFound(id.clone()))?,
+            ],
+            None => games.iter().collect(),
+        };
+        let parity = read_vault_config(&self.config.vault_config_path)?
+            .backup
+            .parity;
+
+        let mut repairs = Vec::new();
+        for game in games {
+            let game_id = self.db.get_game_id_with_external_id(&game.id)?;
+            for backup in self.db.get_backups(game_id)? {
+                for file in self.db.get_backup_files(backup.id)? {
+                    if !parity_path(&file.archive_path).is_file() {
+                        continue;
+                    }
+                    let Some(outcome) = self.repair_file(&file, parity) else {
+                        continue;
+                    };
+                    repairs.push(BackupRepair {
+                        game_id: game.id.clone(),
+                        version: backup.version.clone(),
+                        original_path: file.original_path,
+                        archive_path: file.archive_path,
+                        outcome,
+                    });
+                }
+            }
+        }
+
+        Ok(repairs)
+    }
+
+    // Repair a backup file with a parity file, `None` if neither is damaged.
+    // Unusable parity of an intact file is computed again with `parity` percent.
+    fn repair_file(&self, file: &BackupFile, parity: u32) -> Option<RepairOutcome> {
+        let verify = || {
+            self.backup_format(file)
+                .and_then(|format| verify_backup(&file.archive_path, &format, &file.checksum))
+        };
+
+        let scan = match scan_parity(&file.archive_path) {
+            Ok(scan) if scan.is_intact() => return None,
+            Ok(scan) => scan,
+            Err(e) => {
+                return Some(match verify() {
+                    Ok(()) => match write_parity(&file.archive_path, parity.max(1)) {
+                        Ok(_) => RepairOutcome::ParityRewritten,
+                        Err(e) => RepairOutcome::Failed(e.to_string()),
+                    },
+                    Err(_) => RepairOutcome::Failed(e.to_string()),
+                });
+            }
+        };
+
+        let outcome = repair_with_parity(&file.archive_path).and_then(|_| verify());
+        Some(match outcome {
+            Err(e) => RepairOutcome::Failed(e.to_string()),
+            Ok(()) if scan.file_damaged() => RepairOutcome::Repaired {
+                blocks: scan.damaged_shards,
+            },
+            Ok(()) => RepairOutcome::ParityRewritten,
+        })
+    }
+
+    /// Rebuild the backup records of games from the vault directory,
+    /// e.g. after the database was lost.
+    ///
+    /// Versions are read from their manifests, or inferred from their layout if they have none,
+    /// and every backup file is hashed again. The records of a game are replaced as a whole;
+    /// file metadata and recorded fields the vault can't tell are kept from records of the same
+    /// files. Game records come from the vault config, like on every run.
+    /// If '--id' is given, only reindex the specific game.
+    pub fn reindex(&mut self, request: &ReindexRequest) -> Result<ReindexReport, KaguyaError> {
+        let _lock = self.lock(LockMode::Exclusive)?;
+        let vault_config = read_vault_config(&self.config.vault_config_path)?;
+        let games: Vec<&GameConfig> = match &request.id {
+            Some(id) => vec![
+                find_game_ref(&vault_config.games, id)
+                    .ok_or_else(|| KaguyaError::GameNotFound(id.clone()))?,
+            ],
+            None => vault_config.games.iter().collect(),
+        };
+        let keyframe_interval = vault_config.backup.delta_keyframe_interval;
+        let backup_dir = &self.config.backup_dir;
+
+        let mut report = ReindexReport::default();
+        if request.id.is_none() {
+            scan_unknown_games(backup_dir, &vault_config.games, &mut report)?;
+        }
+        let scanned = games
+            .iter()
+            .map(|game| {
+                let game_dir = backup_dir.join(&game.id);
+                scan_game_dir(game, &game_dir, keyframe_interval, &mut report)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (game, versions) in games.into_iter().zip(scanned) {
+            let game_reindex = self.replace_backup_records(game, versions, &mut report)?;
+            report.games.push(game_reindex);
+        }
+        // The vault key of a lost database is taken over from the vault directory
+        if let Some(envelope) = self.key_envelope()? {
+            self.save_key_envelope(&envelope)?;
+        }
+        Ok(report)
+    }
+
+    // Replace the backup records of a game with the scanned versions
+    fn replace_backup_records(
+        &mut self,
+        game: &GameConfig,
+        versions: Vec<ScannedVersion>,
+        report: &mut ReindexReport,
+    ) -> Result<GameReindex, KaguyaError> {
+        let game_id = self.db.get_game_id_with_external_id(&game.id)?;
+
+        // Current records, by version and archive path
+        let previous = self.db.get_backups(game_id)?;
+        let mut timestamps = HashMap::new();
+        let mut known = HashMap::new();
+        for backup in &previous {
+            timestamps.insert(backup.version.clone(), backup.timestamp.clone());
+            for file in self.db.get_backup_files(backup.id)? {
+                let entries = self.db.get_backup_file_entries(file.id)?;
+                known.insert(
+                    (backup.version.clone(), file.archive_path.clone()),
+                    (file, entries),
+                );
+            }
+        }
+        // Newest first, so no delta file outlives its successor
+        for backup in previous.iter().rev() {
+            self.db.delete_backup(backup.id)?;
+        }
+
+        let mut game_reindex = GameReindex {
+            id: game.id.clone(),
+            versions: versions.len(),
+            files: 0,
+            from_manifests: versions.iter().filter(|v| v.from_manifest).count(),
+            previous_versions: previous.len(),
+        };
+        // Backup file IDs by '<version>/<file name>', to resolve delta bases
+        let mut file_ids: HashMap<String, i64> = HashMap::new();
+        for scanned in versions {
+            let timestamp = if scanned.from_manifest {
+                scanned.timestamp
+            } else {
+                // Inferred from the version name, the recorded one is more precise
+                timestamps
+                    .remove(&scanned.version)
+                    .unwrap_or(scanned.timestamp)
+            };
+            let backup_id = self.db.insert_backup(&Backup {
+                id: 0,
+                game_id,
+                version: scanned.version.clone(),
+                timestamp,
+            })?;
+
+            let mut records: Vec<BackupFileRecord> = Vec::new();
+            for ScannedFile { mut file, delta } in scanned.files {
+                let mut entries = Vec::new();
+                if let Some((recorded, recorded_entries)) =
+                    known.remove(&(scanned.version.clone(), file.archive_path.clone()))
+                    && recorded.checksum == file.checksum
+                {
+                    entries = recorded_entries;
+                    if !scanned.from_manifest {
+                        file = recorded;
+                    }
+                }
+
+                let link = delta.map(|delta| DeltaLink {
+                    base_file_id: delta.base.and_then(|base| {
+                        let id = file_ids.get(&base).copied();
+                        if id.is_none() {
+                            report.mismatched(
+                                &file.archive_path,
+                                format!("delta base '{}' is not in the vault", base),
+                            );
+                        }
+                        id
+                    }),
+                    depth: delta.depth,
+                    keyframe_interval: delta.keyframe_interval,
+                });
+                records.push((file, entries, link));
+            }
+            game_reindex.files += records.len();
+            self.db.insert_backup_file(backup_id, records)?;
+
+            for file in self.db.get_backup_files(backup_id)? {
+                let name = Path::new(&file.archive_path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                file_ids.insert(format!("{}/{}", scanned.version, name), file.id);
+            }
+        }
+
+        Ok(game_reindex)
+    }
+
+    /// Change the passphrase of the vault encryption key.
+    ///
+    /// Only the wrapped data key is replaced, archives stay encrypted with the same key.
+    pub fn rekey(&mut self, request: &RekeyRequest) -> Result<(), KaguyaError> {
+        let _lock = self.lock(LockMode::Exclusive)?;
+        let key = self.data_key()?;
+        let passphrase = read_new_passphrase(request.new_key_file.as_deref())?;
+        self.save_key_envelope(&KeyEnvelope::seal(&key, &passphrase)?)?;
+
+        println!("Vault passphrase changed.");
+        if let Some(key_file) = read_vault_config(&self.config.vault_config_path)?
+            .backup
+            .key_file
+            && request.new_key_file.as_ref() != Some(&key_file)
+        {
+            println!(
+                "'key_file' still points to '{}', update it to the new passphrase.",
+                key_file.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Compare current saves with their latest backups.
+    ///
+    /// If '--id' is given, only report the specific game.
+    pub fn status(&self, request: &StatusRequest) -> Result<Vec<GameStatus>, KaguyaError> {
+        let _lock = self.lock(LockMode::Shared)?;
+        let games = self.get_game_list()?;
+        let games: Vec<&GameConfig> = match &request.id {
+            Some(id) => vec![
+                find_game_ref(&games, id).ok_or_else(|| KaguyaError::GameNotFound(id.clone()))?,
+            ],
+            None => games.iter().collect(),
+        };
+
+        games
+            .into_iter()
+            .map(|game| self.game_status(game, request.paranoid))
+            .collect()
+    }
+
+    // Build status report of a single game
+    fn game_status(&self, game: &GameConfig, paranoid: bool) -> Result<GameStatus, KaguyaError> {
+        let game_id = self.db.get_game_id_with_external_id(&game.id)?;
+
+        let paths = game
+            .paths
+            .iter()
+            .map(|path| {
+                Ok(PathStatus {
+                    path: path.clone(),
+                    kind: self.path_status(game_id, path, paranoid)?,
+                })
+            })
+            .collect::<Result<Vec<_>, KaguyaError>>()?;
+
+        Ok(GameStatus {
+            id: game.id.clone(),
+            name: game.name.clone(),
+            last_backup: self.db.get_latest_backup(game_id)?,
+            paths,
+        })
+    }
+
+    // Compare current content checksum of a path with the latest backup of it
+    fn path_status(
+        &self,
+        game_id: i64,
+        path: &Path,
+        paranoid: bool,
+    ) -> Result<PathStatusKind, KaguyaError> {
+        if !path.exists() {
+            return Ok(PathStatusKind::Missing);
+        }
+
+        let Some((backup, file)) = self.db.get_latest_backup_file(game_id, &path)? else {
+            return Ok(PathStatusKind::NeverBackedUp);
+        };
+
+        let version = backup.version;
+        Ok(match file.source_checksum {
+            Some(recorded) if recorded == self.source_checksum(path, paranoid)? => {
+                PathStatusKind::UpToDate { version }
+            }
+            Some(_) => PathStatusKind::Modified { version },
+            None => PathStatusKind::Unknown { version },
+        })
+    }
+
+    // Content checksum of a configured path, reusing cached hashes of unchanged files
+    // unless 'paranoid' is set. The file index is only read, under the shared lock of
+    // 'status', the next backup records the new hashes.
+    fn source_checksum(&self, path: &Path, paranoid: bool) -> Result<String, KaguyaError> {
+        let index = self.db.get_file_index(&path)?;
+        let (checksum, _) = calculate_entry_checksum_cached(path, &index, paranoid)?;
+        Ok(checksum)
+    }
+
+    fn object_store(&self) -> ObjectStore {
+        ObjectStore::new(&self.config.objects_dir)
+    }
+
+    // Format of a recorded backup file, unlocking the vault key for an encrypted archive
+    fn backup_format(&self, backup_file: &BackupFile) -> Result<BackupFormat, KaguyaError> {
+        if backup_file.codec.as_deref() == Some(DELTA_CODEC) {
+            return Ok(BackupFormat::Delta(self.delta_bases(backup_file)?));
+        }
+        match BackupFormat::resolve(
+            backup_file.codec.as_deref(),
+            &backup_file.archive_path,
+            &self.object_store(),
+        )? {
+            BackupFormat::Archive(codec) if is_encrypted(&backup_file.archive_path)? => {
+                Ok(BackupFormat::EncryptedArchive(codec, self.data_key()?))
+            }
+            format => Ok(format),
+        }
+    }
+
+    // Key new archives are encrypted with, `None` if encryption is disabled.
+    // The vault key is created on the first encrypted backup, unless the vault already has
+    // encrypted archives: their key would be replaced by one that can't decrypt them.
+    fn backup_encryption_key(
+        &mut self,
+        backup_settings: &BackupSettings,
+    ) -> Result<Option<DataKey>, KaguyaError> {
+        if !backup_settings.encryption {
+            return Ok(None);
+        }
+        if let Some(envelope) = self.key_envelope()? {
+            // Vaults encrypted before the key was kept in the vault directory get a copy of it
+            self.save_key_envelope(&envelope)?;
+            return self.data_key().map(Some);
+        }
+        for path in self.version_files()? {
+            if is_encrypted(&path)? {
+                return Err(KaguyaError::Encryption(format!(
+                    "'{}' is encrypted, but the vault key is missing, restore '{}' of the vault or the database holding it",
+                    path.display(),
+                    KEY_ENVELOPE_FILE
+                )));
+            }
+        }
+
+        let key = DataKey::generate();
+        let passphrase = read_passphrase(backup_settings.key_file.as_deref(), true)?;
+        let envelope = KeyEnvelope::seal(&key, &passphrase)?;
+        self.save_key_envelope(&envelope)?;
+        println!(
+            "Created the vault encryption key. Keep the passphrase safe, encrypted backups can't be restored without it."
+        );
+
+        self.data_key.set(key.clone()).ok();
+        Ok(Some(key))
+    }
+
+    // The vault key, unlocked with the passphrase on first use
+    fn data_key(&self) -> Result<DataKey, KaguyaError> {
+        if let Some(key) = self.data_key.get() {
+            return Ok(key.clone());
+        }
+
+        let envelope = self.key_envelope()?.ok_or_else(|| {
+            KaguyaError::Encryption("The vault has no encryption key".to_string())
+        })?;
+        let key_file = read_vault_config(&self.config.vault_config_path)?
+            .backup
+            .key_file;
+        let key = envelope.open(&read_passphrase(key_file.as_deref(), false)?)?;
+
+        self.data_key.set(key.clone()).ok();
+        Ok(key)
+    }
+
+    // Wrapped vault key with its key derivation parameters, `None` if encryption was never enabled.
+    // A database that lost it falls back to the copy in the vault directory.
+    fn key_envelope(&self) -> Result<Option<KeyEnvelope>, KaguyaError> {
+        match self.db.get_meta_value(KEY_ENCRYPTION) {
+            Ok(value) => Ok(Some(toml::from_str(&value)?)),
+            Err(KaguyaError::Database(rusqlite::Error::QueryReturnedNoRows)) => {
+                KeyEnvelope::load(&self.config.vault_dir.join(KEY_ENVELOPE_FILE))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // Keep the wrapped vault key both in the database and in the vault directory
+    fn save_key_envelope(&self, envelope: &KeyEnvelope) -> Result<(), KaguyaError> {
+        let path = self.config.vault_dir.join(KEY_ENVELOPE_FILE);
+        if KeyEnvelope::load(&path)?.as_ref() != Some(envelope) {
+            envelope.save(&path)?;
+        }
+        self.db
+            .update_meta_value(KEY_ENCRYPTION, &toml::to_string(envelope)?)
+    }
+
+    // Delta files a delta file is stored against, keyframe first.
+    // The recorded chain is validated along the way: every link must exist,
+    // be a delta file one step closer to the keyframe, and stay within its interval.
+    fn delta_bases(&self, backup_file: &BackupFile) -> Result<Vec<DeltaFile>, KaguyaError> {
+        let broken = |reason: String| {
+            KaguyaError::InvalidInput(format!(
+                "Broken delta chain of '{}': {}",
+                backup_file.archive_path, reason
+            ))
+        };
+
+        let mut link = self
+            .db
+            .get_delta_link(backup_file.id)?
+            .ok_or_else(|| broken("no chain recorded".to_string()))?;
+        let mut bases = Vec::new();
+        let mut file_id = backup_file.id;
+        loop {
+            if link.depth >= link.keyframe_interval.max(1) {
+                return Err(broken(format!(
+                    "depth {} of backup file {} exceeds its keyframe interval {}",
+                    link.depth, file_id, link.keyframe_interval
+                )));
+            }
+            let Some(base_id) = link.base_file_id else {
+                break;
+            };
+
+            let base = self
+                .db
+                .get_backup_file(base_id)
+                .map_err(|_| broken(format!("base backup file {} is missing", base_id)))?;
+            if base.codec.as_deref() != Some(DELTA_CODEC) {
+                return Err(broken(format!(
+                    "base backup file {} is not a delta",
+                    base_id
+                )));
+            }
+            let base_link = self
+                .db
+                .get_delta_link(base_id)?
+                .ok_or_else(|| broken(format!("no chain recorded for base {}", base_id)))?;
+            if base_link.depth + 1 != link.depth {
+                return Err(broken(format!(
+                    "depth {} of backup file {} doesn't follow depth {} of its base",
+                    link.depth, file_id, base_link.depth
+                )));
+            }
+
+            bases.push(DeltaFile {
+                path: PathBuf::from(&base.archive_path),
+                checksum: base.checksum,
+            });
+            file_id = base_id;
+            link = base_link;
+        }
+
+        if link.depth != 0 {
+            return Err(broken(format!("backup file {} is not a keyframe", file_id)));
+        }
+        bases.reverse();
+        Ok(bases)
+    }
+
+    // Chain of a delta file up to the file itself, `None` if it has no chain recorded
+    fn delta_chain(&self, backup_file: &BackupFile) -> Result<Option<PreviousDelta>, KaguyaError> {
+        let Some(link) = self.db.get_delta_link(backup_file.id)? else {
+            return Ok(None);
+        };
+        let mut chain = self.delta_bases(backup_file)?;
+        chain.push(DeltaFile {
+            path: expand_path(&backup_file.archive_path)?,
+            checksum: backup_file.checksum.clone(),
+        });
+
+        Ok(Some(PreviousDelta {
+            file_id: backup_file.id,
+            link,
+            chain,
+        }))
+    }
+
+    // Key new versions are signed with, `None` if signing is disabled.
+    // The key file is created on the first signed backup.
+    fn backup_signing_key(
+        &self,
+        backup_settings: &BackupSettings,
+    ) -> Result<Option<SigningKey>, KaguyaError> {
+        let Some(path) = &backup_settings.signing_key else {
+            return Ok(None);
+        };
+        let path = expand_path(path)?;
+        let signing = self.vault_signing()?;
+        if path.exists() {
+            let key = load_signing_key(&path)?;
+            if let Some(signing) = &signing {
+                signing.check_key(&key.verifying_key())?;
+            }
+            return Ok(Some(key));
+        }
+        if let Some(signing) = &signing {
+            return Err(KaguyaError::Signature(format!(
+                "the signing key '{}' is missing, the vault was signed with the key {}",
+                path.display(),
+                signing.public_key
+            )));
+        }
+
+        let key = create_signing_key(&path)?;
+        println!(
+            "Created the signing key '{}', public key {}. Keep it outside the vault.",
+            path.display(),
+            public_key_hex(&key.verifying_key())
+        );
+        Ok(Some(key))
+    }
+
+    // Record of signed vaults, next to the global config
+    fn signing_record_path(&self) -> PathBuf {
+        self.config
+            .global_config_path
+            .with_file_name(SIGNING_RECORD_FILE)
+    }
+
+    // Signing of this vault recorded outside of it, `None` if it was never signed
+    fn vault_signing(&self) -> Result<Option<VaultSigning>, KaguyaError> {
+        let vault_dir = to_absolute_path(&self.config.vault_dir)?;
+        Ok(SigningRecord::load(&self.signing_record_path())?
+            .vault(&vault_dir)
+            .cloned())
+    }
+
+    // Record the first signed version of a game outside the vault, unless it already is.
+    // Versions signed before the record was kept count as well.
+    fn record_signed_version(
+        &self,
+        game: &GameConfig,
+        game_id: i64,
+        key: &SigningKey,
+    ) -> Result<(), KaguyaError> {
+        let path = self.signing_record_path();
+        let mut record = SigningRecord::load(&path)?;
+        let signing = record.vault_mut(
+            &to_absolute_path(&self.config.vault_dir)?,
+            &public_key_hex(&key.verifying_key()),
+        );
+        if signing.games.contains_key(&game.id) {
+            return Ok(());
+        }
+
+        let game_dir = self.config.backup_dir.join(&game.id);
+        let first = self
+            .db
+            .get_backups(game_id)?
+            .into_iter()
+            .find(|backup| is_signed(&game_dir.join(&backup.version)));
+        if let Some(first) = first {
+            signing.games.insert(game.id.clone(), first.version);
+            record.save(&path)?;
+        }
+        Ok(())
+    }
+
+    // The configured signing key, `None` if 'signing_key' is not set
+    fn signing_key(&self) -> Result<Option<SigningKey>, KaguyaError> {
+        read_vault_config(&self.config.vault_config_path)?
+            .backup
+            .signing_key
+            .map(|path| load_signing_key(&expand_path(&path)?))
+            .transpose()
+    }
+
+    // Hash of the manifest of the latest signed version of a game, the next one chains to it
+    fn latest_signed_manifest_hash(
+        &self,
+        game: &GameConfig,
+        game_id: i64,
+    ) -> Result<Option<String>, KaguyaError> {
+        for backup in self.db.get_backups(game_id)?.iter().rev() {
+            let version_dir = self.config.backup_dir.join(&game.id).join(&backup.version);
+            if is_signed(&version_dir) {
+                return manifest_hash(&version_dir);
+            }
+        }
+        Ok(None)
+    }
+
+    // Write the manifest of a version, signed with `key` if given, returning its hash
+    fn write_manifest(
+        &self,
+        game: &GameConfig,
+        backup: &Backup,
+        files: Vec<ManifestEntry>,
+        previous: Option<String>,
+        key: Option<&SigningKey>,
+    ) -> Result<String, KaguyaError> {
+        let manifest = VersionManifest {
+            game_id: game.id.clone(),
+            name: game.name.clone(),
+            version: backup.version.clone(),
+            timestamp: backup.timestamp.clone(),
+            kaguya_version: env!("CARGO_PKG_VERSION").to_string(),
+            previous,
+            signer: key.map(|key| public_key_hex(&key.verifying_key())),
+            files,
+        };
+        let version_dir = self.config.backup_dir.join(&game.id).join(&backup.version);
+        write_version_manifest(&version_dir, &manifest, key)
+    }
+
+    // Manifest entry of a recorded backup file
+    fn manifest_entry(&self, file: &BackupFile) -> Result<ManifestEntry, KaguyaError> {
+        let delta = self
+            .db
+            .get_delta_link(file.id)?
+            .map(|link| self.manifest_delta(&link))
+            .transpose()?;
+        ManifestEntry::new(file, delta)
+    }
+
+    // Chain position of a delta file, its base named by version and file name
+    fn manifest_delta(&self, link: &DeltaLink) -> Result<ManifestDelta, KaguyaError> {
+        let base = match link.base_file_id {
+            Some(base_id) => {
+                let base = PathBuf::from(self.db.get_backup_file(base_id)?.archive_path);
+                let name = |path: Option<&Path>| {
+                    path.and_then(|p| p.file_name())
+                        .map(|name| name.to_string_lossy().to_string())
+                        .ok_or_else(|| KaguyaError::FileNameError(base.display().to_string()))
+                };
+                Some(format!(
+                    "{}/{}",
+                    name(base.parent())?,
+                    name(Some(base.as_path()))?
+                ))
+            }
+            None => None,
+        };
+
+        Ok(ManifestDelta {
+            base,
+            depth: link.depth,
+            keyframe_interval: link.keyframe_interval,
+        })
+    }
+
+    // Write the manifests of every version of a game again from its recorded files.
+    // Signed versions are signed again and re-chained, unless `key` is missing,
+    // then they are left untouched.
+    fn rewrite_manifests(
+        &self,
+        game: &GameConfig,
+        key: Option<&SigningKey>,
+    ) -> Result<(), KaguyaError> {
+        let game_id = self.db.get_game_id_with_external_id(&game.id)?;
+        let mut previous = None;
+        for backup in self.db.get_backups(game_id)? {
+            let version_dir = self.config.backup_dir.join(&game.id).join(&backup.version);
+            let files = self
+                .db
+                .get_backup_files(backup.id)?
+                .iter()
+                .map(|file| self.manifest_entry(file))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if !is_signed(&version_dir) {
+                self.write_manifest(game, &backup, files, None, None)?;
+            } else if let Some(key) = key {
+                previous = Some(self.write_manifest(game, &backup, files, previous, Some(key))?);
+            }
+        }
+        Ok(())
+    }
+
+    // Files of a game that don't match the signed manifests of their versions, and
+    // files the manifests list that the vault doesn't have.
+    //
+    // Versions older than the first signed one are not checked, every later version
+    // must be signed and name the manifest of the signed version before it.
+    fn signature_problems(
+        &self,
+        game: &GameConfig,
+        backups: &[Backup],
+        key: &VerifyingKey,
+    ) -> Result<Vec<BackupCheck>, KaguyaError> {
+        // Versions from the first signed one on must be signed, even if their signatures are gone.
+        // Version names sort by time.
+        let first_signed = match self.vault_signing()? {
+            Some(signing) => {
+                signing.check_key(key)?;
+                signing.games.get(&game.id).cloned()
+            }
+            None => None,
+        };
+        let mut problems = Vec::new();
+        let mut previous: Option<String> = None;
+        let mut signed_seen = false;
+
+        for backup in backups {
+            let version_dir = self.config.backup_dir.join(&game.id).join(&backup.version);
+            let files = self.db.get_backup_files(backup.id)?;
+            let problem = |file: &BackupFile, problem: String| BackupCheck {
+                game_id: game.id.clone(),
+                version: backup.version.clone(),
+                original_path: file.original_path.clone(),
+                archive_path: file.archive_path.clone(),
+                problem: Some(problem),
+            };
+
+            let hash = if is_signed(&version_dir) {
+                manifest_hash(&version_dir)?
+            } else {
+                None
+            };
+            let before_signing = first_signed
+                .as_ref()
+                .is_none_or(|first| backup.version < *first);
+            if hash.is_none() && !signed_seen && before_signing {
+                continue;
+            }
+            let expected_previous = std::mem::replace(&mut previous, hash);
+            signed_seen = true;
+
+            let version_problem = match read_signed_manifest(&version_dir, key) {
+                Ok(Some(manifest)) => {
+                    if manifest.game_id != game.id || manifest.version != backup.version {
+                        Err(format!(
+                            "the signed manifest belongs to version {} of '{}'",
+                            manifest.version, manifest.game_id
+                        ))
+                    } else if manifest.previous != expected_previous {
+                        Err(
+                            "the signed manifest doesn't follow the previous signed version, versions were removed or reordered"
+                                .to_string(),
+                        )
+                    } else {
+                        Ok(manifest)
+                    }
+                }
+                Ok(None) => Err("the version is not signed".to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            let manifest = match version_problem {
+                Ok(manifest) => manifest,
+                Err(version_problem) => {
+                    problems.extend(files.iter().map(|f| problem(f, version_problem.clone())));
+                    continue;
+                }
+            };
+
+            for file in &files {
+                let entry = manifest
+                    .files
+                    .iter()
+                    .find(|entry| entry.original_path == file.original_path);
+                let mismatch = match entry {
+                    None => Some("not listed in the signed manifest".to_string()),
+                    Some(entry) => match self.manifest_entry(file) {
+                        Ok(recorded) => {
+                            let in_version = Path::new(&file.archive_path).parent()
+                                == Some(version_dir.as_path());
+                            (!entry.matches(&recorded) || !in_version)
+                                .then(|| "doesn't match the signed manifest".to_string())
+                        }
+                        Err(e) => Some(e.to_string()),
+                    },
+                };
+                if let Some(mismatch) = mismatch {
+                    problems.push(problem(file, mismatch));
+                }
+            }
+
+            problems.extend(
+                manifest
+                    .files
+                    .iter()
+                    .filter(|entry| !files.iter().any(|f| f.original_path == entry.original_path))
+                    .map(|entry| BackupCheck {
+                        game_id: game.id.clone(),
+                        version: backup.version.clone(),
+                        original_path: entry.original_path.clone(),
+                        archive_path: version_dir
+                            .join(&entry.archive)
+                            .to_string_lossy()
+                            .to_string(),
+                        problem: Some(
+                            "listed in the signed manifest, but missing from the vault".to_string(),
+                        ),
+                    }),
+            );
+        }
+
+        Ok(problems)
+    }
+
+    // Lock the vault for an operation, until the returned lock is dropped
+    fn lock(&self, mode: LockMode) -> Result<VaultLock, KaguyaError> {
+        VaultLock::acquire(&self.config.vault_dir, mode)
+    }
+
     fn get_game_list(&self) -> Result<Vec<GameConfig>, KaguyaError> {
         Ok(read_vault_config(&self.config.vault_config_path)?.games)
     }
+
+    // A game removed from the vault config whose backups are kept, as the config it had
+    fn removed_game(&self, id: &str) -> Result<GameConfig, KaguyaError> {
+        self.db
+            .get_db_game_list()?
+            .iter()
+            .find(|game| game.external_id == id && game.removed_at.is_some())
+            .map(GameConfig::from)
+            .ok_or_else(|| KaguyaError::GameNotFound(id.to_string()))
+    }
+}
+
+// Files directly inside `dir`
+fn files_in(dir: &Path) -> Result<Vec<PathBuf>, KaguyaError> {
+    let mut files = Vec::new();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+// Tell whether a damaged backup file can be repaired from its parity file, if it has one
+fn parity_hint(file: &BackupFile, problem: String) -> String {
+    if !parity_path(&file.archive_path).is_file() {
+        return problem;
+    }
+    match scan_parity(&file.archive_path) {
+        Ok(scan) if scan.file_damaged() && scan.is_repairable() => format!(
+            "{} ({} damaged block(s), repairable with 'kaguya vault repair')",
+            problem, scan.damaged_shards
+        ),
+        Ok(scan) if scan.file_damaged() => format!(
+            "{} ({} damaged block(s), more than its parity can repair)",
+            problem, scan.damaged_shards
+        ),
+        Ok(_) => problem,
+        Err(e) => format!("{} ({})", problem, e),
+    }
+}
+
+// Damage of the parity file of an intact backup file
+fn parity_problem(file: &BackupFile) -> Option<String> {
+    if !parity_path(&file.archive_path).is_file() {
+        return None;
+    }
+    match scan_parity(&file.archive_path) {
+        Ok(scan) if scan.is_intact() => None,
+        Ok(_) => Some("parity data damaged, rewrite it with 'kaguya vault repair'".to_string()),
+        Err(e) => Some(format!("{}, rewrite it with 'kaguya vault repair'", e)),
+    }
+}
+
+// Archive format of a game: its own setting if any, otherwise the vault-wide one
+fn compression_settings(
+    game: &GameConfig,
+    backup_settings: &BackupSettings,
+) -> Result<CompressionSettings, KaguyaError> {
+    let mut settings = match &game.compression {
+        Some(codec) => CompressionSettings::new(codec, game.compression_level)?,
+        None => CompressionSettings::new(
+            &backup_settings.compression,
+            backup_settings.compression_level,
+        )?,
+    };
+    settings.adaptive = backup_settings.adaptive_compression;
+    Ok(settings)
+}
+
+// Storage mode of a game: its own setting if any, otherwise the vault-wide one
+fn storage_mode(
+    game: &GameConfig,
+    backup_settings: &BackupSettings,
+) -> Result<StorageMode, KaguyaError> {
+    StorageMode::from_str(game.storage.as_ref().unwrap_or(&backup_settings.storage))
+}
+
+// Largest path stored as a delta in bytes. A delta holds its base and the new version in
+// memory, which must fit the zstd window together.
+fn delta_max_size(backup_settings: &BackupSettings) -> Result<u64, KaguyaError> {
+    let max_size = backup_settings
+        .delta_max_size_mb
+        .saturating_mul(1024 * 1024);
+    if max_size > (MAX_DELTA_SPAN / 2) as u64 {
+        return Err(KaguyaError::InvalidInput(format!(
+            "'delta_max_size_mb' is {}, deltas support at most {} MiB",
+            backup_settings.delta_max_size_mb,
+            MAX_DELTA_SPAN / 2 / (1024 * 1024)
+        )));
+    }
+    Ok(max_size)
+}
+
+// Backups beyond the latest `keep` ones, given oldest first; `keep` of 0 or less keeps all
+fn retention_expired(backups: &[Backup], keep: i64) -> Vec<i64> {
+    if keep <= 0 {
+        return Vec::new();
+    }
+    let expired = backups.len().saturating_sub(keep as usize);
+    backups[..expired].iter().map(|b| b.id).collect()
 }
@@ -1,12 +1,12 @@
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
-use rusqlite::params;
+use rusqlite::{OptionalExtension, params};
 
 use super::DbManager;
 use crate::{
     models::{
         KaguyaError,
-        db::{Backup, BackupFile},
+        db::{Backup, BackupFile, BackupFileEntry, DeltaLink},
     },
     utils::path::expand_path,
 };
@@ -17,15 +17,59 @@ pub trait DbManagerBackupExt {
     fn insert_backup_file(
         &mut self,
         game_id: i64,
-        files: Vec<BackupFile>,
+        files: Vec<(BackupFile, Vec<BackupFileEntry>, Option<DeltaLink>)>,
     ) -> Result<(), KaguyaError>;
 
-    fn get_archive_file_path(
+    fn get_backup_file_entries(
+        &self,
+        backup_file_id: i64,
+    ) -> Result<Vec<BackupFileEntry>, KaguyaError>;
+
+    fn get_delta_link(&self, backup_file_id: i64) -> Result<Option<DeltaLink>, KaguyaError>;
+
+    fn get_delta_successor(&self, backup_file_id: i64) -> Result<Option<i64>, KaguyaError>;
+
+    fn get_restore_backup_file(
         &self,
         game_id: i64,
         version: Option<String>,
         original_path: &impl AsRef<Path>,
-    ) -> Result<PathBuf, KaguyaError>;
+    ) -> Result<BackupFile, KaguyaError>;
+
+    fn get_latest_backup(&self, game_id: i64) -> Result<Option<Backup>, KaguyaError>;
+
+    fn get_latest_backup_file(
+        &self,
+        game_id: i64,
+        original_path: &impl AsRef<Path>,
+    ) -> Result<Option<(Backup, BackupFile)>, KaguyaError>;
+
+    fn get_backups(&self, game_id: i64) -> Result<Vec<Backup>, KaguyaError>;
+
+    fn get_backup_file(&self, backup_file_id: i64) -> Result<BackupFile, KaguyaError>;
+
+    fn get_backup_files(&self, backup_id: i64) -> Result<Vec<BackupFile>, KaguyaError>;
+
+    fn get_backup_files_by_codec(&self, codec: &str) -> Result<Vec<BackupFile>, KaguyaError>;
+
+    fn move_backup_file(
+        &mut self,
+        backup_file_id: i64,
+        backup_id: i64,
+        archive_path: &impl AsRef<Path>,
+    ) -> Result<(), KaguyaError>;
+
+    fn rebase_delta(
+        &mut self,
+        backup_file_id: i64,
+        link: &DeltaLink,
+        size_bytes: i64,
+        checksum: &str,
+    ) -> Result<(), KaguyaError>;
+
+    fn delete_backup_file(&mut self, backup_file_id: i64) -> Result<(), KaguyaError>;
+
+    fn delete_backup(&mut self, backup_id: i64) -> Result<(), KaguyaError>;
 }
 
 impl DbManagerBackupExt for DbManager {
@@ -43,63 +87,410 @@ impl DbManagerBackupExt for DbManager {
     fn insert_backup_file(
         &mut self,
         backup_id: i64,
-        files: Vec<BackupFile>,
+        files: Vec<(BackupFile, Vec<BackupFileEntry>, Option<DeltaLink>)>,
     ) -> Result<(), KaguyaError> {
         let tx = self.conn.transaction()?;
         {
+            let mut delta_stmt = tx.prepare(
+                "INSERT INTO backup_file_delta (backup_file_id, base_file_id, depth, keyframe_interval)
+                    VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            let mut entry_stmt = tx.prepare(
+                "INSERT INTO backup_file_entry (backup_file_id, rel_path, mtime_ns, mode, hash)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
             let mut stmt = tx.prepare(
-                "INSERT INTO backup_file (backup_id, original_path, archive_path, size_bytes, checksum)
-                    VALUES (?1, ?2, ?3, ?4, ?5)"
+                "INSERT INTO backup_file (backup_id, original_path, archive_path, size_bytes, checksum, source_checksum, codec, original_size_bytes)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
             )?;
 
-            for file in files {
+            for (file, entries, delta) in files {
                 stmt.execute((
                     backup_id,
                     file.original_path,
                     file.archive_path,
                     file.size_bytes,
                     file.checksum,
+                    file.source_checksum,
+                    file.codec,
+                    file.original_size_bytes,
                 ))?;
+
+                let backup_file_id = tx.last_insert_rowid();
+                for entry in entries {
+                    entry_stmt.execute((
+                        backup_file_id,
+                        entry.rel_path,
+                        entry.mtime_ns,
+                        entry.mode,
+                        entry.hash,
+                    ))?;
+                }
+
+                if let Some(link) = delta {
+                    delta_stmt.execute((
+                        backup_file_id,
+                        link.base_file_id,
+                        link.depth,
+                        link.keyframe_interval,
+                    ))?;
+                }
             }
         } // stmt end life here
         tx.commit()?;
         Ok(())
     }
 
-    fn get_archive_file_path(
+    fn get_backup_file_entries(
+        &self,
+        backup_file_id: i64,
+    ) -> Result<Vec<BackupFileEntry>, KaguyaError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT rel_path, mtime_ns, mode, hash FROM backup_file_entry
+                WHERE backup_file_id = ?1
+                ORDER BY rel_path",
+        )?;
+
+        let entries = stmt
+            .query_map([backup_file_id], |row| {
+                Ok(BackupFileEntry {
+                    rel_path: row.get(0)?,
+                    mtime_ns: row.get(1)?,
+                    mode: row.get(2)?,
+                    hash: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(entries)
+    }
+
+    // Chain link of a delta backup file, `None` if it has none recorded
+    fn get_delta_link(&self, backup_file_id: i64) -> Result<Option<DeltaLink>, KaguyaError> {
+        let link = self
+            .conn
+            .query_row(
+                "SELECT base_file_id, depth, keyframe_interval FROM backup_file_delta
+                    WHERE backup_file_id = ?1",
+                [backup_file_id],
+                |row| {
+                    Ok(DeltaLink {
+                        base_file_id: row.get(0)?,
+                        depth: row.get(1)?,
+                        keyframe_interval: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(link)
+    }
+
+    // Delta backup file stored against the given one, if any
+    fn get_delta_successor(&self, backup_file_id: i64) -> Result<Option<i64>, KaguyaError> {
+        let successor = self
+            .conn
+            .query_row(
+                "SELECT backup_file_id FROM backup_file_delta WHERE base_file_id = ?1",
+                [backup_file_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(successor)
+    }
+
+    // Find the backup file to restore a path from, with its archive path expanded
+    fn get_restore_backup_file(
         &self,
         game_id: i64,
         version: Option<String>,
         original_path: &impl AsRef<Path>,
-    ) -> Result<PathBuf, KaguyaError> {
+    ) -> Result<BackupFile, KaguyaError> {
         // Convert Path to String for SQLite comparison (TEXT field)
         let path_str = original_path.as_ref().to_string_lossy().to_string();
 
-        let archive_path_str: String = match version {
-            // Case 1: Specific version provided
+        let mut file = match version {
+            // Case 1: Specific version provided.
+            // Unchanged paths are skipped by later backups, so fall back to the
+            // latest backup of the path made at or before that version.
             Some(ver) => self.conn.query_row(
-                "SELECT bf.archive_path 
+                "SELECT bf.id, bf.backup_id, bf.original_path, bf.archive_path, bf.size_bytes, bf.checksum, bf.source_checksum, bf.codec, bf.original_size_bytes
              FROM backup b 
              JOIN backup_file bf ON b.id = bf.backup_id 
-             WHERE b.game_id = ?1 AND b.version = ?2 AND bf.original_path = ?3",
+             WHERE b.game_id = ?1 AND bf.original_path = ?3
+                AND b.id <= (SELECT id FROM backup WHERE game_id = ?1 AND version = ?2)
+             ORDER BY b.id DESC
+             LIMIT 1",
                 params![game_id, ver, path_str],
-                |row| row.get(0),
+                backup_file_from_row,
             )?,
 
             // Case 2: Version is None, find the latest by timestamp
             None => self.conn.query_row(
-                "SELECT bf.archive_path 
+                "SELECT bf.id, bf.backup_id, bf.original_path, bf.archive_path, bf.size_bytes, bf.checksum, bf.source_checksum, bf.codec, bf.original_size_bytes
              FROM backup b 
              JOIN backup_file bf ON b.id = bf.backup_id 
              WHERE b.game_id = ?1 AND bf.original_path = ?2
-             ORDER BY b.timestamp DESC 
+             ORDER BY b.timestamp DESC, b.id DESC
              LIMIT 1",
                 params![game_id, path_str],
-                |row| row.get(0),
+                backup_file_from_row,
             )?,
         };
 
-        // Ok(PathBuf::from(archive_path_str))
-        expand_path(&archive_path_str)
+        file.archive_path = expand_path(&file.archive_path)?
+            .to_string_lossy()
+            .to_string();
+        Ok(file)
+    }
+
+    // Latest backup of a game by timestamp
+    fn get_latest_backup(&self, game_id: i64) -> Result<Option<Backup>, KaguyaError> {
+        let backup = self
+            .conn
+            .query_row(
+                "SELECT id, game_id, version, timestamp
+             FROM backup
+             WHERE game_id = ?1
+             ORDER BY timestamp DESC, id DESC
+             LIMIT 1",
+                [game_id],
+                backup_from_row,
+            )
+            .optional()?;
+        Ok(backup)
+    }
+
+    // Latest backup containing the given original path, with its file record
+    fn get_latest_backup_file(
+        &self,
+        game_id: i64,
+        original_path: &impl AsRef<Path>,
+    ) -> Result<Option<(Backup, BackupFile)>, KaguyaError> {
+        let path_str = original_path.as_ref().to_string_lossy().to_string();
+
+        let result = self
+            .conn
+            .query_row(
+                "SELECT b.id, b.game_id, b.version, b.timestamp,
+                    bf.id, bf.original_path, bf.archive_path, bf.size_bytes, bf.checksum, bf.source_checksum, bf.codec, bf.original_size_bytes
+             FROM backup b
+             JOIN backup_file bf ON b.id = bf.backup_id
+             WHERE b.game_id = ?1 AND bf.original_path = ?2
+             ORDER BY b.timestamp DESC, b.id DESC
+             LIMIT 1",
+                params![game_id, path_str],
+                |row| {
+                    let backup = Backup {
+                        id: row.get(0)?,
+                        game_id: row.get(1)?,
+                        version: row.get(2)?,
+                        timestamp: row.get(3)?,
+                    };
+                    let file = BackupFile {
+                        id: row.get(4)?,
+                        backup_id: backup.id,
+                        original_path: row.get(5)?,
+                        archive_path: row.get(6)?,
+                        size_bytes: row.get(7)?,
+                        checksum: row.get(8)?,
+                        source_checksum: row.get(9)?,
+                        codec: row.get(10)?,
+                        original_size_bytes: row.get(11)?,
+                    };
+                    Ok((backup, file))
+                },
+            )
+            .optional()?;
+        Ok(result)
     }
+
+    // All backups of a game, oldest first
+    fn get_backups(&self, game_id: i64) -> Result<Vec<Backup>, KaguyaError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, game_id, version, timestamp
+             FROM backup
+             WHERE game_id = ?1
+             ORDER BY id",
+        )?;
+
+        let backups = stmt
+            .query_map([game_id], backup_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(backups)
+    }
+
+    // A single backup file, with its archive path expanded
+    fn get_backup_file(&self, backup_file_id: i64) -> Result<BackupFile, KaguyaError> {
+        let file = self.conn.query_row(
+            "SELECT id, backup_id, original_path, archive_path, size_bytes, checksum, source_checksum, codec, original_size_bytes
+             FROM backup_file
+             WHERE id = ?1",
+            [backup_file_id],
+            backup_file_from_row,
+        )?;
+        Ok(expand_archive_paths(vec![file])?.remove(0))
+    }
+
+    // Files of a single backup, with their archive paths expanded
+    fn get_backup_files(&self, backup_id: i64) -> Result<Vec<BackupFile>, KaguyaError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, backup_id, original_path, archive_path, size_bytes, checksum, source_checksum, codec, original_size_bytes
+             FROM backup_file
+             WHERE backup_id = ?1
+             ORDER BY id",
+        )?;
+
+        let files = stmt
+            .query_map([backup_id], backup_file_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        expand_archive_paths(files)
+    }
+
+    // Files of every backup stored with the given codec, e.g. all object manifests
+    fn get_backup_files_by_codec(&self, codec: &str) -> Result<Vec<BackupFile>, KaguyaError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, backup_id, original_path, archive_path, size_bytes, checksum, source_checksum, codec, original_size_bytes
+             FROM backup_file
+             WHERE codec = ?1
+             ORDER BY id",
+        )?;
+
+        let files = stmt
+            .query_map([codec], backup_file_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        expand_archive_paths(files)
+    }
+
+    // Reassign a backup file to another backup, after its archive was moved there
+    fn move_backup_file(
+        &mut self,
+        backup_file_id: i64,
+        backup_id: i64,
+        archive_path: &impl AsRef<Path>,
+    ) -> Result<(), KaguyaError> {
+        self.conn.execute(
+            "UPDATE backup_file SET backup_id = ?1, archive_path = ?2 WHERE id = ?3",
+            params![
+                backup_id,
+                archive_path.as_ref().to_string_lossy().to_string(),
+                backup_file_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Record a delta file re-encoded against another base.
+    // Files further down its chain move one step closer to the keyframe as well.
+    fn rebase_delta(
+        &mut self,
+        backup_file_id: i64,
+        link: &DeltaLink,
+        size_bytes: i64,
+        checksum: &str,
+    ) -> Result<(), KaguyaError> {
+        let tx = self.conn.transaction()?;
+        let old_depth: u32 = tx.query_row(
+            "SELECT depth FROM backup_file_delta WHERE backup_file_id = ?1",
+            [backup_file_id],
+            |row| row.get(0),
+        )?;
+        tx.execute(
+            "UPDATE backup_file_delta SET base_file_id = ?1, depth = ?2, keyframe_interval = ?3
+                WHERE backup_file_id = ?4",
+            params![
+                link.base_file_id,
+                link.depth,
+                link.keyframe_interval,
+                backup_file_id
+            ],
+        )?;
+        tx.execute(
+            "WITH RECURSIVE descendant(id) AS (
+                SELECT backup_file_id FROM backup_file_delta WHERE base_file_id = ?1
+                UNION ALL
+                SELECT d.backup_file_id FROM backup_file_delta d
+                    JOIN descendant ON d.base_file_id = descendant.id
+             )
+             UPDATE backup_file_delta SET depth = depth - ?2
+                WHERE backup_file_id IN (SELECT id FROM descendant)",
+            params![backup_file_id, old_depth.saturating_sub(link.depth)],
+        )?;
+        tx.execute(
+            "UPDATE backup_file SET size_bytes = ?1, checksum = ?2 WHERE id = ?3",
+            params![size_bytes, checksum, backup_file_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete_backup_file(&mut self, backup_file_id: i64) -> Result<(), KaguyaError> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM backup_file_delta WHERE backup_file_id = ?1",
+            [backup_file_id],
+        )?;
+        tx.execute(
+            "DELETE FROM backup_file_entry WHERE backup_file_id = ?1",
+            [backup_file_id],
+        )?;
+        tx.execute("DELETE FROM backup_file WHERE id = ?1", [backup_file_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Delete a backup with its remaining files and the events referencing it
+    fn delete_backup(&mut self, backup_id: i64) -> Result<(), KaguyaError> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM backup_file_entry WHERE backup_file_id IN
+                (SELECT id FROM backup_file WHERE backup_id = ?1)",
+            [backup_id],
+        )?;
+        tx.execute(
+            "DELETE FROM backup_file_delta WHERE backup_file_id IN
+                (SELECT id FROM backup_file WHERE backup_id = ?1)",
+            [backup_id],
+        )?;
+        tx.execute("DELETE FROM backup_file WHERE backup_id = ?1", [backup_id])?;
+        tx.execute("DELETE FROM event WHERE backup_id = ?1", [backup_id])?;
+        tx.execute("DELETE FROM backup WHERE id = ?1", [backup_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+// Map a row of (id, game_id, version, timestamp)
+fn backup_from_row(row: &rusqlite::Row) -> Result<Backup, rusqlite::Error> {
+    Ok(Backup {
+        id: row.get(0)?,
+        game_id: row.get(1)?,
+        version: row.get(2)?,
+        timestamp: row.get(3)?,
+    })
+}
+
+// Expand '~' in archive paths of backup files
+fn expand_archive_paths(mut files: Vec<BackupFile>) -> Result<Vec<BackupFile>, KaguyaError> {
+    for file in &mut files {
+        file.archive_path = expand_path(&file.archive_path)?
+            .to_string_lossy()
+            .to_string();
+    }
+    Ok(files)
+}
+
+// Map a row of (id, backup_id, original_path, archive_path, size_bytes, checksum, source_checksum, codec,
+// original_size_bytes)
+fn backup_file_from_row(row: &rusqlite::Row) -> Result<BackupFile, rusqlite::Error> {
+    Ok(BackupFile {
+        id: row.get(0)?,
+        backup_id: row.get(1)?,
+        original_path: row.get(2)?,
+        archive_path: row.get(3)?,
+        size_bytes: row.get(4)?,
+        checksum: row.get(5)?,
+        source_checksum: row.get(6)?,
+        codec: row.get(7)?,
+        original_size_bytes: row.get(8)?,
+    })
 }
@@ -2,13 +2,27 @@
 //!
 //! The `DbManager` struct is the central entry point for all database operations.
 //! The [`new`](DbManager::new) associated function is responsible for opening a
-//! connection, running the initial schema if necessary, and performing the first
-//! sync. Other modules extend its functionality using traits.
+//! connection, creating or [migrating](super::migration) the schema if necessary,
+//! and performing the first sync, under the vault lock. Other modules extend its
+//! functionality using traits.
 
-use super::{DbManagerMetaExt, DbManagerSyncExt};
-use crate::models::{KEY_SCHEMA_VERSION, KaguyaError};
+use super::{DbManagerMetaExt, DbManagerMigrationExt, DbManagerSyncExt, migration::SCHEMA_VERSION};
+use crate::{
+    fs_utils::{
+        crypto::KeyEnvelope,
+        lock::{LockMode, VaultLock},
+    },
+    models::{BACKUP_DIR, KEY_ENCRYPTION, KEY_ENVELOPE_FILE, KaguyaError},
+};
 use rusqlite::Connection;
-use std::{fs::create_dir_all, path::Path};
+use std::{
+    fs::{create_dir_all, read_dir},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// How long a connection waits for another one to release its lock
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct DbManager {
     pub conn: Connection,
@@ -16,40 +30,105 @@ pub struct DbManager {
 
 /// Database initialize and sync
 impl DbManager {
-    // If no kaguya SQLite DB exists, initialize it.
-    // Otherwise, sync with vault config if needed
+    /// Open the database, creating or migrating its schema, and sync it with the vault config.
+    ///
+    /// The vault lock is held meanwhile: shared while the database is only read,
+    /// exclusive once it has to be initialized, migrated or synced.
     pub fn new(
         db_path: &impl AsRef<Path>,
         vault_config_path: &impl AsRef<Path>,
     ) -> Result<Self, KaguyaError> {
-        create_dir_all(
-            db_path
-                .as_ref()
-                .parent()
-                .expect("'db_path' should have parent."),
-        )?;
+        let is_new = !db_path.as_ref().exists();
+        let manager = Self::open(db_path)?;
+        let vault_dir = vault_dir(db_path);
+
+        let lock = VaultLock::acquire(&vault_dir, LockMode::Shared)?;
+        if manager.pending_migrations()?.is_empty() && !manager.needs_sync(vault_config_path)? {
+            return Ok(manager);
+        }
+        // Both are checked again under the exclusive lock, another run may have done them
+        drop(lock);
+        let _lock = VaultLock::acquire(&vault_dir, LockMode::Exclusive)?;
+        manager.initialize(&vault_dir, vault_config_path, is_new)
+    }
+
+    /// Like [`new`](Self::new), for a caller already holding the exclusive vault lock
+    pub fn new_locked(
+        db_path: &impl AsRef<Path>,
+        vault_config_path: &impl AsRef<Path>,
+    ) -> Result<Self, KaguyaError> {
+        let is_new = !db_path.as_ref().exists();
+        Self::open(db_path)?.initialize(&vault_dir(db_path), vault_config_path, is_new)
+    }
+
+    /// Open the database without creating its schema, migrating or syncing it,
+    /// e.g. to inspect pending migrations
+    pub fn open(db_path: &impl AsRef<Path>) -> Result<Self, KaguyaError> {
+        create_dir_all(vault_dir(db_path))?;
 
         let conn = Connection::open(db_path)?;
-        let mut manager = Self { conn };
-        manager.ensure_initialized()?;
-        manager.sync(vault_config_path, false)?;
-        Ok(manager)
+        configure_connection(&conn)?;
+        Ok(Self { conn })
     }
 
-    fn ensure_initialized(&mut self) -> Result<(), KaguyaError> {
-        let result = self.get_meta_value(KEY_SCHEMA_VERSION);
-        if result.is_err() {
-            println!("Database not found. Initializing new database...");
-            self.run_initialize_schema()?;
+    // If no kaguya SQLite DB exists, initialize it.
+    // Otherwise, migrate it and sync with vault config if needed
+    fn initialize(
+        mut self,
+        vault_dir: &Path,
+        vault_config_path: &impl AsRef<Path>,
+        is_new: bool,
+    ) -> Result<Self, KaguyaError> {
+        self.ensure_initialized()?;
+        self.sync(vault_config_path, false)?;
+
+        // A new database takes over the vault key kept in the vault directory
+        if is_new && let Some(envelope) = KeyEnvelope::load(&vault_dir.join(KEY_ENVELOPE_FILE))? {
+            self.update_meta_value(KEY_ENCRYPTION, &toml::to_string(&envelope)?)?;
         }
-        Ok(())
+
+        // A new database next to existing backups, e.g. after it was deleted
+        let has_backups =
+            read_dir(vault_dir.join(BACKUP_DIR)).is_ok_and(|mut entries| entries.next().is_some());
+        if is_new && has_backups {
+            println!(
+                "The vault already has backups the new database doesn't know, run 'kaguya vault reindex' to index them."
+            );
+        }
+        Ok(self)
     }
 
-    // SQLite DB initialize schema, located at <project_root>/migrations/
-    fn run_initialize_schema(&mut self) -> Result<(), KaguyaError> {
-        let init_sql = include_str!("../../migrations/V1__initial_schema.sql");
-        self.conn.execute_batch(init_sql)?;
-        println!("Database initialized successfully.");
+    // Create the schema of a new database, or migrate an older one
+    fn ensure_initialized(&mut self) -> Result<(), KaguyaError> {
+        let report = self.migrate(false)?;
+        match (report.from, &report.backup_path) {
+            (None, _) => println!("Database not found, initialized a new database."),
+            (Some(from), Some(backup_path)) => println!(
+                "Migrated database schema from version {} to {}, previous database saved to '{}'.",
+                from,
+                SCHEMA_VERSION,
+                backup_path.display()
+            ),
+            _ => {}
+        }
         Ok(())
     }
 }
+
+// Directory of the database, the vault directory
+fn vault_dir(db_path: &impl AsRef<Path>) -> PathBuf {
+    db_path
+        .as_ref()
+        .parent()
+        .expect("'db_path' should have parent.")
+        .to_path_buf()
+}
+
+// Settings SQLite doesn't keep in the database file, so every connection needs them:
+// enforced foreign keys, write-ahead logging, and waiting for a concurrent writer
+fn configure_connection(conn: &Connection) -> Result<(), KaguyaError> {
+    conn.pragma_update(None, "foreign_keys", true)?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Caches file hashes in the `file_index` table.
+//!
+//! This module defines the [`DbManagerFileIndexExt`] trait, which extends the
+//! [`DbManager`] with methods to load and replace the [`FileIndex`] of a
+//! configured path, so unchanged files don't need to be rehashed.
+
+use std::path::Path;
+
+use super::DbManager;
+use crate::{
+    fs_utils::hash::{FileIndex, FileIndexEntry},
+    models::KaguyaError,
+};
+
+pub trait DbManagerFileIndexExt {
+    fn get_file_index(&self, root_path: &impl AsRef<Path>) -> Result<FileIndex, KaguyaError>;
+
+    fn replace_file_index(
+        &mut self,
+        root_path: &impl AsRef<Path>,
+        index: &FileIndex,
+    ) -> Result<(), KaguyaError>;
+}
+
+impl DbManagerFileIndexExt for DbManager {
+    // Load cached file hashes of a configured path
+    fn get_file_index(&self, root_path: &impl AsRef<Path>) -> Result<FileIndex, KaguyaError> {
+        let root_str = root_path.as_ref().to_string_lossy().to_string();
+
+        let mut stmt = self.conn.prepare(
+            "SELECT rel_path, size_bytes, mtime_ns, inode, hash
+             FROM file_index
+             WHERE root_path = ?1",
+        )?;
+
+        let entries = stmt.query_map([root_str], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                FileIndexEntry {
+                    size_bytes: row.get(1)?,
+                    mtime_ns: row.get(2)?,
+                    inode: row.get(3)?,
+                    hash: row.get(4)?,
+                },
+            ))
+        })?;
+
+        Ok(entries.collect::<Result<FileIndex, rusqlite::Error>>()?)
+    }
+
+    // Replace all cached file hashes of a configured path,
+    // dropping entries of files that no longer exist
+    fn replace_file_index(
+        &mut self,
+        root_path: &impl AsRef<Path>,
+        index: &FileIndex,
+    ) -> Result<(), KaguyaError> {
+        let root_str = root_path.as_ref().to_string_lossy().to_string();
+
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM file_index WHERE root_path = ?1", [&root_str])?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO file_index (root_path, rel_path, size_bytes, mtime_ns, inode, hash)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+
+            for (rel_path, entry) in index {
+                stmt.execute((
+                    &root_str,
+                    rel_path,
+                    entry.size_bytes,
+                    entry.mtime_ns,
+                    entry.inode,
+                    &entry.hash,
+                ))?;
+            }
+        } // stmt end life here
+        tx.commit()?;
+        Ok(())
+    }
+}
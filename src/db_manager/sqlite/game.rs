@@ -5,25 +5,31 @@
 
 use std::collections::HashSet;
 
-use rusqlite::params;
+use chrono::Utc;
+use rusqlite::{Row, params};
 
 use super::{DbManager, DbManagerGamePathExt};
 use crate::models::{Game, KaguyaError, VaultConfig};
 
+const GAME_COLUMNS: &str =
+    "id, external_id, name, comment, keep_versions, created_at, updated_at, removed_at";
+
 pub trait DbManagerGameExt {
     fn upsert_games_from_config(
         &mut self,
         vault_config_file: &VaultConfig,
     ) -> Result<(), KaguyaError>;
 
-    fn prune_obsolete_games(
+    fn mark_removed_games(
         &mut self,
         vault_config_file: &VaultConfig,
     ) -> Result<Vec<String>, KaguyaError>;
 
     fn get_game_id_with_external_id(&self, external_id: &str) -> Result<i64, KaguyaError>;
+    fn get_db_game(&self, external_id: &str) -> Result<Game, KaguyaError>;
     fn get_db_game_list(&self) -> Result<Vec<Game>, KaguyaError>;
     fn upsert_game(&self, game: &Game) -> Result<Option<i64>, KaguyaError>;
+    fn delete_game(&mut self, game_id: i64) -> Result<(), KaguyaError>;
 }
 
 impl DbManagerGameExt for DbManager {
@@ -47,8 +53,9 @@ impl DbManagerGameExt for DbManager {
         Ok(())
     }
 
-    // Prune database of games and paths not found int the vault config.
-    fn prune_obsolete_games(
+    // Mark games not found in the vault config as removed.
+    // Their backups are kept, until they are purged with 'kaguya vault prune --id <ID> --purge'.
+    fn mark_removed_games(
         &mut self,
         vault_config_file: &VaultConfig,
     ) -> Result<Vec<String>, KaguyaError> {
@@ -59,21 +66,23 @@ impl DbManagerGameExt for DbManager {
             .map(|config_game| config_game.id.as_ref())
             .collect();
 
-        let mut pruned_games = Vec::new();
+        let mut removed_games = Vec::new();
         for db_game in &db_game_list {
-            if !config_game_ids.contains(&db_game.external_id.as_ref()) {
-                let sql = "DELETE FROM game WHERE id = ?1";
-                self.conn.execute(sql, [&db_game.id])?;
-                pruned_games.push(db_game.external_id.clone());
+            if db_game.removed_at.is_none()
+                && !config_game_ids.contains(&db_game.external_id.as_ref())
+            {
+                let sql = "UPDATE game SET removed_at = ?1 WHERE id = ?2";
+                self.conn.execute(sql, (Utc::now(), &db_game.id))?;
+                removed_games.push(db_game.external_id.clone());
 
                 println!(
-                    "Pruned game with ID '{}' from DB as it's not in config.",
+                    "Game with ID '{}' is not in config anymore, its backups are kept.",
                     &db_game.external_id
                 );
             }
         }
 
-        Ok(pruned_games)
+        Ok(removed_games)
     }
 
     // Return game.id if game exists, according to Game ID: game(external_id)
@@ -85,25 +94,19 @@ impl DbManagerGameExt for DbManager {
         Ok(id)
     }
 
-    /// Get games list from database.
+    /// Get a game from database by its Game ID, removed from the vault config or not.
+    fn get_db_game(&self, external_id: &str) -> Result<Game, KaguyaError> {
+        let sql = format!("SELECT {} FROM game WHERE external_id = ?1", GAME_COLUMNS);
+        let game = self.conn.query_row(&sql, [external_id], game_from_row)?;
+        Ok(game)
+    }
+
+    /// Get games list from database, removed games included.
     fn get_db_game_list(&self) -> Result<Vec<Game>, KaguyaError> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, external_id, name, comment, keep_versions, created_at, updated_at 
-             FROM game 
-             ORDER BY name",
-        )?;
-
-        let game_list_iter = stmt.query_map([], |row| {
-            Ok(Game {
-                id: row.get(0)?,
-                external_id: row.get(1)?,
-                name: row.get(2)?,
-                comment: row.get(3)?,
-                keep_versions: row.get(4)?,
-                created_at: row.get(5)?,
-                updated_at: row.get(6)?,
-            })
-        })?;
+        let sql = format!("SELECT {} FROM game ORDER BY name", GAME_COLUMNS);
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        let game_list_iter = stmt.query_map([], game_from_row)?;
 
         let game_list = game_list_iter.collect::<Result<Vec<_>, rusqlite::Error>>()?;
         Ok(game_list)
@@ -118,10 +121,12 @@ impl DbManagerGameExt for DbManager {
                 name = excluded.name,
                 comment = excluded.comment,
                 keep_versions = excluded.keep_versions,
-                updated_at = excluded.updated_at
+                updated_at = excluded.updated_at,
+                removed_at = NULL
             WHERE name IS NOT excluded.name
                 OR comment IS NOT excluded.comment
                 OR keep_versions IS NOT excluded.keep_versions
+                OR removed_at IS NOT NULL
             RETURNING id
             ";
 
@@ -143,6 +148,14 @@ impl DbManagerGameExt for DbManager {
         }
     }
 
+    /// Delete a game record along with its paths and events.
+    /// Its backups must be deleted first.
+    fn delete_game(&mut self, game_id: i64) -> Result<(), KaguyaError> {
+        self.conn
+            .execute("DELETE FROM game WHERE id = ?1", [game_id])?;
+        Ok(())
+    }
+
     // /// Update or insert a game record to the DB, return game.id
     // fn upsert_game(&self, game: &Game) -> Result<Option<i64>, KaguyaError> {
     //     let mut stmt = self.conn.prepare(
@@ -176,3 +189,17 @@ impl DbManagerGameExt for DbManager {
     //     }
     // }
 }
+
+// A game read from a row of `GAME_COLUMNS`
+fn game_from_row(row: &Row) -> rusqlite::Result<Game> {
+    Ok(Game {
+        id: row.get(0)?,
+        external_id: row.get(1)?,
+        name: row.get(2)?,
+        comment: row.get(3)?,
+        keep_versions: row.get(4)?,
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+        removed_at: row.get(7)?,
+    })
+}
@@ -0,0 +1,262 @@
+//! Versioned schema migrations of the database.
+//!
+//! This module defines the [`DbManagerMigrationExt`] trait, which brings the schema
+//! recorded in `meta.schema_version` up to [`SCHEMA_VERSION`]. Every pending
+//! [`Migration`] is applied in order inside its own transaction, together with the
+//! new schema version, so an interrupted run resumes where it stopped.
+//!
+//! The initial schema is migration 1, a new database is created by applying every
+//! migration. An existing database is copied next to itself before it is migrated,
+//! and a database from a newer kaguya is refused.
+
+use rusqlite::{OptionalExtension, Transaction};
+use std::{fs::remove_file, path::PathBuf};
+
+use super::{DbManager, DbManagerMetaExt};
+use crate::models::{KEY_SCHEMA_VERSION, KaguyaError};
+
+/// A schema migration, applied once to databases older than its version
+#[derive(Debug)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub step: MigrationStep,
+}
+
+/// How a [`Migration`] changes the database
+#[derive(Debug)]
+pub enum MigrationStep {
+    /// A SQL script from `migrations/`
+    Sql(&'static str),
+    /// Changes that need more than SQL, e.g. reading rows to compute new values
+    Code(fn(&Transaction) -> Result<(), KaguyaError>),
+}
+
+/// Every migration, ordered by version
+pub const MIGRATIONS: [Migration; 11] = [
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        step: MigrationStep::Sql(include_str!("../../migrations/V1__initial_schema.sql")),
+    },
+    Migration {
+        version: 2,
+        name: "backup_file_source_checksum",
+        step: MigrationStep::Sql(include_str!(
+            "../../migrations/V2__backup_file_source_checksum.sql"
+        )),
+    },
+    Migration {
+        version: 3,
+        name: "file_index",
+        step: MigrationStep::Sql(include_str!("../../migrations/V3__file_index.sql")),
+    },
+    Migration {
+        version: 4,
+        name: "backup_file_codec",
+        step: MigrationStep::Sql(include_str!("../../migrations/V4__backup_file_codec.sql")),
+    },
+    Migration {
+        version: 5,
+        name: "backup_file_original_size",
+        step: MigrationStep::Sql(include_str!(
+            "../../migrations/V5__backup_file_original_size.sql"
+        )),
+    },
+    Migration {
+        version: 6,
+        name: "backup_file_entry",
+        step: MigrationStep::Sql(include_str!("../../migrations/V6__backup_file_entry.sql")),
+    },
+    Migration {
+        version: 7,
+        name: "backup_file_entry_hash",
+        step: MigrationStep::Sql(include_str!(
+            "../../migrations/V7__backup_file_entry_hash.sql"
+        )),
+    },
+    Migration {
+        version: 8,
+        name: "backup_file_delta",
+        step: MigrationStep::Sql(include_str!("../../migrations/V8__backup_file_delta.sql")),
+    },
+    Migration {
+        version: 9,
+        name: "remove_orphans",
+        step: MigrationStep::Code(remove_orphans),
+    },
+    Migration {
+        version: 10,
+        name: "cascade_game_deletes",
+        step: MigrationStep::Sql(include_str!(
+            "../../migrations/V10__cascade_game_deletes.sql"
+        )),
+    },
+    Migration {
+        version: 11,
+        name: "keep_removed_games",
+        step: MigrationStep::Sql(include_str!("../../migrations/V11__keep_removed_games.sql")),
+    },
+];
+
+// Rows left behind while foreign keys were not enforced, parents before children.
+// A delta link whose base is gone is removed as well, 'vault check' reports its broken chain.
+const ORPHAN_QUERIES: [&str; 7] = [
+    "DELETE FROM game_path WHERE game_id NOT IN (SELECT id FROM game)",
+    "DELETE FROM backup WHERE game_id NOT IN (SELECT id FROM game)",
+    "DELETE FROM backup_file WHERE backup_id NOT IN (SELECT id FROM backup)",
+    "DELETE FROM backup_file_entry WHERE backup_file_id NOT IN (SELECT id FROM backup_file)",
+    "DELETE FROM backup_file_delta WHERE backup_file_id NOT IN (SELECT id FROM backup_file)
+        OR base_file_id NOT IN (SELECT id FROM backup_file)",
+    "DELETE FROM event WHERE game_id NOT IN (SELECT id FROM game)",
+    "DELETE FROM event WHERE backup_id NOT IN (SELECT id FROM backup)",
+];
+
+/// Schema version of this kaguya, the version of the last migration
+pub const SCHEMA_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// Outcome of [`DbManagerMigrationExt::migrate`]
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    /// Schema version before migrating, `None` for a new database
+    pub from: Option<u32>,
+    /// Migrations applied, or that would be applied in a dry run
+    pub applied: Vec<&'static Migration>,
+    /// Copy of the database made before migrating
+    pub backup_path: Option<PathBuf>,
+}
+
+pub trait DbManagerMigrationExt {
+    fn schema_version(&self) -> Result<Option<u32>, KaguyaError>;
+
+    fn pending_migrations(&self) -> Result<Vec<&'static Migration>, KaguyaError>;
+
+    fn migrate(&mut self, dry_run: bool) -> Result<MigrationReport, KaguyaError>;
+}
+
+impl DbManagerMigrationExt for DbManager {
+    // Schema version recorded in the database, `None` if it has no schema yet
+    fn schema_version(&self) -> Result<Option<u32>, KaguyaError> {
+        let has_meta: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'meta')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_meta {
+            return Ok(None);
+        }
+
+        let version = self.get_meta_value(KEY_SCHEMA_VERSION)?;
+        version
+            .parse()
+            .map(Some)
+            .map_err(|_| KaguyaError::InvalidInput("Invalid database schema version".to_string()))
+    }
+
+    // Migrations newer than the schema of the database, refusing a database from a newer kaguya
+    fn pending_migrations(&self) -> Result<Vec<&'static Migration>, KaguyaError> {
+        let current = self.schema_version()?;
+        if let Some(version) = current
+            && version > SCHEMA_VERSION
+        {
+            return Err(KaguyaError::SchemaTooNew(version, SCHEMA_VERSION));
+        }
+
+        Ok(MIGRATIONS
+            .iter()
+            .filter(|m| current.is_none_or(|version| m.version > version))
+            .collect())
+    }
+
+    // Apply every pending migration, copying an existing database first.
+    // With `dry_run`, only report what would be applied.
+    fn migrate(&mut self, dry_run: bool) -> Result<MigrationReport, KaguyaError> {
+        let from = self.schema_version()?;
+        let pending = self.pending_migrations()?;
+        let mut report = MigrationReport {
+            from,
+            applied: pending,
+            backup_path: None,
+        };
+        if dry_run || report.applied.is_empty() {
+            return Ok(report);
+        }
+
+        if let Some(version) = from {
+            report.backup_path = Some(self.backup_database(version)?);
+        }
+
+        // Tables are created again by some migrations, which must not cascade,
+        // so foreign keys are only checked once each migration is done
+        self.conn.pragma_update(None, "foreign_keys", false)?;
+        let result = report
+            .applied
+            .iter()
+            .try_for_each(|migration| self.apply_migration(migration));
+        self.conn.pragma_update(None, "foreign_keys", true)?;
+        result?;
+
+        Ok(report)
+    }
+}
+
+impl DbManager {
+    // Apply a single migration along with its schema version, in a transaction
+    fn apply_migration(&mut self, migration: &Migration) -> Result<(), KaguyaError> {
+        let tx = self.conn.transaction()?;
+        match migration.step {
+            MigrationStep::Sql(sql) => tx.execute_batch(sql)?,
+            MigrationStep::Code(step) => step(&tx)?,
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+            (KEY_SCHEMA_VERSION, migration.version.to_string()),
+        )?;
+
+        let violation: Option<String> = tx
+            .query_row("PRAGMA foreign_key_check", [], |row| row.get(0))
+            .optional()?;
+        if let Some(table) = violation {
+            return Err(KaguyaError::InvalidInput(format!(
+                "Migration V{}__{} left rows of '{}' without their parent",
+                migration.version, migration.name, table
+            )));
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Copy the database to '<db>.v<version>.bak' next to it, replacing an older copy
+    fn backup_database(&self, version: u32) -> Result<PathBuf, KaguyaError> {
+        let db_path = self
+            .conn
+            .path()
+            .filter(|path| !path.is_empty())
+            .ok_or_else(|| {
+                KaguyaError::InvalidInput("Can't back up an in-memory database".to_string())
+            })?;
+        let backup_path = PathBuf::from(format!("{}.v{}.bak", db_path, version));
+        if backup_path.exists() {
+            remove_file(&backup_path)?;
+        }
+
+        // A consistent copy, even while the database is in use
+        self.conn.execute(
+            "VACUUM INTO ?1",
+            [backup_path.to_string_lossy().to_string()],
+        )?;
+        Ok(backup_path)
+    }
+}
+
+// Migration 9: delete orphan rows, reporting how many were found
+fn remove_orphans(tx: &Transaction) -> Result<(), KaguyaError> {
+    let mut removed = 0;
+    for sql in ORPHAN_QUERIES {
+        removed += tx.execute(sql, [])?;
+    }
+    if removed > 0 {
+        println!("Removed {} orphan record(s) from the database.", removed);
+    }
+    Ok(())
+}
@@ -2,14 +2,18 @@
 
 pub mod backup;
 pub mod connection;
+pub mod file_index;
 pub mod game;
 pub mod game_path;
 pub mod meta;
+pub mod migration;
 pub mod sync;
 
 pub use backup::DbManagerBackupExt;
 pub use connection::DbManager;
+pub use file_index::DbManagerFileIndexExt;
 pub use game::DbManagerGameExt;
 pub use game_path::DbManagerGamePathExt;
 pub use meta::DbManagerMetaExt;
+pub use migration::DbManagerMigrationExt;
 pub use sync::DbManagerSyncExt;
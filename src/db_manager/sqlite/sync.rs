@@ -3,14 +3,15 @@
 //! This module defines the [`DbManagerSyncExt`] trait, which provides the main
 //! [`sync`](DbManagerSyncExt::sync) method. This method acts as an orchestrator,
 //! checking file hashes and then calling methods from other modules to upsert
-//! games and prune obsolete records.
+//! games, mark the removed ones and prune obsolete paths.
 
 use super::{DbManager, DbManagerGameExt, DbManagerGamePathExt, DbManagerMetaExt};
 use crate::{
-    db_manager::toml::read_vault_config,
+    db_manager::toml::{read_vault_config, vault_config_files},
     fs_utils::hash::calculate_entry_checksum,
     models::{KEY_VAULT_CONFIG_HASH, KaguyaError},
 };
+use sha2::{Digest, Sha256};
 use std::path::Path;
 
 pub trait DbManagerSyncExt {
@@ -19,28 +20,41 @@ pub trait DbManagerSyncExt {
         vault_config_path: &impl AsRef<Path>,
         force: bool,
     ) -> Result<(), KaguyaError>;
+
+    fn needs_sync(&self, vault_config_path: &impl AsRef<Path>) -> Result<bool, KaguyaError>;
 }
 
 impl DbManagerSyncExt for DbManager {
-    // Sync database if vault config have been changed
+    // Sync database if any file of the vault config has been changed
     // Do nothing if vault config doesn't exist
     fn sync(
         &mut self,
         vault_config_path: &impl AsRef<Path>,
         force: bool,
     ) -> Result<(), KaguyaError> {
-        let file_hash = calculate_entry_checksum(vault_config_path).ok();
+        if !vault_config_path.as_ref().is_file() {
+            return Ok(());
+        }
+        // A vault config that can't be read, e.g. a missing include, is an error
+        let file_hash = vault_config_checksum(vault_config_path)?;
         let db_hash_record = self.get_meta_value(KEY_VAULT_CONFIG_HASH).ok();
 
-        if (force && file_hash.is_some()) || (!force && file_hash != db_hash_record) {
+        if force || db_hash_record.as_ref() != Some(&file_hash) {
             println!("Vault config has been changed, syncing database...");
-            self.perform_sync(
-                vault_config_path,
-                file_hash.expect("file_hash should be exist."),
-            )?;
+            self.perform_sync(vault_config_path, file_hash)?;
         }
         Ok(())
     }
+
+    // Whether 'sync' would change the database, without changing it
+    fn needs_sync(&self, vault_config_path: &impl AsRef<Path>) -> Result<bool, KaguyaError> {
+        if !vault_config_path.as_ref().is_file() {
+            return Ok(false);
+        }
+        let file_hash = vault_config_checksum(vault_config_path)?;
+        let db_hash_record = self.get_meta_value(KEY_VAULT_CONFIG_HASH).ok();
+        Ok(db_hash_record != Some(file_hash))
+    }
 }
 
 impl DbManager {
@@ -53,7 +67,7 @@ impl DbManager {
         let vault_config_file = read_vault_config(vault_config_path)?;
 
         self.upsert_games_from_config(&vault_config_file)?;
-        self.prune_obsolete_games(&vault_config_file)?;
+        self.mark_removed_games(&vault_config_file)?;
         self.prune_obsolete_paths(&vault_config_file)?;
 
         self.update_meta_value(KEY_VAULT_CONFIG_HASH, &new_hash)?;
@@ -62,3 +76,21 @@ impl DbManager {
         Ok(())
     }
 }
+
+// Checksum of every file of the vault config, so a change to any of them is synced.
+// A vault config in a single file keeps the checksum of that file.
+fn vault_config_checksum(vault_config_path: &impl AsRef<Path>) -> Result<String, KaguyaError> {
+    let files = vault_config_files(vault_config_path)?;
+    if let [file] = files.as_slice() {
+        return calculate_entry_checksum(file);
+    }
+
+    let mut hasher = Sha256::new();
+    for file in &files {
+        hasher.update(file.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(calculate_entry_checksum(file)?.as_bytes());
+        hasher.update([0]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
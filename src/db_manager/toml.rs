@@ -1,84 +1,217 @@
 //! Serializing and deserializing toml config
+//!
+//! The vault config is edited as a document: only the table of the game being changed is
+//! touched, comments, ordering and formatting of the rest of the file are kept.
+//!
+//! Games may also be kept out of `vault.toml`: one game per file in `games.d/` next to it,
+//! and in the files and directories listed by its `include`. Files with '[[games]]' tables
+//! are read like `vault.toml`, files in a directory hold the keys of a single game.
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, de::DeserializeOwned};
+use toml_edit::{Array, ArrayOfTables, DocumentMut, Item, Table, Value, value};
 
 use crate::{
-    models::{AddGameRequest, GameConfig, KaguyaError, VaultConfig},
-    utils::path::{expand_path, find_game_mut, shrink_path, transform_paths},
+    models::{AddGameRequest, GAMES_DIR, GameConfig, KaguyaError, VaultConfig},
+    utils::path::{expand_path, is_valid_game_id, shrink_path, transform_paths},
 };
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    fs::{File, copy, read_dir, remove_file, rename},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// A file of the vault config holding games
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigFile {
+    /// `vault.toml`, or an included file, with '[[games]]' tables
+    Tables(PathBuf),
+    /// A file in `games.d/` or an included directory, with a single game
+    Game(PathBuf),
+}
+
+impl ConfigFile {
+    pub fn path(&self) -> &Path {
+        match self {
+            ConfigFile::Tables(path) | ConfigFile::Game(path) => path,
+        }
+    }
+}
+
+/// Games of each file of the vault config, as written
+pub type FileGames = Vec<(ConfigFile, Vec<GameConfig>)>;
+
+/// Text of the vault config given to `kaguya config edit`
+#[derive(Debug)]
+pub struct EditableConfig {
+    /// File replaced once the edit is valid
+    pub path: PathBuf,
+    /// Game edited alone out of a file with '[[games]]' tables
+    pub game_id: Option<String>,
+    pub content: String,
+    /// Text of the file when the edit began, `None` if it didn't exist
+    pub file_content: Option<String>,
+}
+
+// An included file with '[[games]]' tables
+#[derive(Debug, Default, Deserialize)]
+struct IncludedConfig {
+    #[serde(default)]
+    games: Vec<GameConfig>,
+}
 
 /// Adds a new game [`GameConfig`] to the vault config file
+///
+/// An existing game is updated in the file defining it. A new game gets its own file in
+/// `games.d/` if the directory exists, otherwise it is added to `vault.toml`.
 pub fn add_or_update_game_to_file(
     vault_config_path: &impl AsRef<Path>,
     request: AddGameRequest,
 ) -> Result<(), KaguyaError> {
-    // Deserialize and read string from games.toml
-    let mut vault_config_contents: VaultConfig = read_vault_config(vault_config_path)?;
+    let vault_config_path = vault_config_path.as_ref();
 
     // Find the game if it exists
-    if let Some(existing_game) = find_game_mut(&mut vault_config_contents.games, &request.id) {
-        // Game exists, update it.
-        println!("Updating existing game '{}' ...", &request.id);
-        apply_update(existing_game, &request)?;
-    } else {
-        // Game not exists, add a new one.
-        println!("Adding game '{}'...", &request.id);
+    match find_game_file(vault_config_path, &request.id)? {
+        Some(ConfigFile::Tables(path)) => {
+            // Game exists, update it.
+            println!("Updating existing game '{}' ...", &request.id);
+            let mut document = read_document(&path)?;
+            let game = games_tables(&mut document)?
+                .iter_mut()
+                .find(|game| is_game(game, &request.id))
+                .expect("Game should be in the file defining it.");
+            apply_update(game, &request)?;
+            save_tables_document(vault_config_path, &path, &document)?;
+        }
 
-        let new_game = GameConfig::from(&request);
-        vault_config_contents.games.push(new_game);
+        Some(ConfigFile::Game(path)) => {
+            println!("Updating existing game '{}' ...", &request.id);
+            let mut document = read_document(&path)?;
+            apply_update(document.as_table_mut(), &request)?;
+            save_document::<GameConfig>(&path, &document)?;
+        }
+
+        None => {
+            // Game not exists, add a new one.
+            println!("Adding game '{}'...", &request.id);
+
+            let new_game = game_table(GameConfig::from(&request))?;
+            let games_dir = games_dir(vault_config_path);
+            if games_dir.is_dir() {
+                let mut document = DocumentMut::new();
+                *document.as_table_mut() = new_game;
+                document.decor_mut().clear();
+                save_document::<GameConfig>(&game_file_path(&games_dir, &request.id)?, &document)?;
+            } else {
+                let mut document = read_vault_document(vault_config_path)?;
+                games_tables(&mut document)?.push(new_game);
+                save_document::<VaultConfig>(vault_config_path, &document)?;
+            }
+        }
     }
     println!("Game added or updated successfully!");
 
-    save_vault_config(vault_config_path, &mut vault_config_contents)
+    Ok(())
 }
 
-// Update existing [`GameConfig`] with [`AddGameRequest`]
+// Update the table of an existing game with [`AddGameRequest`]
 // Paths will be merged.
 // See also `GameConfig`
-fn apply_update(exist: &mut GameConfig, request: &AddGameRequest) -> Result<(), KaguyaError> {
-    // Merge paths: combine old and new, remove duplicates
+fn apply_update(exist: &mut Table, request: &AddGameRequest) -> Result<(), KaguyaError> {
+    // Merge paths: keep old ones as written, append new ones
     if let Some(paths) = &request.paths {
-        let mut combined_paths = exist.paths.clone();
+        let combined_paths = exist
+            .entry("paths")
+            .or_insert(value(Array::new()))
+            .as_array_mut()
+            .ok_or_else(|| invalid_game_key(request, "paths"))?;
+        let known_paths = combined_paths
+            .iter()
+            .filter_map(Value::as_str)
+            .map(expand_path)
+            .collect::<Result<Vec<_>, KaguyaError>>()?;
+        // One path per line if the array is written so
+        let line_prefix = combined_paths
+            .iter()
+            .last()
+            .and_then(|path| path.decor().prefix())
+            .and_then(|prefix| prefix.as_str())
+            .filter(|prefix| prefix.contains('\n'))
+            .map(str::to_string);
         for path in paths {
-            if !combined_paths.contains(path) {
-                combined_paths.push(path.to_path_buf());
+            if !known_paths.contains(path) {
+                let mut new_path = Value::from(shrink_path(path)?.to_string_lossy().as_ref());
+                if let Some(prefix) = &line_prefix {
+                    new_path.decor_mut().set_prefix(prefix);
+                }
+                combined_paths.push_formatted(new_path);
             }
         }
-        exist.paths = combined_paths;
     }
 
     if let Some(name) = &request.name {
-        exist.name = name.to_string();
+        set_value(exist, "name", name);
     }
 
-    if request.comment.is_some() {
-        exist.comment = request.comment.clone().map(|c| c.to_string());
+    if let Some(comment) = &request.comment {
+        set_value(exist, "comment", comment);
     }
 
     Ok(())
 }
 
 /// Remove a game configuration in vault config, backups remain.
+///
+/// A file holding only this game is renamed to '<file>.bak'.
 pub fn rm_game_in_vault_config(path: &impl AsRef<Path>, id: &str) -> Result<(), KaguyaError> {
-    // Deserialize and read string from config.toml
-    let mut vault_config_contents: VaultConfig = read_vault_config(path)?;
+    let vault_config_path = path.as_ref();
 
-    let original_len = vault_config_contents.games.len();
-    vault_config_contents.games.retain(|g| *g.id != *id);
+    match find_game_file(vault_config_path, id)? {
+        Some(ConfigFile::Tables(path)) => {
+            let mut document = read_document(&path)?;
+            let games = games_tables(&mut document)?;
+            let index = games
+                .iter()
+                .position(|game| is_game(game, id))
+                .expect("Game should be in the file defining it.");
+            games.remove(index);
+            save_tables_document(vault_config_path, &path, &document)
+        }
 
-    if vault_config_contents.games.len() >= original_len {
-        return Err(KaguyaError::GameNotFound(id.to_string()));
-    }
+        Some(ConfigFile::Game(path)) => Ok(rename(&path, path.with_extension("toml.bak"))?),
 
-    save_vault_config(path, &mut vault_config_contents)
+        None => Err(KaguyaError::GameNotFound(id.to_string())),
+    }
 }
 
+/// Read the vault config, along with the games of every included file.
+/// Fails if a game ID is defined more than once.
 pub fn read_vault_config(path: &impl AsRef<Path>) -> Result<VaultConfig, KaguyaError> {
-    let mut vault_config = read_toml_file::<VaultConfig>(path)?;
-    vault_config.games = vault_config
-        .games
+    load_vault_config(path.as_ref(), None)
+}
+
+// Read the vault config, reading `replaced` from the given text instead of its file
+fn load_vault_config(
+    path: &Path,
+    replaced: Option<(&Path, &str)>,
+) -> Result<VaultConfig, KaguyaError> {
+    let (mut vault_config, files) = load_config_files(path, replaced)?;
+    if let Some((id, first, other)) = duplicate_game_ids(&files).into_iter().next() {
+        return Err(KaguyaError::InvalidInput(if first == other {
+            format!("Game ID '{}' is defined twice in '{}'", id, first.display())
+        } else {
+            format!(
+                "Game ID '{}' is defined in both '{}' and '{}'",
+                id,
+                first.display(),
+                other.display()
+            )
+        }));
+    }
+
+    vault_config.games = files
         .into_iter()
+        .flat_map(|(_, games)| games)
         .map(|mut game| {
             game.paths = transform_paths(game.paths, expand_path)?;
             Ok(game)
@@ -88,6 +221,133 @@ pub fn read_vault_config(path: &impl AsRef<Path>) -> Result<VaultConfig, KaguyaE
     Ok(vault_config)
 }
 
+/// Every file of the vault config, `vault.toml` first
+pub fn vault_config_files(path: &impl AsRef<Path>) -> Result<Vec<PathBuf>, KaguyaError> {
+    let path = path.as_ref();
+    if !path.is_file() {
+        return Err(KaguyaError::PathNotFound(
+            path.to_string_lossy().to_string(),
+        ));
+    }
+
+    let vault_config = read_toml_file::<VaultConfig>(&path)?;
+    Ok(config_files(path, &vault_config.include)?
+        .into_iter()
+        .map(|file| file.path().to_path_buf())
+        .collect())
+}
+
+/// The vault config to edit, or the single game `id` when given.
+/// A game sharing its file with others is edited alone, as the keys of its table.
+pub fn editable_config(
+    vault_config_path: &impl AsRef<Path>,
+    id: Option<&str>,
+) -> Result<EditableConfig, KaguyaError> {
+    let vault_config_path = vault_config_path.as_ref();
+    let Some(id) = id else {
+        // Read as text, a broken config can be edited too
+        let file_content = read_file_content(vault_config_path)?;
+        let content = match &file_content {
+            Some(content) => content.clone(),
+            None => toml::to_string_pretty(&VaultConfig::default())?,
+        };
+        return Ok(EditableConfig {
+            path: vault_config_path.to_path_buf(),
+            game_id: None,
+            content,
+            file_content,
+        });
+    };
+
+    match find_game_file(vault_config_path, id)? {
+        Some(ConfigFile::Tables(path)) => {
+            let file_content = std::fs::read_to_string(&path)?;
+            let mut document: DocumentMut = file_content.parse()?;
+            let game = games_tables(&mut document)?
+                .iter()
+                .find(|game| is_game(game, id))
+                .expect("Game should be in the file defining it.");
+
+            let mut game_document = DocumentMut::new();
+            *game_document.as_table_mut() = game.clone();
+            game_document.as_table_mut().decor_mut().clear();
+            Ok(EditableConfig {
+                path,
+                game_id: Some(id.to_string()),
+                content: game_document.to_string(),
+                file_content: Some(file_content),
+            })
+        }
+
+        Some(ConfigFile::Game(path)) => {
+            let file_content = std::fs::read_to_string(&path)?;
+            Ok(EditableConfig {
+                path,
+                game_id: None,
+                content: file_content.clone(),
+                file_content: Some(file_content),
+            })
+        }
+
+        None => Err(KaguyaError::GameNotFound(id.to_string())),
+    }
+}
+
+/// Check the vault config as it would be with the edit, like it is read by every command.
+/// Returns the new text of the edited file, and the vault config it makes.
+pub fn check_edited_config(
+    vault_config_path: &impl AsRef<Path>,
+    editable: &EditableConfig,
+    edited: &str,
+) -> Result<(String, VaultConfig), KaguyaError> {
+    let content = match (&editable.game_id, &editable.file_content) {
+        // Put the edited table back in place of the game, keeping the rest of its file
+        (Some(id), Some(file_content)) => {
+            let edited_document: DocumentMut = edited.parse()?;
+            let mut document: DocumentMut = file_content.parse()?;
+            let game = games_tables(&mut document)?
+                .iter_mut()
+                .find(|game| is_game(game, id))
+                .ok_or_else(|| KaguyaError::GameNotFound(id.clone()))?;
+
+            let decor = game.decor().clone();
+            let position = game.position();
+            *game = edited_document.as_table().clone();
+            *game.decor_mut() = decor;
+            if let Some(position) = position {
+                game.set_position(position);
+            }
+            document.to_string()
+        }
+        _ => edited.to_string(),
+    };
+
+    let vault_config =
+        load_vault_config(vault_config_path.as_ref(), Some((&editable.path, &content)))?;
+    Ok((content, vault_config))
+}
+
+/// Replace the edited file with its checked new text, unless it was changed meanwhile.
+/// The previous file is kept as '<file>.bak'.
+pub fn save_edited_config(editable: &EditableConfig, content: &str) -> Result<(), KaguyaError> {
+    if read_file_content(&editable.path)? != editable.file_content {
+        return Err(KaguyaError::InvalidInput(format!(
+            "'{}' was changed while it was being edited",
+            editable.path.display()
+        )));
+    }
+    write_config_file(&editable.path, content)
+}
+
+// Text of a file, `None` if it doesn't exist
+fn read_file_content(path: &Path) -> Result<Option<String>, KaguyaError> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
 // Read .toml file, deserialize from TOML to string
 pub fn read_toml_file<T>(path: &impl AsRef<Path>) -> Result<T, KaguyaError>
 where
@@ -102,26 +362,305 @@ where
     }
 }
 
-fn save_vault_config(
+/// Read the vault config and the games of each of its files, as written.
+/// The games of `vault.toml` are moved to the first file.
+pub fn read_config_files(path: &impl AsRef<Path>) -> Result<(VaultConfig, FileGames), KaguyaError> {
+    load_config_files(path.as_ref(), None)
+}
+
+fn load_config_files(
+    path: &Path,
+    replaced: Option<(&Path, &str)>,
+) -> Result<(VaultConfig, FileGames), KaguyaError> {
+    let read = |file: &Path| -> Result<String, KaguyaError> {
+        match replaced {
+            Some((replaced_path, content)) if replaced_path == file => Ok(content.to_string()),
+            _ => Ok(std::fs::read_to_string(file)?),
+        }
+    };
+
+    let mut vault_config = if path.exists() || replaced.is_some_and(|(file, _)| file == path) {
+        toml::from_str::<VaultConfig>(&read(path)?)?
+    } else {
+        VaultConfig::default()
+    };
+    let mut files = vec![(
+        ConfigFile::Tables(path.to_path_buf()),
+        std::mem::take(&mut vault_config.games),
+    )];
+
+    for file in config_files(path, &vault_config.include)?
+        .into_iter()
+        .skip(1)
+    {
+        let content = read(file.path())?;
+        let games = match &file {
+            ConfigFile::Tables(_) => toml::from_str::<IncludedConfig>(&content)?.games,
+            ConfigFile::Game(_) => vec![toml::from_str(&content)?],
+        };
+        files.push((file, games));
+    }
+
+    Ok((vault_config, files))
+}
+
+/// Like [`read_config_files`], reading every file of the vault config that can be read.
+/// The others, e.g. a missing include or a file that isn't valid TOML, are left out
+/// and returned with their errors.
+pub fn read_readable_config_files(
     path: &impl AsRef<Path>,
-    vault_config: &mut VaultConfig,
-) -> Result<(), KaguyaError> {
-    vault_config.games = vault_config
-        .games
-        .clone()
+) -> (FileGames, Vec<(PathBuf, KaguyaError)>) {
+    let path = path.as_ref();
+    let mut failures = Vec::new();
+    let mut vault_config = match read_toml_file::<VaultConfig>(&path) {
+        Ok(vault_config) => vault_config,
+        Err(e) => return (Vec::new(), vec![(path.to_path_buf(), e)]),
+    };
+    let mut files = vec![(
+        ConfigFile::Tables(path.to_path_buf()),
+        std::mem::take(&mut vault_config.games),
+    )];
+
+    for file in config_file_entries(path, &vault_config.include)
         .into_iter()
-        .map(|mut game| {
-            game.paths = transform_paths(game.paths, shrink_path)?;
-            Ok(game)
-        })
-        .collect::<Result<Vec<_>, KaguyaError>>()?;
+        .skip(1)
+    {
+        let file = match file {
+            Ok(file) => file,
+            Err(e) => {
+                failures.push((path.to_path_buf(), e));
+                continue;
+            }
+        };
+        let games = std::fs::read_to_string(file.path())
+            .map_err(KaguyaError::from)
+            .and_then(|content| {
+                Ok(match &file {
+                    ConfigFile::Tables(_) => toml::from_str::<IncludedConfig>(&content)?.games,
+                    ConfigFile::Game(_) => vec![toml::from_str(&content)?],
+                })
+            });
+        match games {
+            Ok(games) => files.push((file, games)),
+            Err(e) => failures.push((file.path().to_path_buf(), e)),
+        }
+    }
+
+    (files, failures)
+}
+
+/// Game IDs defined more than once, with the files of their first and next definitions
+pub fn duplicate_game_ids(files: &FileGames) -> Vec<(String, PathBuf, PathBuf)> {
+    let mut defined_in: HashMap<&str, &Path> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for (file, games) in files {
+        for game in games {
+            if let Some(other) = defined_in.insert(&game.id, file.path()) {
+                duplicates.push((
+                    game.id.clone(),
+                    other.to_path_buf(),
+                    file.path().to_path_buf(),
+                ));
+            }
+        }
+    }
+    duplicates
+}
+
+// Files of the vault config at `path`: itself, 'games.d/', then its includes in order
+fn config_files(path: &Path, include: &[PathBuf]) -> Result<Vec<ConfigFile>, KaguyaError> {
+    config_file_entries(path, include).into_iter().collect()
+}
+
+// Files of the vault config like `config_files`, a directory that can't be listed
+// or an include that doesn't exist is an error in place of its files
+fn config_file_entries(path: &Path, include: &[PathBuf]) -> Vec<Result<ConfigFile, KaguyaError>> {
+    let mut files = vec![Ok(ConfigFile::Tables(path.to_path_buf()))];
+    let extend_with_dir = |files: &mut Vec<_>, dir: &Path| match game_files(dir) {
+        Ok(game_files) => files.extend(game_files.into_iter().map(Ok)),
+        Err(e) => files.push(Err(e)),
+    };
+
+    let games_dir = games_dir(path);
+    if games_dir.is_dir() {
+        extend_with_dir(&mut files, &games_dir);
+    }
+
+    let config_dir = path.parent().unwrap_or(Path::new(""));
+    for included in include {
+        let included = match expand_path(included) {
+            Ok(included) => config_dir.join(included),
+            Err(e) => {
+                files.push(Err(e));
+                continue;
+            }
+        };
+        if included.is_dir() {
+            extend_with_dir(&mut files, &included);
+        } else if included.is_file() {
+            files.push(Ok(ConfigFile::Tables(included)));
+        } else {
+            files.push(Err(KaguyaError::PathNotFound(
+                included.to_string_lossy().to_string(),
+            )));
+        }
+    }
+
+    files
+}
+
+// '*.toml' files of a directory with one game per file, sorted by name
+fn game_files(dir: &Path) -> Result<Vec<ConfigFile>, KaguyaError> {
+    let mut paths = Vec::new();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "toml") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    Ok(paths.into_iter().map(ConfigFile::Game).collect())
+}
+
+// The file defining a game, `None` if no file does
+fn find_game_file(vault_config_path: &Path, id: &str) -> Result<Option<ConfigFile>, KaguyaError> {
+    let (_, files) = read_config_files(&vault_config_path)?;
+    Ok(files
+        .into_iter()
+        .find(|(_, games)| games.iter().any(|game| game.id == id))
+        .map(|(file, _)| file))
+}
 
-    save_to_file(path, vault_config)
+fn games_dir(vault_config_path: &Path) -> PathBuf {
+    vault_config_path
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(GAMES_DIR)
 }
 
-// Serialize config to TOML, and save it to toml file.
-fn save_to_file(path: &impl AsRef<Path>, contents: &impl Serialize) -> Result<(), KaguyaError> {
-    let toml_string = toml::to_string_pretty(contents)?;
-    std::fs::write(path, toml_string)?;
+// File of a new game in 'games.d/', named after its ID
+fn game_file_path(games_dir: &Path, id: &str) -> Result<PathBuf, KaguyaError> {
+    if !is_valid_game_id(id) {
+        return Err(KaguyaError::InvalidInput(format!(
+            "Game ID '{}' can't be used as a file name in '{}'",
+            id,
+            games_dir.display()
+        )));
+    }
+    Ok(games_dir.join(format!("{}.toml", id)))
+}
+
+fn read_document(path: &Path) -> Result<DocumentMut, KaguyaError> {
+    Ok(std::fs::read_to_string(path)?.parse()?)
+}
+
+// Read the vault config as an editable document, a default config if there is none yet
+fn read_vault_document(path: &Path) -> Result<DocumentMut, KaguyaError> {
+    let content = if path.exists() {
+        std::fs::read_to_string(path)?
+    } else {
+        toml::to_string_pretty(&VaultConfig::default())?
+    };
+    Ok(content.parse()?)
+}
+
+// The '[[games]]' tables of the vault config.
+// Games written as an inline array, like the empty one of a new config, are turned into tables.
+fn games_tables(document: &mut DocumentMut) -> Result<&mut ArrayOfTables, KaguyaError> {
+    let invalid = || {
+        KaguyaError::InvalidInput(
+            "'games' in the vault config must be an array of game tables".to_string(),
+        )
+    };
+
+    if let Some(games) = document.remove("games") {
+        let tables = match games.into_array_of_tables() {
+            Ok(tables) => tables,
+            Err(item) if item.as_array().is_some_and(Array::is_empty) => ArrayOfTables::new(),
+            Err(_) => return Err(invalid()),
+        };
+        // Tables of an inline array are written after the other tables
+        document.insert("games", Item::ArrayOfTables(tables));
+    }
+    document
+        .entry("games")
+        .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or_else(invalid)
+}
+
+fn is_game(game: &Table, id: &str) -> bool {
+    game.get("id").and_then(Item::as_str) == Some(id)
+}
+
+// Table of a new game, as the whole config would be serialized
+fn game_table(mut game: GameConfig) -> Result<Table, KaguyaError> {
+    game.paths = transform_paths(game.paths, shrink_path)?;
+    let document: DocumentMut = toml::to_string_pretty(&game)?.parse()?;
+    let mut table = document.as_table().clone();
+    // Separated from the previous table by a blank line
+    table.decor_mut().set_prefix("\n");
+    Ok(table)
+}
+
+// Replace a value, keeping the whitespace and comment around the old one
+fn set_value(table: &mut Table, key: &str, new_value: &str) {
+    let mut new_value = Value::from(new_value);
+    if let Some(old_value) = table.get(key).and_then(Item::as_value) {
+        *new_value.decor_mut() = old_value.decor().clone();
+    }
+    table.insert(key, Item::Value(new_value));
+}
+
+fn invalid_game_key(request: &AddGameRequest, key: &str) -> KaguyaError {
+    KaguyaError::InvalidInput(format!(
+        "Invalid '{}' of game '{}' in the vault config",
+        key, request.id
+    ))
+}
+
+// Save a file with '[[games]]' tables, `vault.toml` or an included one
+fn save_tables_document(
+    vault_config_path: &Path,
+    path: &Path,
+    document: &DocumentMut,
+) -> Result<(), KaguyaError> {
+    if path == vault_config_path {
+        save_document::<VaultConfig>(path, document)
+    } else {
+        save_document::<IncludedConfig>(path, document)
+    }
+}
+
+// Save a file of the vault config, once it reads back as a `T`
+fn save_document<T: DeserializeOwned>(
+    path: &Path,
+    document: &DocumentMut,
+) -> Result<(), KaguyaError> {
+    let content = document.to_string();
+    // Never save a config that can't be read back
+    toml::from_str::<T>(&content)?;
+
+    write_config_file(path, &content)
+}
+
+// Write a file of the vault config next to the old one, then rename it into place, so it is
+// never left half written. The previous file is kept as '<file>.bak'.
+fn write_config_file(path: &Path, content: &str) -> Result<(), KaguyaError> {
+    let temp_path = path.with_extension("toml.tmp");
+    let written = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(content.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(e) = written {
+        remove_file(&temp_path).ok();
+        return Err(e.into());
+    }
+
+    if path.exists() {
+        copy(path, path.with_extension("toml.bak"))?;
+    }
+    rename(&temp_path, path)?;
     Ok(())
 }
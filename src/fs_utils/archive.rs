@@ -1,28 +1,65 @@
 //! Compress and decompress games saves and configuration backups
 
-use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use scopeguard::defer;
 use std::{
-    fs::{File, create_dir_all, metadata},
+    fs::{File, create_dir_all, metadata, read_dir, remove_file},
+    io::{self, BufReader, BufWriter, Seek, Write},
+    os::unix::fs::PermissionsExt,
     path::Path,
 };
-use tar::Archive;
+use tar::{Archive, HeaderMode};
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
 
-use crate::{models::KaguyaError, utils::path::get_file_name};
+use crate::{
+    fs_utils::{
+        codec::{ArchiveCodec, CompressionSettings, Decoder},
+        compressibility::is_incompressible,
+        crypto::{DataKey, DecryptingReader, EncryptingWriter, is_encrypted},
+        hash::{HashingReader, HashingWriter, calculate_entry_checksum},
+        restore::generate_unique_temp_name,
+    },
+    models::KaguyaError,
+    utils::path::get_file_name,
+};
+
+/// Size and checksum of an archive file, collected while writing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveInfo {
+    pub size_bytes: i64,
+    pub checksum: String,
+}
 
-/// Compress source file or directory to target directory in tar.gz format
+/// Compress source file or directory to target file in the tar format of `settings`
 /// The archive file preserves the top-level directory if dst is a directory.
 ///
+/// Archives are reproducible: entries are sorted, and headers are normalized
+/// (fixed mtime, uid/gid 0, permissions reduced to 644/755), so identical content
+/// yields an identical archive checksum. Real metadata is kept by
+/// [`collect_entry_metadata`](crate::fs_utils::metadata::collect_entry_metadata).
+///
+/// If `key` is given, the compressed stream is encrypted with it, see [`crate::fs_utils::crypto`].
+///
+/// The compressed stream is hashed and counted as it is written,
+/// so the archive doesn't need to be read again for its metadata.
+/// Zip archives are written as a stream too, with sizes and CRCs in data descriptors.
+///
 /// Usage:
-/// ```
-/// let src: PathBuf = "~/games/game-a/saves"
-/// let dst: PathBuf = "~/.local/share/kaguya/vault/backups/2025-12-25_10-00-00/saves.tar.gz"
+/// ```no_run
+/// # use kaguya::{fs_utils::{archive::compress_archive, codec::CompressionSettings}, utils::path::expand_path};
+/// # fn main() -> Result<(), kaguya::models::KaguyaError> {
+/// let src = expand_path("~/games/game-a/saves")?;
+/// let dst = expand_path("~/.local/share/kaguya/vault/backups/2025-12-25_10-00-00/saves.tar.zst")?;
 ///
-/// compress_to_tar_gz(src, dst);
+/// let info = compress_archive(&src, &dst, &CompressionSettings::new("tar.zst", Some(19))?, None)?;
+/// # Ok(())
+/// # }
 /// ```
-pub fn compress_to_tar_gz(
+pub fn compress_archive(
     src: &impl AsRef<Path>,
     dst: &impl AsRef<Path>,
-) -> Result<(), KaguyaError> {
+    settings: &CompressionSettings,
+    key: Option<&DataKey>,
+) -> Result<ArchiveInfo, KaguyaError> {
     let src = src.as_ref();
     let dst = dst.as_ref();
 
@@ -30,38 +67,76 @@ pub fn compress_to_tar_gz(
         return Err(KaguyaError::PathNotFound(src.to_string_lossy().to_string()));
     }
 
+    if settings.codec == ArchiveCodec::Zip {
+        return compress_to_zip(src, dst, settings, key);
+    }
+
     // Build encoder
-    let tar_gz = File::create(dst)?;
-    let enc = GzEncoder::new(tar_gz, Compression::default());
+    let file = HashingWriter::new(BufWriter::new(File::create(dst)?));
+    let enc = settings.encoder(EncryptingWriter::new(file, key)?)?;
     let mut tar = tar::Builder::new(enc);
+    tar.mode(HeaderMode::Deterministic);
 
     // Create archive
     let src_file_name = get_file_name(src).unwrap_or(".".to_string());
-    if src.is_file() {
-        tar.append_path_with_name(src, src_file_name)?;
-    } else {
-        tar.append_dir_all(src_file_name, src)?;
+    append_to_tar(&mut tar, src, &src_file_name)?;
+
+    let (mut file, size_bytes, checksum) = tar.into_inner()?.finish()?.finish()?.finish();
+    file.flush()?;
+
+    Ok(ArchiveInfo {
+        size_bytes: size_bytes as i64,
+        checksum,
+    })
+}
+
+/// Build the uncompressed tar of a source file or directory in memory, with the same
+/// sorted entries and normalized headers as [`compress_archive`], so identical content
+/// yields identical bytes.
+pub fn tar_bytes(src: &impl AsRef<Path>) -> Result<Vec<u8>, KaguyaError> {
+    let src = src.as_ref();
+    if !src.exists() {
+        return Err(KaguyaError::PathNotFound(src.to_string_lossy().to_string()));
     }
 
-    tar.finish()?;
-    Ok(())
+    let mut tar = tar::Builder::new(Vec::new());
+    tar.mode(HeaderMode::Deterministic);
+
+    let src_file_name = get_file_name(src).unwrap_or(".".to_string());
+    append_to_tar(&mut tar, src, &src_file_name)?;
+
+    Ok(tar.into_inner()?)
 }
 
-/// Decompress a tar.gz file to a target directory.
+/// Decompress an archive file in the given format to a target directory.
 /// Assuming that the archive file always preserves the top-level directory.
 /// In other words, `dst` must be a directory.
 ///
+/// If `expected_checksum` is given, the archive is verified while it is being
+/// decompressed (zip archives are verified before), and [`KaguyaError::ChecksumMismatch`] is returned if it differs.
+/// Callers should decompress into a staging directory, since files are already
+/// unpacked when the mismatch is detected.
+///
+/// Encrypted archives are decrypted with `key`, plain ones are read as they are.
+///
 /// Usage:
-/// ```
-/// let src: PathBuf = "~/.local/share/kaguya/vault/backups/2025-12-25_10-00-00/saves.tar.gz"
-/// let dst: PathBuf = "~/games/game-a"
+/// ```no_run
+/// # use kaguya::{fs_utils::{archive::decompress_archive, codec::ArchiveCodec}, utils::path::expand_path};
+/// # fn main() -> Result<(), kaguya::models::KaguyaError> {
+/// let src = expand_path("~/.local/share/kaguya/vault/backups/2025-12-25_10-00-00/saves.tar.gz")?;
+/// let dst = expand_path("~/games/game-a")?;
 ///
 /// // Will decompress to '~/games/game-a/saves'
-/// decompress_from_tar_gz(src, dst)?;
+/// decompress_archive(&src, &dst, ArchiveCodec::TarGz, None, None)?;
+/// # Ok(())
+/// # }
 /// ```
-pub fn decompress_from_tar_gz(
+pub fn decompress_archive(
     src: &impl AsRef<Path>,
     dst: &impl AsRef<Path>,
+    codec: ArchiveCodec,
+    expected_checksum: Option<&str>,
+    key: Option<&DataKey>,
 ) -> Result<(), KaguyaError> {
     let src = src.as_ref();
     let dst = dst.as_ref();
@@ -74,17 +149,176 @@ pub fn decompress_from_tar_gz(
         create_dir_all(dst)?;
     }
 
+    if codec == ArchiveCodec::Zip {
+        return decompress_from_zip(src, dst, expected_checksum, key);
+    }
+
     // Build decoder
-    let tar_gz = File::open(src)?;
-    let decoder = GzDecoder::new(tar_gz);
+    let file = HashingReader::new(BufReader::new(File::open(src)?));
+    let decoder = Decoder::new(codec, DecryptingReader::new(file, key))?;
     let mut archive = Archive::new(decoder);
 
     archive.unpack(dst)?;
 
+    let checksum = archive.into_inner().into_inner().into_inner().finish()?;
+    if let Some(expected) = expected_checksum
+        && checksum != expected
+    {
+        return Err(KaguyaError::ChecksumMismatch(
+            src.to_string_lossy().to_string(),
+        ));
+    }
+
     Ok(())
 }
 
-/// Calculate file bytes for archive file
-pub fn calculate_file_bytes<P: AsRef<Path>>(path: P) -> Result<i64, KaguyaError> {
-    Ok(metadata(path)?.len() as i64)
+// Recursively add a file or directory to a tar archive under `name`, in sorted order
+fn append_to_tar<W: Write>(
+    tar: &mut tar::Builder<W>,
+    path: &Path,
+    name: &str,
+) -> Result<(), KaguyaError> {
+    if path.is_dir() {
+        tar.append_dir(name, path)?;
+
+        let mut entries = read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let child_name = format!("{}/{}", name, entry.file_name().to_string_lossy());
+            append_to_tar(tar, &entry.path(), &child_name)?;
+        }
+    } else {
+        tar.append_path_with_name(path, name)?;
+    }
+
+    Ok(())
+}
+
+// Compress source file or directory to a zip archive, preserving the top-level directory.
+// With adaptive compression, already compressed files are stored as they are.
+// The zip is written as a stream, so it is hashed, and encrypted with `key`, as it is written
+// like tar archives, and its plain content never touches the vault.
+fn compress_to_zip(
+    src: &Path,
+    dst: &Path,
+    settings: &CompressionSettings,
+    key: Option<&DataKey>,
+) -> Result<ArchiveInfo, KaguyaError> {
+    let file = HashingWriter::new(BufWriter::new(File::create(dst)?));
+    let mut zip = ZipWriter::new_stream(EncryptingWriter::new(file, key)?);
+    write_zip(&mut zip, src, settings)?;
+
+    let (mut file, size_bytes, checksum) = zip.finish()?.into_inner().finish()?.finish();
+    file.flush()?;
+
+    Ok(ArchiveInfo {
+        size_bytes: size_bytes as i64,
+        checksum,
+    })
+}
+
+// Add source file or directory to a zip archive with the options of `settings`
+fn write_zip<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    src: &Path,
+    settings: &CompressionSettings,
+) -> Result<(), KaguyaError> {
+    // Fixed timestamp (1980-01-01) for reproducible archives
+    let options = SimpleFileOptions::default()
+        .last_modified_time(zip::DateTime::default())
+        .compression_method(CompressionMethod::Deflated)
+        .compression_level(Some(settings.effective_level() as i64));
+
+    let src_file_name = get_file_name(src).unwrap_or(".".to_string());
+    append_to_zip(zip, src, &src_file_name, options, settings.adaptive)
+}
+
+// Recursively add a file or directory to a zip archive under `name`
+fn append_to_zip<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    path: &Path,
+    name: &str,
+    options: SimpleFileOptions,
+    adaptive: bool,
+) -> Result<(), KaguyaError> {
+    // Permissions are normalized the same way as tar's deterministic headers
+    let mode = metadata(path)?.permissions().mode();
+    let mut options = options.unix_permissions(if path.is_dir() || mode & 0o100 != 0 {
+        0o755
+    } else {
+        0o644
+    });
+
+    if path.is_dir() {
+        // Not `add_directory`, which flags a data descriptor in stream mode without writing it,
+        // and Info-ZIP rejects the archive for it
+        zip.start_file(
+            format!("{}/", name),
+            options
+                .compression_method(CompressionMethod::Stored)
+                .compression_level(None),
+        )?;
+
+        let mut entries = read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let child_name = format!("{}/{}", name, entry.file_name().to_string_lossy());
+            append_to_zip(zip, &entry.path(), &child_name, options, adaptive)?;
+        }
+    } else {
+        if adaptive && is_incompressible(&path)? {
+            options = options
+                .compression_method(CompressionMethod::Stored)
+                .compression_level(None);
+        }
+        zip.start_file(name, options)?;
+        io::copy(&mut File::open(path)?, zip)?;
+    }
+
+    Ok(())
+}
+
+// Decompress a zip archive to a target directory, verifying its checksum first.
+// An encrypted zip is decrypted into a temporary file in the target directory first,
+// since reading it needs seeking, and removed once it is unpacked.
+fn decompress_from_zip(
+    src: &Path,
+    dst: &Path,
+    expected_checksum: Option<&str>,
+    key: Option<&DataKey>,
+) -> Result<(), KaguyaError> {
+    if let Some(expected) = expected_checksum
+        && calculate_entry_checksum(src)? != expected
+    {
+        return Err(KaguyaError::ChecksumMismatch(
+            src.to_string_lossy().to_string(),
+        ));
+    }
+
+    if is_encrypted(&src)? {
+        let temp_path = dst.join(generate_unique_temp_name(".kaguya-zip", 8));
+        defer! {
+            remove_file(&temp_path).ok();
+        }
+        let mut temp = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        let mut writer = BufWriter::new(&mut temp);
+        io::copy(
+            &mut DecryptingReader::new(BufReader::new(File::open(src)?), key),
+            &mut writer,
+        )?;
+        writer.flush()?;
+        drop(writer);
+
+        temp.rewind()?;
+        ZipArchive::new(BufReader::new(temp))?.extract(dst)?;
+        return Ok(());
+    }
+
+    let mut archive = ZipArchive::new(BufReader::new(File::open(src)?))?;
+    archive.extract(dst)?;
+    Ok(())
 }
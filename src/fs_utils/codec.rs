@@ -0,0 +1,270 @@
+//! Archive codecs: tar with an optional compression layer
+
+use flate2::{Compression, GzBuilder, read::GzDecoder, write::GzEncoder};
+use std::{
+    fmt,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+use xz2::{read::XzDecoder, write::XzEncoder};
+
+use crate::models::KaguyaError;
+
+/// Format of an archive file in the vault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveCodec {
+    /// Uncompressed tar
+    Tar,
+    TarGz,
+    TarZst,
+    TarXz,
+    /// Zip archive, for sharing saves with other platforms
+    Zip,
+}
+
+impl ArchiveCodec {
+    pub const ALL: [ArchiveCodec; 5] =
+        [Self::Tar, Self::TarGz, Self::TarZst, Self::TarXz, Self::Zip];
+
+    /// File extension of archives in this format, also used as the codec name
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+            Self::TarZst => "tar.zst",
+            Self::TarXz => "tar.xz",
+            Self::Zip => "zip",
+        }
+    }
+
+    /// Guess the codec from the extension of an archive file
+    pub fn from_archive_path(path: &impl AsRef<Path>) -> Option<Self> {
+        let name = path.as_ref().file_name()?.to_string_lossy().to_string();
+        Self::ALL
+            .into_iter()
+            .find(|codec| name.ends_with(&format!(".{}", codec.extension())))
+    }
+
+    /// Codec of an existing archive: the recorded one if any,
+    /// otherwise guessed from its extension, defaulting to 'tar.gz'
+    pub fn resolve(
+        recorded: Option<&str>,
+        archive_path: &impl AsRef<Path>,
+    ) -> Result<Self, KaguyaError> {
+        match recorded {
+            Some(codec) => Self::from_str(codec),
+            None => Ok(Self::from_archive_path(archive_path).unwrap_or(Self::TarGz)),
+        }
+    }
+
+    // Valid compression levels and the default one
+    fn level_range(&self) -> Option<(i32, i32, i32)> {
+        match self {
+            Self::Tar => None,
+            Self::TarGz => Some((0, 9, 6)),
+            Self::TarZst => Some((1, 22, 3)),
+            Self::TarXz => Some((0, 9, 6)),
+            Self::Zip => Some((0, 9, 6)),
+        }
+    }
+}
+
+impl fmt::Display for ArchiveCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+impl FromStr for ArchiveCodec {
+    type Err = KaguyaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|codec| codec.extension() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|c| c.extension()).collect();
+                KaguyaError::InvalidInput(format!(
+                    "Unsupported compression format '{}', expected one of: {}",
+                    s,
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+/// Codec and compression level used for new archives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionSettings {
+    pub codec: ArchiveCodec,
+    /// Compression level, `None` for the codec's default
+    pub level: Option<i32>,
+    /// Skip compressing content that is compressed already
+    pub adaptive: bool,
+}
+
+impl CompressionSettings {
+    /// Parse and validate a codec name and level from the vault config
+    pub fn new(codec: &str, level: Option<i32>) -> Result<Self, KaguyaError> {
+        let codec = ArchiveCodec::from_str(codec)?;
+
+        if let Some(level) = level {
+            match codec.level_range() {
+                Some((min, max, _)) if (min..=max).contains(&level) => {}
+                Some((min, max, _)) => {
+                    return Err(KaguyaError::InvalidInput(format!(
+                        "Compression level {} is out of range for '{}' ({}..={})",
+                        level, codec, min, max
+                    )));
+                }
+                None => {
+                    return Err(KaguyaError::InvalidInput(format!(
+                        "'{}' does not support compression levels",
+                        codec
+                    )));
+                }
+            }
+        }
+
+        Ok(Self {
+            codec,
+            level,
+            adaptive: true,
+        })
+    }
+
+    /// Whether new archives actually compress their content
+    pub fn is_compressed(&self) -> bool {
+        self.codec != ArchiveCodec::Tar
+    }
+
+    /// Settings of a plain tar archive, for paths that wouldn't benefit from compression
+    pub fn store_only(&self) -> Self {
+        Self {
+            codec: ArchiveCodec::Tar,
+            level: None,
+            adaptive: self.adaptive,
+        }
+    }
+
+    /// Level to use, falling back to the codec's default
+    pub fn effective_level(&self) -> i32 {
+        self.level
+            .or(self.codec.level_range().map(|(_, _, default)| default))
+            .unwrap_or(0)
+    }
+
+    /// Wrap a writer with the compression layer of a tar codec
+    pub fn encoder<W: Write>(&self, inner: W) -> Result<Encoder<W>, KaguyaError> {
+        let level = self.effective_level();
+        Ok(match self.codec {
+            ArchiveCodec::Tar => Encoder::Plain(inner),
+            // No timestamp in the gzip header, for reproducible archives
+            ArchiveCodec::TarGz => Encoder::Gz(
+                GzBuilder::new()
+                    .mtime(0)
+                    .write(inner, Compression::new(level as u32)),
+            ),
+            ArchiveCodec::TarZst => Encoder::Zst(zstd::Encoder::new(inner, level)?),
+            ArchiveCodec::TarXz => Encoder::Xz(XzEncoder::new(inner, level as u32)),
+            ArchiveCodec::Zip => return Err(not_a_tar_codec()),
+        })
+    }
+}
+
+impl Default for CompressionSettings {
+    fn default() -> Self {
+        Self {
+            codec: ArchiveCodec::TarGz,
+            level: None,
+            adaptive: true,
+        }
+    }
+}
+
+/// Compressing writer of any [`ArchiveCodec`]
+pub enum Encoder<W: Write> {
+    Plain(W),
+    Gz(GzEncoder<W>),
+    Zst(zstd::Encoder<'static, W>),
+    Xz(XzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Write the end of the compressed stream and return the inner writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Plain(w) => Ok(w),
+            Self::Gz(enc) => enc.finish(),
+            Self::Zst(enc) => enc.finish(),
+            Self::Xz(enc) => enc.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(w) => w.write(buf),
+            Self::Gz(enc) => enc.write(buf),
+            Self::Zst(enc) => enc.write(buf),
+            Self::Xz(enc) => enc.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(w) => w.flush(),
+            Self::Gz(enc) => enc.flush(),
+            Self::Zst(enc) => enc.flush(),
+            Self::Xz(enc) => enc.flush(),
+        }
+    }
+}
+
+/// Decompressing reader of any [`ArchiveCodec`]
+pub enum Decoder<R: Read> {
+    Plain(R),
+    Gz(GzDecoder<R>),
+    Zst(zstd::Decoder<'static, BufReader<R>>),
+    Xz(XzDecoder<R>),
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(codec: ArchiveCodec, inner: R) -> Result<Self, KaguyaError> {
+        Ok(match codec {
+            ArchiveCodec::Tar => Self::Plain(inner),
+            ArchiveCodec::TarGz => Self::Gz(GzDecoder::new(inner)),
+            ArchiveCodec::TarZst => Self::Zst(zstd::Decoder::new(inner)?),
+            ArchiveCodec::TarXz => Self::Xz(XzDecoder::new(inner)),
+            ArchiveCodec::Zip => return Err(not_a_tar_codec()),
+        })
+    }
+
+    /// Return the inner reader, any bytes it buffered are lost
+    pub fn into_inner(self) -> R {
+        match self {
+            Self::Plain(r) => r,
+            Self::Gz(dec) => dec.into_inner(),
+            Self::Zst(dec) => dec.finish().into_inner(),
+            Self::Xz(dec) => dec.into_inner(),
+        }
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(r) => r.read(buf),
+            Self::Gz(dec) => dec.read(buf),
+            Self::Zst(dec) => dec.read(buf),
+            Self::Xz(dec) => dec.read(buf),
+        }
+    }
+}
+
+// Zip archives are not a compressed tar stream, and are handled by `fs_utils::archive`
+fn not_a_tar_codec() -> KaguyaError {
+    KaguyaError::InvalidInput("'zip' is not a compressed tar stream".to_string())
+}
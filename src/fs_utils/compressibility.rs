@@ -0,0 +1,92 @@
+//! Detect already-compressed content, where compressing again wastes CPU for no gain
+
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use crate::{fs_utils::hash::FileIndex, models::KaguyaError};
+
+/// Extensions of formats that are compressed already (images, audio, video, archives)
+const INCOMPRESSIBLE_EXTENSIONS: [&str; 27] = [
+    "png", "jpg", "jpeg", "webp", "gif", "avif", "heic", "ogg", "oga", "opus", "mp3", "m4a",
+    "flac", "mp4", "mkv", "webm", "avi", "zip", "gz", "tgz", "xz", "zst", "bz2", "7z", "rar",
+    "lz4", "br",
+];
+
+/// Bytes read from the start of a file to estimate its entropy
+const PROBE_SIZE: u64 = 64 * 1024;
+
+/// Files smaller than this are never probed
+const MIN_PROBE_SIZE: usize = 512;
+
+/// Entropy in bits per byte above which content is considered incompressible
+const ENTROPY_THRESHOLD: f64 = 7.5;
+
+/// Share of incompressible bytes above which a whole path is stored without compression
+pub const STORE_ONLY_THRESHOLD: f64 = 0.9;
+
+/// Whether a file is already compressed, judged by its extension,
+/// or by the entropy of its first bytes if the extension is unknown.
+pub fn is_incompressible(path: &impl AsRef<Path>) -> Result<bool, KaguyaError> {
+    let path = path.as_ref();
+
+    if let Some(ext) = path.extension() {
+        let ext = ext.to_string_lossy().to_lowercase();
+        if INCOMPRESSIBLE_EXTENSIONS.contains(&ext.as_str()) {
+            return Ok(true);
+        }
+    }
+
+    let mut probe = Vec::new();
+    BufReader::new(File::open(path)?)
+        .take(PROBE_SIZE)
+        .read_to_end(&mut probe)?;
+
+    Ok(probe.len() >= MIN_PROBE_SIZE && shannon_entropy(&probe) > ENTROPY_THRESHOLD)
+}
+
+/// Share of bytes under `root` that are incompressible, weighted by file size.
+/// Files are taken from the file index of `root`, so the path isn't walked again.
+pub fn incompressible_share(root: &Path, index: &FileIndex) -> Result<f64, KaguyaError> {
+    let mut total: u64 = 0;
+    let mut incompressible: u64 = 0;
+
+    for (rel_path, entry) in index {
+        let file = if rel_path.is_empty() {
+            root.to_path_buf()
+        } else {
+            root.join(rel_path)
+        };
+
+        let size = entry.size_bytes as u64;
+        total += size;
+        if is_incompressible(&file)? {
+            incompressible += size;
+        }
+    }
+
+    if total == 0 {
+        return Ok(0.0);
+    }
+    Ok(incompressible as f64 / total as f64)
+}
+
+// Shannon entropy of the data in bits per byte, from 0 (constant) to 8 (random)
+fn shannon_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
@@ -0,0 +1,440 @@
+//! Client-side authenticated encryption of archives.
+//!
+//! Archives are encrypted with a random data key, in chunks of [`CHUNK_SIZE`] bytes
+//! sealed with XChaCha20-Poly1305. Each chunk nonce is a random per-file prefix
+//! followed by the chunk counter, and the last chunk is marked in its associated
+//! data, so reordered, truncated or edited chunks fail to decrypt.
+//!
+//! The data key itself is stored in vault metadata and next to the backups, wrapped by
+//! a key derived from the user's passphrase with Argon2id. Changing the passphrase only re-wraps the
+//! data key, archives don't need to be encrypted again.
+//!
+//! Encrypted file layout: `MAGIC | nonce prefix | chunk...`, where every chunk is
+//! the ciphertext of up to `CHUNK_SIZE` bytes followed by its 16-byte tag.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    Key, XChaCha20Poly1305, XNonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, File, remove_file, rename},
+    io::{self, Read, Write},
+    path::Path,
+};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::models::KaguyaError;
+
+/// Leading bytes of every encrypted file, with the format version last
+const MAGIC: &[u8; 8] = b"KGYENC\0\x01";
+
+/// Plaintext bytes per encrypted chunk
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Bytes of the authentication tag following every encrypted chunk
+pub const TAG_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = 16;
+
+/// Bytes of the header before the first chunk, the magic and the nonce prefix
+pub const HEADER_LEN: usize = MAGIC.len() + NONCE_PREFIX_LEN;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+/// Key derivation function recorded in a [`KeyEnvelope`]
+const KDF_ARGON2ID: &str = "argon2id";
+
+/// Argon2id cost of new passphrases: 64 MiB of memory, 3 passes, 1 lane
+const KDF_MEMORY_KIB: u32 = 64 * 1024;
+const KDF_ITERATIONS: u32 = 3;
+const KDF_PARALLELISM: u32 = 1;
+
+/// Random key every archive of a vault is encrypted with
+#[derive(Clone, PartialEq, Eq)]
+pub struct DataKey([u8; KEY_LEN]);
+
+impl DataKey {
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+}
+
+impl Drop for DataKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DataKey(..)")
+    }
+}
+
+/// The data key wrapped by a passphrase, with the parameters to derive the wrapping key again.
+/// Stored in vault metadata and in the vault directory, it holds nothing secret without
+/// the passphrase.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyEnvelope {
+    pub kdf: String,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    /// Hex encoded salt of the key derivation
+    pub salt: String,
+    /// Hex encoded nonce and ciphertext of the data key
+    pub nonce: String,
+    pub wrapped_key: String,
+}
+
+impl KeyEnvelope {
+    /// Wrap `key` with a key derived from `passphrase` and a fresh salt
+    pub fn seal(key: &DataKey, passphrase: &str) -> Result<Self, KaguyaError> {
+        let salt: [u8; SALT_LEN] = rand::random();
+        let nonce: [u8; 24] = rand::random();
+        let mut envelope = Self {
+            kdf: KDF_ARGON2ID.to_string(),
+            memory_kib: KDF_MEMORY_KIB,
+            iterations: KDF_ITERATIONS,
+            parallelism: KDF_PARALLELISM,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            wrapped_key: String::new(),
+        };
+
+        let wrapped = envelope
+            .wrapping_cipher(passphrase)?
+            .encrypt(XNonce::from_slice(&nonce), key.0.as_slice())
+            .map_err(|_| KaguyaError::Encryption("Could not wrap the vault key".to_string()))?;
+        envelope.wrapped_key = hex::encode(wrapped);
+        Ok(envelope)
+    }
+
+    /// Read the envelope saved at `path`, `None` if it doesn't exist
+    pub fn load(path: &impl AsRef<Path>) -> Result<Option<Self>, KaguyaError> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(toml::from_str(&content)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the envelope to `path`, next to it first and then renamed into place
+    pub fn save(&self, path: &impl AsRef<Path>) -> Result<(), KaguyaError> {
+        let path = path.as_ref();
+        let temp_path = path.with_extension("toml.tmp");
+        if let Err(e) = fs::write(&temp_path, toml::to_string(self)?) {
+            remove_file(&temp_path).ok();
+            return Err(e.into());
+        }
+        rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Unwrap the data key, failing with [`KaguyaError::WrongPassphrase`] if `passphrase` doesn't match
+    pub fn open(&self, passphrase: &str) -> Result<DataKey, KaguyaError> {
+        let nonce = decode_hex(&self.nonce, 24)?;
+        let wrapped = decode_hex(&self.wrapped_key, KEY_LEN + TAG_LEN)?;
+
+        let key = Zeroizing::new(
+            self.wrapping_cipher(passphrase)?
+                .decrypt(XNonce::from_slice(&nonce), wrapped.as_slice())
+                .map_err(|_| KaguyaError::WrongPassphrase)?,
+        );
+        let mut data_key = DataKey([0; KEY_LEN]);
+        data_key.0.copy_from_slice(&key);
+        Ok(data_key)
+    }
+
+    // Cipher keyed with the key derived from `passphrase`
+    fn wrapping_cipher(&self, passphrase: &str) -> Result<XChaCha20Poly1305, KaguyaError> {
+        if self.kdf != KDF_ARGON2ID {
+            return Err(KaguyaError::Encryption(format!(
+                "Unsupported key derivation function '{}'",
+                self.kdf
+            )));
+        }
+        let params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_LEN),
+        )
+        .map_err(|e| {
+            KaguyaError::Encryption(format!("Invalid key derivation parameters: {}", e))
+        })?;
+
+        let salt = decode_hex(&self.salt, SALT_LEN)?;
+        let mut key = Zeroizing::new([0; KEY_LEN]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, key.as_mut_slice())
+            .map_err(|e| KaguyaError::Encryption(format!("Could not derive key: {}", e)))?;
+        Ok(XChaCha20Poly1305::new(Key::from_slice(key.as_slice())))
+    }
+}
+
+/// Whether a file starts with the header of an encrypted file.
+/// A missing file is not encrypted, so callers report it as missing instead.
+pub fn is_encrypted(path: &impl AsRef<Path>) -> Result<bool, KaguyaError> {
+    let path = path.as_ref();
+    if !path.is_file() {
+        return Ok(false);
+    }
+
+    let mut header = Vec::with_capacity(MAGIC.len());
+    File::open(path)?
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut header)?;
+    Ok(header == MAGIC)
+}
+
+/// A writer encrypting everything written through it with `key`,
+/// or passing it through unchanged without a key.
+/// [`finish`](EncryptingWriter::finish) must be called to write the last chunk.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    cipher: Option<XChaCha20Poly1305>,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    counter: u64,
+    buffer: Zeroizing<Vec<u8>>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn new(mut inner: W, key: Option<&DataKey>) -> io::Result<Self> {
+        let nonce_prefix: [u8; NONCE_PREFIX_LEN] = rand::random();
+        if key.is_some() {
+            inner.write_all(MAGIC)?;
+            inner.write_all(&nonce_prefix)?;
+        }
+
+        Ok(Self {
+            inner,
+            cipher: key.map(DataKey::cipher),
+            nonce_prefix,
+            counter: 0,
+            buffer: Zeroizing::new(Vec::with_capacity(CHUNK_SIZE)),
+        })
+    }
+
+    /// Writes the last chunk, and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.cipher.is_some() {
+            self.seal_chunk(true)?;
+        }
+        Ok(self.inner)
+    }
+
+    // Encrypt the buffered chunk and write it out
+    fn seal_chunk(&mut self, last: bool) -> io::Result<()> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(());
+        };
+        let nonce = chunk_nonce(&self.nonce_prefix, self.counter);
+        let sealed = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &self.buffer,
+                    aad: &[last as u8],
+                },
+            )
+            .map_err(|_| io::Error::other("Could not encrypt archive chunk"))?;
+
+        self.inner.write_all(&sealed)?;
+        self.buffer.clear();
+        self.counter += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.cipher.is_none() {
+            return self.inner.write(buf);
+        }
+
+        let len = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        // A full chunk is never the last one, the last chunk may be empty
+        if self.buffer.len() == CHUNK_SIZE {
+            self.seal_chunk(false)?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader decrypting a file written by [`EncryptingWriter`] with `key`.
+/// Files without the encryption header are read unchanged, so archives written
+/// before encryption was enabled stay readable.
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    key: Option<DataKey>,
+    state: ReadState,
+}
+
+enum ReadState {
+    /// The header is not read yet
+    Start,
+    /// Plain file, with the bytes read while looking for the header
+    Plain(Vec<u8>),
+    Encrypted {
+        cipher: XChaCha20Poly1305,
+        nonce_prefix: [u8; NONCE_PREFIX_LEN],
+        counter: u64,
+        chunk: Zeroizing<Vec<u8>>,
+        pos: usize,
+        done: bool,
+    },
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(inner: R, key: Option<&DataKey>) -> Self {
+        Self {
+            inner,
+            key: key.cloned(),
+            state: ReadState::Start,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Look for the encryption header and pick the plain or encrypted state
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut header = Vec::with_capacity(MAGIC.len());
+        (&mut self.inner)
+            .take(MAGIC.len() as u64)
+            .read_to_end(&mut header)?;
+        if header != MAGIC {
+            self.state = ReadState::Plain(header);
+            return Ok(());
+        }
+
+        let Some(key) = &self.key else {
+            return Err(io::Error::other(
+                "Archive is encrypted, but no vault key was given",
+            ));
+        };
+        let mut nonce_prefix = [0; NONCE_PREFIX_LEN];
+        self.inner.read_exact(&mut nonce_prefix)?;
+        self.state = ReadState::Encrypted {
+            cipher: key.cipher(),
+            nonce_prefix,
+            counter: 0,
+            chunk: Zeroizing::new(Vec::new()),
+            pos: 0,
+            done: false,
+        };
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if matches!(self.state, ReadState::Start) {
+            self.read_header()?;
+        }
+
+        match &mut self.state {
+            ReadState::Start => unreachable!("header is read above"),
+            ReadState::Plain(pending) => {
+                if pending.is_empty() {
+                    return self.inner.read(buf);
+                }
+                let len = buf.len().min(pending.len());
+                buf[..len].copy_from_slice(&pending[..len]);
+                pending.drain(..len);
+                Ok(len)
+            }
+            ReadState::Encrypted {
+                cipher,
+                nonce_prefix,
+                counter,
+                chunk,
+                pos,
+                done,
+            } => {
+                if *pos == chunk.len() {
+                    if *done {
+                        return Ok(0);
+                    }
+
+                    let mut sealed = Vec::with_capacity(CHUNK_SIZE + TAG_LEN);
+                    (&mut self.inner)
+                        .take((CHUNK_SIZE + TAG_LEN) as u64)
+                        .read_to_end(&mut sealed)?;
+                    if sealed.is_empty() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "Encrypted archive is truncated",
+                        ));
+                    }
+
+                    let last = sealed.len() < CHUNK_SIZE + TAG_LEN;
+                    let nonce = chunk_nonce(nonce_prefix, *counter);
+                    *chunk = Zeroizing::new(
+                        cipher
+                            .decrypt(
+                                XNonce::from_slice(&nonce),
+                                Payload {
+                                    msg: &sealed,
+                                    aad: &[last as u8],
+                                },
+                            )
+                            .map_err(|_| {
+                                io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    "Encrypted archive chunk failed authentication",
+                                )
+                            })?,
+                    );
+                    *pos = 0;
+                    *counter += 1;
+                    *done = last;
+                }
+
+                let len = buf.len().min(chunk.len() - *pos);
+                buf[..len].copy_from_slice(&chunk[*pos..*pos + len]);
+                *pos += len;
+                Ok(len)
+            }
+        }
+    }
+}
+
+/// Decrypt a whole encrypted file without keeping the plaintext,
+/// so every chunk is authenticated.
+pub fn verify_encrypted(path: &impl AsRef<Path>, key: &DataKey) -> Result<(), KaguyaError> {
+    let mut reader = DecryptingReader::new(io::BufReader::new(File::open(path)?), Some(key));
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(())
+}
+
+// Nonce of a chunk: the random prefix of the file followed by the chunk counter
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u64) -> [u8; 24] {
+    let mut nonce = [0; 24];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+// Decode a hex field of a key envelope with the expected length
+fn decode_hex(value: &str, len: usize) -> Result<Vec<u8>, KaguyaError> {
+    match hex::decode(value) {
+        Ok(bytes) if bytes.len() == len => Ok(bytes),
+        _ => Err(KaguyaError::Encryption(
+            "Malformed vault key metadata".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,202 @@
+//! Binary delta chains for large saves that change slightly between versions.
+//!
+//! Every version of a path is its reproducible tar, zstd-compressed with the tar of
+//! the previous version as reference ("patch-from"), so only the changed bytes cost
+//! space. A chain starts with a keyframe compressed on its own, and a new keyframe
+//! is written every `keyframe_interval` versions, bounding the number of deltas a
+//! restore has to apply.
+
+use std::{
+    fs::{File, create_dir_all, remove_file, rename},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+use tar::Archive;
+
+use crate::{
+    fs_utils::{
+        archive::ArchiveInfo,
+        hash::{HashingReader, HashingWriter},
+    },
+    models::KaguyaError,
+};
+
+/// zstd level of delta files, higher levels find more matches against the base
+const DELTA_LEVEL: i32 = 9;
+
+/// Bounds of the zstd window, which must span the base and the new version
+const MIN_WINDOW_LOG: u32 = 10;
+const MAX_WINDOW_LOG: u32 = 31;
+
+/// Largest size of a base and the new version together, both are held in memory
+/// and must fit the zstd window
+pub const MAX_DELTA_SPAN: usize = 1 << MAX_WINDOW_LOG;
+
+/// A stored delta file with its recorded checksum
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaFile {
+    pub path: PathBuf,
+    pub checksum: String,
+}
+
+/// Compress `data` to `dst`, as a delta against `base` if given, otherwise as a keyframe.
+/// The file is written next to its final path, then renamed into place.
+///
+/// A delta whose base and data exceed [`MAX_DELTA_SPAN`] together is refused.
+pub fn write_delta(
+    data: &[u8],
+    base: Option<&[u8]>,
+    dst: &impl AsRef<Path>,
+) -> Result<ArchiveInfo, KaguyaError> {
+    let dst = dst.as_ref();
+    if let Some(base) = base
+        && base.len() + data.len() > MAX_DELTA_SPAN
+    {
+        return Err(KaguyaError::InvalidInput(format!(
+            "'{}' would span {} bytes with its base, deltas span at most {}",
+            dst.display(),
+            base.len() + data.len(),
+            MAX_DELTA_SPAN
+        )));
+    }
+    let temp_path = dst.with_extension("tmp");
+
+    let write_result = (|| -> Result<ArchiveInfo, KaguyaError> {
+        let writer = HashingWriter::new(BufWriter::new(File::create(&temp_path)?));
+        let mut encoder = match base {
+            Some(base) => {
+                let mut encoder = zstd::Encoder::with_ref_prefix(writer, DELTA_LEVEL, base)?;
+                encoder.long_distance_matching(true)?;
+                encoder.window_log(window_log(base.len() + data.len()))?;
+                encoder
+            }
+            None => zstd::Encoder::new(writer, DELTA_LEVEL)?,
+        };
+        encoder.include_checksum(true)?;
+        encoder.write_all(data)?;
+
+        let (mut writer, size_bytes, checksum) = encoder.finish()?.finish();
+        writer.flush()?;
+        Ok(ArchiveInfo {
+            size_bytes: size_bytes as i64,
+            checksum,
+        })
+    })();
+
+    match write_result {
+        Ok(info) => {
+            rename(&temp_path, dst)?;
+            Ok(info)
+        }
+        Err(e) => {
+            remove_file(&temp_path).ok();
+            Err(e)
+        }
+    }
+}
+
+/// Reconstruct the tar of the last file of a chain, given from its keyframe on.
+/// Every file is verified against its checksum. Returns `None` for an empty chain.
+pub fn reconstruct(chain: &[DeltaFile]) -> Result<Option<Vec<u8>>, KaguyaError> {
+    let mut data: Option<Vec<u8>> = None;
+    for file in chain {
+        data = Some(read_delta(
+            &file.path,
+            Some(&file.checksum),
+            data.as_deref(),
+        )?);
+    }
+    Ok(data)
+}
+
+/// Reconstruct the delta file `src` on top of its `bases`, and unpack it into the directory `dst`.
+/// If `expected_checksum` is given, `src` is verified against it before anything is unpacked.
+pub fn unpack_delta(
+    src: &impl AsRef<Path>,
+    bases: &[DeltaFile],
+    dst: &impl AsRef<Path>,
+    expected_checksum: Option<&str>,
+) -> Result<(), KaguyaError> {
+    let base = reconstruct(bases)?;
+    let data = read_delta(src.as_ref(), expected_checksum, base.as_deref())?;
+
+    let dst = dst.as_ref();
+    if !dst.exists() {
+        create_dir_all(dst)?;
+    }
+    Archive::new(data.as_slice()).unpack(dst)?;
+    Ok(())
+}
+
+/// Verify the delta file `src` and its whole chain of `bases` against their
+/// checksums, and that the chain still reconstructs.
+pub fn verify_delta(
+    src: &impl AsRef<Path>,
+    bases: &[DeltaFile],
+    expected_checksum: &str,
+) -> Result<(), KaguyaError> {
+    let base = reconstruct(bases)?;
+    read_delta(src.as_ref(), Some(expected_checksum), base.as_deref())?;
+    Ok(())
+}
+
+/// Re-encode `successor`, a delta against `removed`, against the base of `removed` instead,
+/// so `removed` can be deleted. `bases` is the chain `removed` is a delta against,
+/// `successor` becomes a keyframe if it is empty.
+///
+/// Returns the new size and checksum of `successor`.
+pub fn rebase_delta(
+    removed: &DeltaFile,
+    successor: &DeltaFile,
+    bases: &[DeltaFile],
+) -> Result<ArchiveInfo, KaguyaError> {
+    let base = reconstruct(bases)?;
+    let removed_data = read_delta(&removed.path, Some(&removed.checksum), base.as_deref())?;
+    let successor_data = read_delta(
+        &successor.path,
+        Some(&successor.checksum),
+        Some(&removed_data),
+    )?;
+
+    write_delta(&successor_data, base.as_deref(), &successor.path)
+}
+
+// Decompress a delta file against its reconstructed base, verifying the file checksum.
+// zstd frames carry a content checksum too, so a wrong base is detected as well.
+fn read_delta(
+    path: &Path,
+    expected_checksum: Option<&str>,
+    base: Option<&[u8]>,
+) -> Result<Vec<u8>, KaguyaError> {
+    if !path.is_file() {
+        return Err(KaguyaError::PathNotFound(
+            path.to_string_lossy().to_string(),
+        ));
+    }
+
+    let reader = BufReader::new(HashingReader::new(File::open(path)?));
+    let mut decoder = match base {
+        Some(base) => zstd::Decoder::with_ref_prefix(reader, base)?,
+        None => zstd::Decoder::with_buffer(reader)?,
+    };
+    decoder.window_log_max(MAX_WINDOW_LOG)?;
+
+    let mut data = Vec::new();
+    let decoded = decoder.read_to_end(&mut data);
+    let checksum = decoder.finish().into_inner().finish()?;
+
+    if let Some(expected) = expected_checksum
+        && checksum != expected
+    {
+        return Err(KaguyaError::ChecksumMismatch(
+            path.to_string_lossy().to_string(),
+        ));
+    }
+    decoded?;
+    Ok(data)
+}
+
+// Smallest window covering `len` bytes, within what zstd supports
+fn window_log(len: usize) -> u32 {
+    (usize::BITS - len.leading_zeros()).clamp(MIN_WINDOW_LOG, MAX_WINDOW_LOG)
+}
@@ -1,9 +1,31 @@
 use crate::models::KaguyaError;
+use rayon::prelude::*;
 use sha2::{Digest, Sha256};
-use std::collections::BTreeMap;
-use std::fs::{self, File};
-use std::io::{BufReader, Read};
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Cached hash of a single file, valid while its size, mtime and inode are unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileIndexEntry {
+    pub size_bytes: i64,
+    pub mtime_ns: i64,
+    pub inode: i64,
+    pub hash: String,
+}
+
+impl FileIndexEntry {
+    fn matches(&self, meta: &Metadata) -> bool {
+        self.size_bytes == meta.len() as i64
+            && self.mtime_ns == mtime_ns(meta)
+            && self.inode == meta.ino() as i64
+    }
+}
+
+/// File index of a configured path, keyed by path relative to it ('' if it is a file).
+pub type FileIndex = HashMap<String, FileIndexEntry>;
 
 /// Calculates checksum for a file or directory.
 /// Directly hashes files; recursively hashes directories.
@@ -26,6 +48,58 @@ pub fn calculate_entry_checksum<P: AsRef<Path>>(path: P) -> Result<String, Kaguy
     }
 }
 
+/// Calculates the same checksum as [`calculate_entry_checksum`], reusing hashes from
+/// `index` for files whose size, mtime and inode are unchanged.
+/// With `paranoid`, every file is rehashed regardless of the index.
+///
+/// Returns the checksum and the up-to-date index of the path.
+pub fn calculate_entry_checksum_cached<P: AsRef<Path>>(
+    path: P,
+    index: &FileIndex,
+    paranoid: bool,
+) -> Result<(String, FileIndex), KaguyaError> {
+    let p = path.as_ref();
+
+    if !p.exists() {
+        return Err(KaguyaError::PathNotFound(p.to_string_lossy().to_string()));
+    }
+
+    let hash_file = |file: &Path, rel_path: &str| -> Result<FileIndexEntry, KaguyaError> {
+        let meta = fs::metadata(file)?;
+        let hash = match index.get(rel_path) {
+            Some(entry) if !paranoid && entry.matches(&meta) => entry.hash.clone(),
+            _ => calculate_file_hash(file)?,
+        };
+
+        Ok(FileIndexEntry {
+            size_bytes: meta.len() as i64,
+            mtime_ns: mtime_ns(&meta),
+            inode: meta.ino() as i64,
+            hash,
+        })
+    };
+
+    if p.is_file() {
+        let entry = hash_file(p, "")?;
+        let checksum = entry.hash.clone();
+        Ok((checksum, FileIndex::from([(String::new(), entry)])))
+    } else if p.is_dir() {
+        let new_index = hash_dir_files(p, |file, rel_path| hash_file(file, rel_path))?;
+        let checksum = combine_file_hashes(
+            new_index
+                .iter()
+                .map(|(rel_path, entry)| (rel_path.clone(), entry.hash.clone()))
+                .collect(),
+        );
+        Ok((checksum, new_index.into_iter().collect()))
+    } else {
+        Err(KaguyaError::InvalidInput(format!(
+            "Unsupported file type with '{}'",
+            p.display()
+        )))
+    }
+}
+
 /// Calculate SHA-256 hash of the file
 fn calculate_file_hash<P: AsRef<Path>>(path: P) -> Result<String, KaguyaError> {
     let file = File::open(path.as_ref())?;
@@ -47,27 +121,44 @@ fn calculate_file_hash<P: AsRef<Path>>(path: P) -> Result<String, KaguyaError> {
 
 /// Recursively calculates directory checksum.
 fn calculate_dir_checksum(dir: &Path) -> Result<String, KaguyaError> {
-    let mut hasher = Sha256::new();
+    let file_hashes = hash_dir_files(dir, |path, _| calculate_file_hash(path))?;
+    Ok(combine_file_hashes(file_hashes))
+}
 
-    // Use BTreeMap to ensure paths are processed alphabetically (Determinism)
-    let mut file_hashes: BTreeMap<String, String> = BTreeMap::new();
+/// Hashes every file under `dir` in parallel with `hash_file(path, rel_path)`.
+/// Runs on the current rayon thread pool.
+fn hash_dir_files<T, F>(dir: &Path, hash_file: F) -> Result<BTreeMap<String, T>, KaguyaError>
+where
+    T: Send,
+    F: Fn(&Path, &str) -> Result<T, KaguyaError> + Sync,
+{
+    let mut files = Vec::new();
+    collect_files_recursive(dir, dir, &mut files)?;
+
+    files
+        .par_iter()
+        .map(|(path, rel_path)| Ok((rel_path.clone(), hash_file(path, rel_path)?)))
+        .collect()
+}
 
-    collect_hashes_recursive(dir, dir, &mut file_hashes)?;
+/// Feeds sorted relative paths and file hashes into the final hasher.
+/// BTreeMap ensures paths are processed alphabetically (Determinism)
+fn combine_file_hashes(file_hashes: BTreeMap<String, String>) -> String {
+    let mut hasher = Sha256::new();
 
-    // Feed sorted paths and file hashes into final hasher
     for (rel_path, content_hash) in file_hashes {
         hasher.update(rel_path.as_bytes());
         hasher.update(content_hash.as_bytes());
     }
 
-    Ok(hex::encode(hasher.finalize()))
+    hex::encode(hasher.finalize())
 }
 
-/// Recursively collects file hashes into the accumulator.
-fn collect_hashes_recursive(
+/// Recursively collects files with their paths relative to `root`.
+fn collect_files_recursive(
     root: &Path,
     current_dir: &Path,
-    acc: &mut BTreeMap<String, String>,
+    acc: &mut Vec<(PathBuf, String)>,
 ) -> Result<(), KaguyaError> {
     let entries = fs::read_dir(current_dir)?;
 
@@ -82,12 +173,82 @@ fn collect_hashes_recursive(
                 .to_string_lossy()
                 .to_string();
 
-            let hash = calculate_file_hash(&path)?;
-            acc.insert(rel_path, hash);
+            acc.push((path, rel_path));
         } else if path.is_dir() {
-            collect_hashes_recursive(root, &path, acc)?;
+            collect_files_recursive(root, &path, acc)?;
         }
     }
 
     Ok(())
 }
+
+// Modification time in nanoseconds since the Unix epoch
+fn mtime_ns(meta: &Metadata) -> i64 {
+    meta.mtime() * 1_000_000_000 + meta.mtime_nsec()
+}
+
+/// A writer that hashes and counts every byte written through it,
+/// so the checksum of a stream comes out of the same pass that writes it.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Returns the inner writer, the number of bytes written and their SHA-256 hash.
+    pub fn finish(self) -> (W, u64, String) {
+        (self.inner, self.bytes, hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader that hashes every byte read through it,
+/// so a stream can be verified while it is being consumed.
+pub struct HashingReader<R: Read> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Reads the rest of the stream and returns SHA-256 hash of everything read.
+    pub fn finish(mut self) -> Result<String, KaguyaError> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok(hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..bytes_read]);
+        Ok(bytes_read)
+    }
+}
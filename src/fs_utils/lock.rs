@@ -0,0 +1,119 @@
+//! Advisory lock of the vault, so concurrent kaguya runs don't race on it.
+//!
+//! Operations changing the vault config, version directories or the database take
+//! the lock exclusively, read-only ones share it. The lock is held on a lock file in
+//! the vault directory and released by the OS when its holder exits, even on a crash.
+//!
+//! Holders record their PID and command line in the lock file, to name them when the
+//! lock is busy. The file is never removed, so entries of processes that are gone are
+//! stale: they are ignored when naming holders, and cleared by the next exclusive holder.
+
+use std::{
+    fs::{File, OpenOptions, TryLockError, read_to_string},
+    io::Write,
+    path::Path,
+    process,
+};
+
+use crate::models::{KaguyaError, LOCK_FILE};
+
+/// How a [`VaultLock`] is held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Read-only operations, any number of them at once
+    Shared,
+    /// Operations changing the vault, alone
+    Exclusive,
+}
+
+/// The vault lock, released when dropped
+#[derive(Debug)]
+pub struct VaultLock {
+    file: File,
+    mode: LockMode,
+}
+
+impl VaultLock {
+    /// Take the lock of the vault at `vault_dir`, without waiting.
+    /// Fails with [`KaguyaError::VaultLocked`] naming the holders if it is busy.
+    pub fn acquire(vault_dir: &impl AsRef<Path>, mode: LockMode) -> Result<Self, KaguyaError> {
+        let path = vault_dir.as_ref().join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let locked = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+        match locked {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(KaguyaError::VaultLocked(describe_holders(&path)));
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        // Nobody else holds the lock, every entry left is stale
+        if mode == LockMode::Exclusive {
+            file.set_len(0)?;
+        }
+        // A single append, so entries of concurrent shared holders don't interleave
+        file.write_all(format!("{} {}\n", process::id(), command_line()).as_bytes())?;
+
+        Ok(Self { file, mode })
+    }
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        if self.mode == LockMode::Exclusive {
+            self.file.set_len(0).ok();
+        }
+        self.file.unlock().ok();
+    }
+}
+
+// Command line of this process, with the program name only
+fn command_line() -> String {
+    let mut args = std::env::args();
+    let program = args
+        .next()
+        .and_then(|arg| {
+            Path::new(&arg)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        })
+        .unwrap_or_else(|| "kaguya".to_string());
+
+    std::iter::once(program)
+        .chain(args)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Holders recorded in the lock file that are still running, e.g. "PID 1234 (kaguya vault backup)"
+fn describe_holders(path: &Path) -> String {
+    let holders: Vec<String> = read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (pid, command) = line.split_once(' ')?;
+            let pid: u32 = pid.parse().ok()?;
+            is_running(pid).then(|| format!("PID {} ({})", pid, command))
+        })
+        .collect();
+
+    if holders.is_empty() {
+        "another kaguya process".to_string()
+    } else {
+        holders.join(", ")
+    }
+}
+
+// Whether a process with this PID exists
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
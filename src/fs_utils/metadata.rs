@@ -0,0 +1,89 @@
+//! File metadata kept outside of archives.
+//!
+//! Archives are written with normalized headers so identical content yields identical
+//! archives, the real modification times and permissions are recorded separately
+//! and applied back on restore.
+
+use filetime::{FileTime, set_file_mtime};
+use std::{
+    fs::{self, Permissions, read_dir},
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::Path,
+};
+
+use crate::models::{KaguyaError, db::BackupFileEntry};
+
+/// Collect metadata of a file or directory and everything under it,
+/// keyed by path relative to it ('' for itself), sorted by path.
+/// Hashes are left empty, they come from the file index of the path.
+pub fn collect_entry_metadata(
+    path: &impl AsRef<Path>,
+) -> Result<Vec<BackupFileEntry>, KaguyaError> {
+    let path = path.as_ref();
+    let mut entries = Vec::new();
+    collect_recursive(path, String::new(), &mut entries)?;
+    Ok(entries)
+}
+
+/// Apply recorded metadata to a restored file or directory.
+/// Entries that no longer exist under `root` are ignored.
+pub fn apply_entry_metadata(
+    root: &impl AsRef<Path>,
+    entries: &[BackupFileEntry],
+) -> Result<(), KaguyaError> {
+    let root = root.as_ref();
+
+    // Children first, so restoring them can't touch the mtime of their directory again
+    for entry in entries.iter().rev() {
+        let path = if entry.rel_path.is_empty() {
+            root.to_path_buf()
+        } else {
+            root.join(&entry.rel_path)
+        };
+        if !path.exists() {
+            continue;
+        }
+
+        fs::set_permissions(&path, Permissions::from_mode(entry.mode))?;
+        set_file_mtime(
+            &path,
+            FileTime::from_unix_time(
+                entry.mtime_ns.div_euclid(1_000_000_000),
+                entry.mtime_ns.rem_euclid(1_000_000_000) as u32,
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+// Push metadata of `path` and, for directories, of its children in sorted order
+fn collect_recursive(
+    path: &Path,
+    rel_path: String,
+    acc: &mut Vec<BackupFileEntry>,
+) -> Result<(), KaguyaError> {
+    let meta = fs::metadata(path)?;
+    acc.push(BackupFileEntry {
+        rel_path: rel_path.clone(),
+        mtime_ns: meta.mtime() * 1_000_000_000 + meta.mtime_nsec(),
+        mode: meta.mode() & 0o7777,
+        hash: None,
+    });
+
+    if meta.is_dir() {
+        let mut children = read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        children.sort_by_key(|entry| entry.file_name());
+        for child in children {
+            let name = child.file_name().to_string_lossy().to_string();
+            let child_rel_path = if rel_path.is_empty() {
+                name
+            } else {
+                format!("{}/{}", rel_path, name)
+            };
+            collect_recursive(&child.path(), child_rel_path, acc)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,195 @@
+//! Hard-link snapshot mirrors, rsnapshot style.
+//!
+//! A mirror is a plain copy of a backed up path inside its version directory,
+//! browsable with any file manager. Files unchanged since the previous mirror
+//! are hard links to its copies, so they cost no extra space.
+
+use std::{
+    collections::HashMap,
+    fs::{self, Permissions, copy, create_dir_all, hard_link, read_dir},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    fs_utils::hash::{FileIndex, calculate_entry_checksum},
+    models::KaguyaError,
+    utils::path::get_file_name,
+};
+
+/// Previous mirror of a path, whose unchanged files are hard-linked
+#[derive(Debug, Clone)]
+pub struct PreviousMirror {
+    /// Root of the previous mirror
+    pub path: PathBuf,
+    /// File hashes of the previous mirror, keyed by relative path
+    pub hashes: HashMap<String, String>,
+}
+
+/// Result of mirroring a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorInfo {
+    pub linked_files: usize,
+    pub copied_files: usize,
+    /// Size of the copied files, the space the mirror actually added
+    pub copied_bytes: u64,
+}
+
+/// Mirror `src` into the directory `dst`, under its own file name.
+/// Files whose hash in the up-to-date `file_index` matches the `previous` mirror are
+/// hard-linked to it, others are copied. Mirrored files are made read-only, since
+/// editing one in place would also change every version linked to it.
+///
+/// Usage:
+/// ```no_run
+/// # use kaguya::{fs_utils::{hash::{FileIndex, calculate_entry_checksum_cached}, mirror::mirror_tree}, utils::path::expand_path};
+/// # fn main() -> Result<(), kaguya::models::KaguyaError> {
+/// let src = expand_path("~/games/game-a/saves")?;
+/// let dst = expand_path("~/.local/share/kaguya/vault/backups/game-a/2025-12-25_10-00-00")?;
+/// let (_, file_index) = calculate_entry_checksum_cached(&src, &FileIndex::new(), false)?;
+///
+/// // Will mirror to '.../2025-12-25_10-00-00/saves', copying every file
+/// let info = mirror_tree(&src, &dst, &file_index, None)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn mirror_tree(
+    src: &impl AsRef<Path>,
+    dst: &impl AsRef<Path>,
+    file_index: &FileIndex,
+    previous: Option<&PreviousMirror>,
+) -> Result<MirrorInfo, KaguyaError> {
+    let src = src.as_ref();
+    if !src.exists() {
+        return Err(KaguyaError::PathNotFound(src.to_string_lossy().to_string()));
+    }
+
+    let root = dst
+        .as_ref()
+        .join(get_file_name(src).unwrap_or(".".to_string()));
+    let mut info = MirrorInfo {
+        linked_files: 0,
+        copied_files: 0,
+        copied_bytes: 0,
+    };
+
+    let mut files = Vec::new();
+    if src.is_file() {
+        files.push((src.to_path_buf(), String::new()));
+    } else {
+        collect_tree(src, String::new(), &root, &mut files)?;
+    }
+
+    for (path, rel_path) in files {
+        let target = join_rel(&root, &rel_path);
+        let hash = file_index.get(&rel_path).map(|entry| &entry.hash);
+
+        let linked = match previous {
+            Some(previous) if hash.is_some() && previous.hashes.get(&rel_path) == hash => {
+                hard_link(join_rel(&previous.path, &rel_path), &target).is_ok()
+            }
+            _ => false,
+        };
+
+        if linked {
+            info.linked_files += 1;
+        } else {
+            info.copied_bytes += copy(&path, &target)?;
+            info.copied_files += 1;
+            let mode = fs::metadata(&target)?.permissions().mode();
+            fs::set_permissions(&target, Permissions::from_mode(mode & !0o222))?;
+        }
+    }
+
+    Ok(info)
+}
+
+/// Copy a mirror into the directory `dst`, like
+/// [`decompress_archive`](crate::fs_utils::archive::decompress_archive) does with an archive:
+/// the copy is created under the mirror's file name, and is writable again.
+/// If `expected_checksum` is given, the mirror is verified first.
+pub fn copy_mirror(
+    src: &impl AsRef<Path>,
+    dst: &impl AsRef<Path>,
+    expected_checksum: Option<&str>,
+) -> Result<(), KaguyaError> {
+    let src = src.as_ref();
+    verify_mirror(&src, expected_checksum)?;
+
+    let root = dst
+        .as_ref()
+        .join(get_file_name(src).unwrap_or(".".to_string()));
+    let mut files = Vec::new();
+    if src.is_file() {
+        files.push((src.to_path_buf(), String::new()));
+    } else {
+        collect_tree(src, String::new(), &root, &mut files)?;
+    }
+
+    for (path, rel_path) in files {
+        let target = join_rel(&root, &rel_path);
+        copy(&path, &target)?;
+        let mode = fs::metadata(&target)?.permissions().mode();
+        fs::set_permissions(&target, Permissions::from_mode(mode | 0o200))?;
+    }
+    Ok(())
+}
+
+/// Verify a mirror against the content checksum recorded at backup time
+pub fn verify_mirror(
+    src: &impl AsRef<Path>,
+    expected_checksum: Option<&str>,
+) -> Result<(), KaguyaError> {
+    let src = src.as_ref();
+    if !src.exists() {
+        return Err(KaguyaError::PathNotFound(src.to_string_lossy().to_string()));
+    }
+
+    if let Some(expected) = expected_checksum
+        && calculate_entry_checksum(src)? != expected
+    {
+        return Err(KaguyaError::ChecksumMismatch(
+            src.to_string_lossy().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Create the directories of `dir` under `root`, and collect its files with relative paths
+fn collect_tree(
+    dir: &Path,
+    rel_path: String,
+    root: &Path,
+    acc: &mut Vec<(PathBuf, String)>,
+) -> Result<(), KaguyaError> {
+    create_dir_all(join_rel(root, &rel_path))?;
+
+    let mut entries = read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let child_rel_path = if rel_path.is_empty() {
+            name
+        } else {
+            format!("{}/{}", rel_path, name)
+        };
+
+        let path = entry.path();
+        if path.is_dir() {
+            collect_tree(&path, child_rel_path, root, acc)?;
+        } else if path.is_file() {
+            acc.push((path, child_rel_path));
+        }
+    }
+
+    Ok(())
+}
+
+// Join a relative path to its root, '' being the root itself
+fn join_rel(root: &Path, rel_path: &str) -> PathBuf {
+    if rel_path.is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(rel_path)
+    }
+}
@@ -1,5 +1,17 @@
 //! File system utils
 
 pub mod archive;
+pub mod codec;
+pub mod compressibility;
+pub mod crypto;
+pub mod delta;
 pub mod hash;
+pub mod lock;
+pub mod metadata;
+pub mod mirror;
+pub mod objects;
+pub mod parity;
 pub mod restore;
+pub mod signature;
+pub mod storage;
+pub mod version_manifest;
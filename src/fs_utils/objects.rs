@@ -0,0 +1,496 @@
+//! Content-addressed object store shared by all games of a vault.
+//!
+//! Every file is stored once, zstd-compressed, under `objects/<ab>/<rest of hash>`
+//! keyed by the SHA-256 of its content. A backup of a path is then a small manifest
+//! mapping each of its files to an object, so unchanged files cost nothing.
+//!
+//! Large files that change slightly between sessions can instead be split into
+//! content-defined chunks, each stored as an object, so only changed chunks cost space.
+
+use fastcdc::v2020::StreamCDC;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fs::{self, File, create_dir_all, read_dir, remove_file, rename},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    fs_utils::{
+        archive::ArchiveInfo,
+        hash::{FileIndex, HashingReader, HashingWriter, calculate_entry_checksum},
+        restore::generate_unique_temp_name,
+    },
+    models::KaguyaError,
+    utils::path::get_file_name,
+};
+
+/// zstd level of stored objects, favoring speed since most files are saved only once
+const OBJECT_LEVEL: i32 = 3;
+
+/// Prefix of objects being written, renamed into place once complete
+const TEMP_PREFIX: &str = ".kaguya-object";
+
+/// Minimum, average and maximum sizes of content-defined chunks
+const CHUNK_MIN_SIZE: u32 = 16 * 1024;
+const CHUNK_AVG_SIZE: u32 = 64 * 1024;
+const CHUNK_MAX_SIZE: u32 = 256 * 1024;
+
+/// Directory of content-addressed objects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectStore {
+    root: PathBuf,
+}
+
+/// Manifest of a backed up path, written to the version directory
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ObjectManifest {
+    /// File name of the original path, the top-level entry when unpacked
+    pub name: String,
+    /// Directories relative to the original path, '' for itself if it is a directory
+    #[serde(default)]
+    pub dirs: Vec<String>,
+    #[serde(default)]
+    pub files: Vec<ManifestFile>,
+}
+
+/// A single file of an [`ObjectManifest`], stored either as one object or as chunks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Path relative to the original path, '' if the original path is this file
+    pub path: String,
+    /// SHA-256 of the file content, naming its object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object: Option<String>,
+    /// Chunks of the file content in order, if it was chunked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<ManifestChunk>,
+    pub size: u64,
+}
+
+/// A content-defined chunk of a [`ManifestFile`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestChunk {
+    /// SHA-256 of the chunk, naming its object
+    pub object: String,
+    pub size: u64,
+}
+
+/// Result of storing a path in the object store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredTree {
+    /// Size and checksum of the manifest file
+    pub manifest: ArchiveInfo,
+    /// Number of objects that were not in the store yet
+    pub new_objects: usize,
+    /// Compressed size of the new objects
+    pub new_bytes: u64,
+}
+
+/// Outcome of [`ObjectStore::collect_garbage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GarbageReport {
+    pub removed_objects: usize,
+    /// Objects left half-written by an interrupted run
+    pub removed_temp_files: usize,
+    pub freed_bytes: u64,
+}
+
+impl ObjectStore {
+    pub fn new(root: &impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Path of the object with the given hash
+    pub fn object_path(&self, hash: &str) -> PathBuf {
+        let (prefix, rest) = hash.split_at(hash.len().min(2));
+        self.root.join(prefix).join(rest)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.object_path(hash).is_file()
+    }
+
+    /// Store a file unless an object with the `known_hash` of its content exists already.
+    /// The hash is computed again while storing, so a file changed since it was indexed
+    /// is stored under its actual hash.
+    ///
+    /// Returns the hash of the object, and its size if it was newly written.
+    pub fn put_file(
+        &self,
+        path: &impl AsRef<Path>,
+        known_hash: Option<&str>,
+    ) -> Result<(String, Option<u64>), KaguyaError> {
+        if let Some(hash) = known_hash
+            && self.contains(hash)
+        {
+            return Ok((hash.to_string(), None));
+        }
+
+        create_dir_all(&self.root)?;
+        let temp_path = self.root.join(generate_unique_temp_name(TEMP_PREFIX, 8));
+
+        let write_result = (|| -> Result<(String, u64), KaguyaError> {
+            let mut reader = HashingReader::new(BufReader::new(File::open(path.as_ref())?));
+            let writer = HashingWriter::new(BufWriter::new(File::create(&temp_path)?));
+            let mut encoder = zstd::Encoder::new(writer, OBJECT_LEVEL)?;
+            io::copy(&mut reader, &mut encoder)?;
+            let (mut writer, size, _) = encoder.finish()?.finish();
+            writer.flush()?;
+            Ok((reader.finish()?, size))
+        })();
+
+        let (hash, size) = match write_result {
+            Ok(result) => result,
+            Err(e) => {
+                remove_file(&temp_path).ok();
+                return Err(e);
+            }
+        };
+
+        // Concurrent jobs may store the same content, the object is identical either way
+        if self.contains(&hash) {
+            remove_file(&temp_path)?;
+            return Ok((hash, None));
+        }
+        let object_path = self.object_path(&hash);
+        create_dir_all(object_path.parent().unwrap_or(&self.root))?;
+        rename(&temp_path, &object_path)?;
+
+        Ok((hash, Some(size)))
+    }
+
+    /// Store a chunk of data unless it exists already.
+    ///
+    /// Returns the hash of the object, and its size if it was newly written.
+    pub fn put_bytes(&self, data: &[u8]) -> Result<(String, Option<u64>), KaguyaError> {
+        let hash = hex::encode(Sha256::digest(data));
+        if self.contains(&hash) {
+            return Ok((hash, None));
+        }
+
+        create_dir_all(&self.root)?;
+        let temp_path = self.root.join(generate_unique_temp_name(TEMP_PREFIX, 8));
+        let compressed = zstd::encode_all(data, OBJECT_LEVEL)?;
+        if let Err(e) = fs::write(&temp_path, &compressed) {
+            remove_file(&temp_path).ok();
+            return Err(e.into());
+        }
+
+        let object_path = self.object_path(&hash);
+        create_dir_all(object_path.parent().unwrap_or(&self.root))?;
+        rename(&temp_path, &object_path)?;
+
+        Ok((hash, Some(compressed.len() as u64)))
+    }
+
+    /// Write the content of an object to `writer`, verifying it against its hash
+    pub fn read_object(&self, hash: &str, writer: &mut impl Write) -> Result<(), KaguyaError> {
+        let object_path = self.object_path(hash);
+        if !object_path.is_file() {
+            return Err(KaguyaError::PathNotFound(
+                object_path.to_string_lossy().to_string(),
+            ));
+        }
+
+        let mut decoder = zstd::Decoder::new(File::open(&object_path)?)?;
+        let mut writer = HashingWriter::new(writer);
+        io::copy(&mut decoder, &mut writer)?;
+        let (_, _, checksum) = writer.finish();
+
+        if checksum != hash {
+            return Err(KaguyaError::ChecksumMismatch(
+                object_path.to_string_lossy().to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Compressed size of an object on disk
+    pub fn object_size(&self, hash: &str) -> Result<u64, KaguyaError> {
+        Ok(fs::metadata(self.object_path(hash))?.len())
+    }
+
+    /// Hashes of every object in the store
+    pub fn list_objects(&self) -> Result<Vec<String>, KaguyaError> {
+        let mut hashes = Vec::new();
+        if !self.root.is_dir() {
+            return Ok(hashes);
+        }
+
+        for prefix in read_dir(&self.root)? {
+            let prefix = prefix?;
+            if !prefix.file_type()?.is_dir() {
+                continue;
+            }
+            let prefix_name = prefix.file_name().to_string_lossy().to_string();
+            for object in read_dir(prefix.path())? {
+                let name = object?.file_name().to_string_lossy().to_string();
+                hashes.push(format!("{}{}", prefix_name, name));
+            }
+        }
+
+        Ok(hashes)
+    }
+
+    /// Remove every object that is not in `referenced`, and objects left half-written.
+    /// Nothing may be stored meanwhile, the vault lock keeps other runs out.
+    pub fn collect_garbage(
+        &self,
+        referenced: &HashSet<String>,
+    ) -> Result<GarbageReport, KaguyaError> {
+        let mut report = GarbageReport::default();
+        if self.root.is_dir() {
+            for entry in read_dir(&self.root)? {
+                let entry = entry?;
+                if entry.file_type()?.is_file()
+                    && entry.file_name().to_string_lossy().starts_with(TEMP_PREFIX)
+                {
+                    report.freed_bytes += entry.metadata()?.len();
+                    report.removed_temp_files += 1;
+                    remove_file(entry.path())?;
+                }
+            }
+        }
+
+        for hash in self.list_objects()? {
+            if referenced.contains(&hash) {
+                continue;
+            }
+
+            let object_path = self.object_path(&hash);
+            report.freed_bytes += fs::metadata(&object_path)?.len();
+            report.removed_objects += 1;
+            remove_file(&object_path)?;
+
+            // Drop the prefix directory once it is empty
+            if let Some(parent) = object_path.parent() {
+                fs::remove_dir(parent).ok();
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+impl ObjectManifest {
+    /// Read a manifest file, verifying it against its recorded checksum
+    pub fn read(
+        path: &impl AsRef<Path>,
+        expected_checksum: Option<&str>,
+    ) -> Result<Self, KaguyaError> {
+        let path = path.as_ref();
+        if !path.is_file() {
+            return Err(KaguyaError::PathNotFound(
+                path.to_string_lossy().to_string(),
+            ));
+        }
+
+        if let Some(expected) = expected_checksum
+            && calculate_entry_checksum(path)? != expected
+        {
+            return Err(KaguyaError::ChecksumMismatch(
+                path.to_string_lossy().to_string(),
+            ));
+        }
+
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Objects referenced by this manifest with their uncompressed sizes,
+    /// whole files and chunks alike
+    pub fn objects(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.files.iter().flat_map(|file| {
+            file.object
+                .iter()
+                .map(|object| (object.as_str(), file.size))
+                .chain(
+                    file.chunks
+                        .iter()
+                        .map(|chunk| (chunk.object.as_str(), chunk.size)),
+                )
+        })
+    }
+}
+
+/// Store every file of `src` in the object store, and write a manifest of it to `manifest_path`.
+/// Hashes from the up-to-date `file_index` of `src` avoid storing known objects again.
+///
+/// With `chunked`, files are split into content-defined chunks stored as separate objects,
+/// so a large file that changes slightly only adds its changed chunks.
+///
+/// Usage:
+/// ```no_run
+/// # use kaguya::{fs_utils::{hash::{FileIndex, calculate_entry_checksum_cached}, objects::{ObjectStore, store_tree}}, utils::path::expand_path};
+/// # fn main() -> Result<(), kaguya::models::KaguyaError> {
+/// let src = expand_path("~/games/game-a/saves")?;
+/// let manifest = expand_path("~/.local/share/kaguya/vault/backups/game-a/2025-12-25_10-00-00/saves.manifest.toml")?;
+/// let store = ObjectStore::new(&expand_path("~/.local/share/kaguya/vault/objects")?);
+/// let (_, file_index) = calculate_entry_checksum_cached(&src, &FileIndex::new(), false)?;
+///
+/// let stored = store_tree(&src, &file_index, &store, &manifest, false)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn store_tree(
+    src: &impl AsRef<Path>,
+    file_index: &FileIndex,
+    store: &ObjectStore,
+    manifest_path: &impl AsRef<Path>,
+    chunked: bool,
+) -> Result<StoredTree, KaguyaError> {
+    let src = src.as_ref();
+    if !src.exists() {
+        return Err(KaguyaError::PathNotFound(src.to_string_lossy().to_string()));
+    }
+
+    let mut manifest = ObjectManifest {
+        name: get_file_name(src).unwrap_or(".".to_string()),
+        ..Default::default()
+    };
+    let mut new_objects = 0;
+    let mut new_bytes = 0;
+
+    let mut count_written = |written: Option<u64>| {
+        if let Some(size) = written {
+            new_objects += 1;
+            new_bytes += size;
+        }
+    };
+
+    let mut store_file = |path: &Path, rel_path: String| -> Result<(), KaguyaError> {
+        let size = fs::metadata(path)?.len();
+        let mut file = ManifestFile {
+            path: rel_path,
+            object: None,
+            chunks: Vec::new(),
+            size,
+        };
+
+        if chunked {
+            let reader = BufReader::new(File::open(path)?);
+            for chunk in StreamCDC::new(reader, CHUNK_MIN_SIZE, CHUNK_AVG_SIZE, CHUNK_MAX_SIZE) {
+                let chunk = chunk.map_err(io::Error::from)?;
+                let (object, written) = store.put_bytes(&chunk.data)?;
+                count_written(written);
+                file.chunks.push(ManifestChunk {
+                    object,
+                    size: chunk.length as u64,
+                });
+            }
+        } else {
+            let known_hash = file_index.get(&file.path).map(|entry| entry.hash.as_str());
+            let (object, written) = store.put_file(&path, known_hash)?;
+            count_written(written);
+            file.object = Some(object);
+        }
+
+        manifest.files.push(file);
+        Ok(())
+    };
+
+    if src.is_file() {
+        store_file(src, String::new())?;
+    } else {
+        let mut dirs = Vec::new();
+        walk_sorted(src, String::new(), &mut dirs, &mut store_file)?;
+        manifest.dirs = dirs;
+    }
+
+    let manifest = write_manifest(&manifest, manifest_path.as_ref())?;
+    Ok(StoredTree {
+        manifest,
+        new_objects,
+        new_bytes,
+    })
+}
+
+/// Unpack the path described by a manifest into `dst`, like
+/// [`decompress_archive`](crate::fs_utils::archive::decompress_archive) does with an archive:
+/// `dst` must be a directory, and the path is created under its original file name.
+pub fn unpack_manifest(
+    manifest_path: &impl AsRef<Path>,
+    store: &ObjectStore,
+    dst: &impl AsRef<Path>,
+    expected_checksum: Option<&str>,
+) -> Result<(), KaguyaError> {
+    let manifest = ObjectManifest::read(manifest_path, expected_checksum)?;
+    let root = dst.as_ref().join(&manifest.name);
+
+    for dir in &manifest.dirs {
+        create_dir_all(join_rel(&root, dir))?;
+    }
+    for file in &manifest.files {
+        let path = join_rel(&root, &file.path);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        if let Some(object) = &file.object {
+            store.read_object(object, &mut writer)?;
+        }
+        for chunk in &file.chunks {
+            store.read_object(&chunk.object, &mut writer)?;
+        }
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+// Visit files of a directory in sorted order, collecting directories on the way
+fn walk_sorted(
+    dir: &Path,
+    rel_path: String,
+    dirs: &mut Vec<String>,
+    visit_file: &mut impl FnMut(&Path, String) -> Result<(), KaguyaError>,
+) -> Result<(), KaguyaError> {
+    let mut entries = read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    dirs.push(rel_path.clone());
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let child_rel_path = if rel_path.is_empty() {
+            name
+        } else {
+            format!("{}/{}", rel_path, name)
+        };
+
+        let path = entry.path();
+        if path.is_dir() {
+            walk_sorted(&path, child_rel_path, dirs, visit_file)?;
+        } else if path.is_file() {
+            visit_file(&path, child_rel_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Write a manifest next to its final path, then rename it into place
+fn write_manifest(manifest: &ObjectManifest, path: &Path) -> Result<ArchiveInfo, KaguyaError> {
+    let content = toml::to_string(manifest)?;
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, &content)?;
+    rename(&temp_path, path)?;
+
+    Ok(ArchiveInfo {
+        size_bytes: content.len() as i64,
+        checksum: calculate_entry_checksum(path)?,
+    })
+}
+
+// Join a relative manifest path to its root, '' being the root itself
+fn join_rel(root: &Path, rel_path: &str) -> PathBuf {
+    if rel_path.is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(rel_path)
+    }
+}
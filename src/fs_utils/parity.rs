@@ -0,0 +1,350 @@
+//! Reed-Solomon parity files, to repair bit rot of backup files in place.
+//!
+//! A file is split into stripes of [`DATA_SHARDS`] shards, and every stripe gets
+//! parity shards computed over them. Every shard is recorded with its SHA-256 hash,
+//! so damaged shards are located by their hash and rebuilt from the others, as long
+//! as a stripe has no more damaged shards than parity shards.
+//!
+//! Parity file layout: `MAGIC | file size | shard size | parity shards | header hash`,
+//! then for every stripe the hashes of its data and parity shards, followed by its
+//! parity shards. The last stripe of the file is padded with zeros.
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File, remove_file, rename},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::models::KaguyaError;
+
+/// Extension appended to the name of a file to get its parity file
+pub const PARITY_EXT: &str = "par";
+
+/// Leading bytes of every parity file, with the format version last
+const MAGIC: &[u8; 8] = b"KGYPAR\0\x01";
+
+/// Data shards per stripe
+const DATA_SHARDS: usize = 128;
+
+/// Bounds of the shard size, small files get small shards so their parity stays small
+const MIN_SHARD_SIZE: usize = 64;
+const MAX_SHARD_SIZE: usize = 64 * 1024;
+
+const HASH_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + 8 + 4 + 4 + HASH_LEN;
+
+/// Damage found in a file and its parity file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParityScan {
+    /// Shards of the file that don't match their recorded hash
+    pub damaged_shards: usize,
+    /// Parity shards that don't match their recorded hash
+    pub damaged_parity: usize,
+    /// Stripes with more damaged shards than parity shards to rebuild them from
+    pub unrecoverable_stripes: usize,
+    /// The file is longer or shorter than when its parity was computed
+    pub size_changed: bool,
+}
+
+impl ParityScan {
+    /// Whether the file and its parity are both undamaged
+    pub fn is_intact(&self) -> bool {
+        self.damaged_shards == 0 && self.damaged_parity == 0 && !self.size_changed
+    }
+
+    /// Whether the file itself is damaged
+    pub fn file_damaged(&self) -> bool {
+        self.damaged_shards > 0 || self.size_changed
+    }
+
+    /// Whether every damaged stripe can be rebuilt
+    pub fn is_repairable(&self) -> bool {
+        self.unrecoverable_stripes == 0
+    }
+}
+
+// Shape of a parity file, fixed by the size of the protected file and the redundancy
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    file_size: u64,
+    shard_size: usize,
+    parity_shards: usize,
+}
+
+impl Layout {
+    fn new(file_size: u64, parity_shards: usize) -> Self {
+        let shard_size = (file_size as usize)
+            .div_ceil(DATA_SHARDS)
+            .next_multiple_of(MIN_SHARD_SIZE)
+            .clamp(MIN_SHARD_SIZE, MAX_SHARD_SIZE);
+        Self {
+            file_size,
+            shard_size,
+            parity_shards,
+        }
+    }
+
+    fn stripes(&self) -> u64 {
+        self.file_size
+            .div_ceil((DATA_SHARDS * self.shard_size) as u64)
+            .max(1)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.extend(self.file_size.to_le_bytes());
+        header.extend((self.shard_size as u32).to_le_bytes());
+        header.extend((self.parity_shards as u32).to_le_bytes());
+        let hash = Sha256::digest(&header);
+        header.extend(hash);
+        header
+    }
+
+    fn decode(header: &[u8; HEADER_LEN]) -> Result<Self, KaguyaError> {
+        let (fields, hash) = header.split_at(HEADER_LEN - HASH_LEN);
+        if &fields[..MAGIC.len()] != MAGIC || Sha256::digest(fields).as_slice() != hash {
+            return Err(KaguyaError::Parity(
+                "the parity file header is damaged".to_string(),
+            ));
+        }
+
+        // Fields follow the magic: file size (u64), shard size and parity shards (u32)
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(fields[offset..offset + 4].try_into().unwrap_or_default()) as usize
+        };
+        let layout = Self {
+            file_size: u64::from_le_bytes(fields[8..16].try_into().unwrap_or_default()),
+            shard_size: u32_at(16),
+            parity_shards: u32_at(20),
+        };
+        if !(MIN_SHARD_SIZE..=MAX_SHARD_SIZE).contains(&layout.shard_size)
+            || !(1..=DATA_SHARDS).contains(&layout.parity_shards)
+        {
+            return Err(KaguyaError::Parity(
+                "the parity file header is invalid".to_string(),
+            ));
+        }
+        Ok(layout)
+    }
+
+    fn codec(&self) -> Result<ReedSolomon, KaguyaError> {
+        ReedSolomon::new(DATA_SHARDS, self.parity_shards)
+            .map_err(|e| KaguyaError::Parity(e.to_string()))
+    }
+}
+
+/// Parity file of `path`, e.g. 'saves.tar.zst' -> 'saves.tar.zst.par'
+pub fn parity_path(path: &impl AsRef<Path>) -> PathBuf {
+    let mut name = path.as_ref().as_os_str().to_owned();
+    name.push(".");
+    name.push(PARITY_EXT);
+    PathBuf::from(name)
+}
+
+/// Compute the parity of the file `path`, with `percent` parity shards per data shards,
+/// and write it next to it. Returns the size of the parity file.
+pub fn write_parity(path: &impl AsRef<Path>, percent: u32) -> Result<u64, KaguyaError> {
+    let parity_shards = (DATA_SHARDS * percent as usize)
+        .div_ceil(100)
+        .clamp(1, DATA_SHARDS);
+    write_parity_file(path.as_ref(), parity_shards)
+}
+
+/// Compute the parity of `path` again, with the redundancy of its current parity file.
+/// Returns `false` if the file has no parity file.
+pub fn refresh_parity(path: &impl AsRef<Path>) -> Result<bool, KaguyaError> {
+    let path = path.as_ref();
+    let parity_path = parity_path(&path);
+    if !parity_path.is_file() {
+        return Ok(false);
+    }
+
+    let layout = read_header(&mut File::open(&parity_path)?)?;
+    write_parity_file(path, layout.parity_shards)?;
+    Ok(true)
+}
+
+/// Compare the file `path` and its parity file with their recorded shard hashes
+pub fn scan_parity(path: &impl AsRef<Path>) -> Result<ParityScan, KaguyaError> {
+    process(path.as_ref(), None)
+}
+
+/// Rebuild the damaged shards of the file `path` from its parity file, and write the
+/// repaired file in place. The parity file is written again if any of it was damaged.
+///
+/// Returns what was damaged before the repair, the file is left untouched if it can't
+/// be repaired.
+pub fn repair_with_parity(path: &impl AsRef<Path>) -> Result<ParityScan, KaguyaError> {
+    let path = path.as_ref();
+    let temp_path = path.with_extension("repair.tmp");
+
+    let repair_result = (|| -> Result<ParityScan, KaguyaError> {
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        let scan = process(path, Some(&mut writer))?;
+        writer.flush()?;
+        Ok(scan)
+    })();
+
+    match repair_result {
+        Ok(scan) if scan.is_repairable() => {
+            if scan.file_damaged() {
+                rename(&temp_path, path)?;
+            } else {
+                remove_file(&temp_path)?;
+            }
+            if scan.damaged_parity > 0 {
+                refresh_parity(&path)?;
+            }
+            Ok(scan)
+        }
+        Ok(scan) => {
+            remove_file(&temp_path).ok();
+            Err(KaguyaError::Parity(format!(
+                "{} stripe(s) have more damage than parity to rebuild them",
+                scan.unrecoverable_stripes
+            )))
+        }
+        Err(e) => {
+            remove_file(&temp_path).ok();
+            Err(e)
+        }
+    }
+}
+
+// Write the parity file of `path` next to its final path, then rename it into place
+fn write_parity_file(path: &Path, parity_shards: usize) -> Result<u64, KaguyaError> {
+    let layout = Layout::new(fs::metadata(path)?.len(), parity_shards);
+    let codec = layout.codec()?;
+    let parity_path = parity_path(&path);
+    let temp_path = parity_path.with_extension("tmp");
+
+    let write_result = (|| -> Result<(), KaguyaError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        writer.write_all(&layout.encode())?;
+
+        for _ in 0..layout.stripes() {
+            let mut shards = read_shards(&mut reader, DATA_SHARDS, layout.shard_size)?;
+            shards.extend((0..layout.parity_shards).map(|_| vec![0; layout.shard_size]));
+            codec
+                .encode(&mut shards)
+                .map_err(|e| KaguyaError::Parity(e.to_string()))?;
+
+            for shard in &shards {
+                writer.write_all(&Sha256::digest(shard))?;
+            }
+            for shard in &shards[DATA_SHARDS..] {
+                writer.write_all(shard)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    })();
+
+    match write_result {
+        Ok(()) => {
+            rename(&temp_path, &parity_path)?;
+            Ok(fs::metadata(&parity_path)?.len())
+        }
+        Err(e) => {
+            remove_file(&temp_path).ok();
+            Err(e)
+        }
+    }
+}
+
+// Check every stripe of `path` against its parity file. With `repaired`, damaged
+// stripes are rebuilt and the whole repaired file is written to it, up to the first
+// stripe that can't be rebuilt.
+fn process(path: &Path, mut repaired: Option<&mut dyn Write>) -> Result<ParityScan, KaguyaError> {
+    let parity_path = parity_path(&path);
+    if !path.is_file() || !parity_path.is_file() {
+        return Err(KaguyaError::PathNotFound(
+            (if path.is_file() { &parity_path } else { path })
+                .to_string_lossy()
+                .to_string(),
+        ));
+    }
+
+    let mut parity = BufReader::new(File::open(&parity_path)?);
+    let layout = read_header(&mut parity)?;
+    let codec = layout.codec()?;
+    let total_shards = DATA_SHARDS + layout.parity_shards;
+
+    let mut scan = ParityScan {
+        size_changed: fs::metadata(path)?.len() != layout.file_size,
+        ..ParityScan::default()
+    };
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut remaining = layout.file_size;
+
+    for _ in 0..layout.stripes() {
+        let data = read_shards(&mut reader, DATA_SHARDS, layout.shard_size)?;
+        let hashes = read_shards(&mut parity, total_shards, HASH_LEN)?;
+        let parity_shards = read_shards(&mut parity, layout.parity_shards, layout.shard_size)?;
+
+        let mut shards: Vec<Option<Vec<u8>>> = data
+            .into_iter()
+            .chain(parity_shards)
+            .zip(&hashes)
+            .map(|(shard, hash)| (Sha256::digest(&shard).as_slice() == hash).then_some(shard))
+            .collect();
+        let damaged_data = shards[..DATA_SHARDS].iter().filter(|s| s.is_none()).count();
+        let damaged = shards.iter().filter(|s| s.is_none()).count();
+        scan.damaged_shards += damaged_data;
+        scan.damaged_parity += damaged - damaged_data;
+        if damaged > layout.parity_shards {
+            scan.unrecoverable_stripes += 1;
+        }
+
+        let Some(writer) = repaired.as_mut() else {
+            continue;
+        };
+        if damaged > layout.parity_shards {
+            break;
+        }
+        if damaged_data > 0 {
+            codec
+                .reconstruct_data(&mut shards)
+                .map_err(|e| KaguyaError::Parity(e.to_string()))?;
+        }
+        for shard in shards[..DATA_SHARDS].iter().flatten() {
+            let len = remaining.min(shard.len() as u64) as usize;
+            writer.write_all(&shard[..len])?;
+            remaining -= len as u64;
+        }
+    }
+
+    Ok(scan)
+}
+
+fn read_header(reader: &mut impl Read) -> Result<Layout, KaguyaError> {
+    let mut header = [0; HEADER_LEN];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| KaguyaError::Parity("the parity file header is truncated".to_string()))?;
+    Layout::decode(&header)
+}
+
+// Read `count` shards of `size` bytes, missing bytes past the end read as zeros
+fn read_shards(
+    reader: &mut impl Read,
+    count: usize,
+    size: usize,
+) -> Result<Vec<Vec<u8>>, KaguyaError> {
+    let mut shards = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut shard = vec![0; size];
+        let mut filled = 0;
+        while filled < size {
+            match reader.read(&mut shard[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        shards.push(shard);
+    }
+    Ok(shards)
+}
@@ -1,26 +1,45 @@
-use crate::fs_utils::archive::decompress_from_tar_gz;
-use crate::models::KaguyaError;
+use crate::fs_utils::metadata::apply_entry_metadata;
+use crate::fs_utils::storage::{BackupFormat, unpack_backup};
+use crate::models::{KaguyaError, db::BackupFileEntry};
 use rand::{Rng, distr::Alphanumeric};
 use scopeguard::defer;
 use std::fs::{create_dir_all, remove_dir_all, remove_file, rename};
 use std::path::Path;
 use std::process;
 
-/// Restore single archive from `src` to `dst`.
+/// Restore single archive, or object manifest, in the given `format` from `src` to `dst`.
 /// Old saves or configurations will be removed.
 ///
 /// This function assumes the archive was created by kaguya, and therefore
 /// its contents are wrapped in a single top-level directory.
 ///
+/// If `expected_checksum` is given, the archive is verified while unpacking
+/// into the staging directory, and `dst` is left untouched on mismatch.
+///
+/// Recorded `entries` metadata (mtimes and permissions) is applied to the restored files,
+/// since the archive itself only holds normalized headers.
+///
 /// Usage:
-/// ```
-/// let src: PathBuf = "~/.local/share/kaguya/vault/backups/2025-12-25_10-00-00/saves.tar.gz"
-/// let dst: PathBuf = "~/games/game-a/saves"
+/// ```no_run
+/// # use kaguya::{fs_utils::{codec::ArchiveCodec, restore::restore_archive, storage::BackupFormat}, utils::path::expand_path};
+/// # fn main() -> Result<(), kaguya::models::KaguyaError> {
+/// # let checksum = "";
+/// # let entries = [];
+/// let src = expand_path("~/.local/share/kaguya/vault/backups/2025-12-25_10-00-00/saves.tar.gz")?;
+/// let dst = expand_path("~/games/game-a/saves")?;
 ///
 /// // Restore to '~/games/game-a/saves'
-/// restore_archive(src, dst)?;
+/// restore_archive(&src, &dst, &BackupFormat::Archive(ArchiveCodec::TarGz), Some(checksum), &entries)?;
+/// # Ok(())
+/// # }
 /// ```
-pub fn restore_archive(src: &impl AsRef<Path>, dst: &impl AsRef<Path>) -> Result<(), KaguyaError> {
+pub fn restore_archive(
+    src: &impl AsRef<Path>,
+    dst: &impl AsRef<Path>,
+    format: &BackupFormat,
+    expected_checksum: Option<&str>,
+    entries: &[BackupFileEntry],
+) -> Result<(), KaguyaError> {
     let src = src.as_ref();
     let dst = dst.as_ref();
 
@@ -48,7 +67,7 @@ pub fn restore_archive(src: &impl AsRef<Path>, dst: &impl AsRef<Path>) -> Result
         ))
     })?);
 
-    decompress_from_tar_gz(&src, &temp_dir)?;
+    unpack_backup(&src, &temp_dir, format, expected_checksum)?;
 
     if dst.exists() {
         if dst.is_dir() {
@@ -63,12 +82,13 @@ pub fn restore_archive(src: &impl AsRef<Path>, dst: &impl AsRef<Path>) -> Result
         }
     }
     rename(unpacked_path, dst)?;
+    apply_entry_metadata(&dst, entries)?;
 
     Ok(())
 }
 
-// Generate a temp name with prefix + process ID + random string
-fn generate_unique_temp_name(prefix: &str, rnd_str_len: u32) -> String {
+/// Generate a temp name with prefix + process ID + random string
+pub fn generate_unique_temp_name(prefix: &str, rnd_str_len: u32) -> String {
     format!(
         "{}-{}-{}",
         prefix,
@@ -0,0 +1,219 @@
+//! Signed version manifests, for tamper evidence.
+//!
+//! With a signing key, the [manifest](super::version_manifest) of every version is
+//! signed with an ed25519 key kept by the user outside the vault. Each signed manifest
+//! also records the hash of the manifest of the previous signed version, so removed or
+//! reordered versions break the chain.
+//!
+//! The signature is stored next to the manifest, as the hex encoded signature of
+//! the exact manifest bytes.
+//!
+//! Everything in the vault can be edited by whoever can write to it, signatures
+//! included. So the public key and the first signed version of every game are also
+//! recorded in a [`SigningRecord`] next to the global config, outside the vault:
+//! a version from then on without a signature, or signed with another key, is reported.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions, create_dir_all, remove_file, rename},
+    io::{ErrorKind, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    fs_utils::version_manifest::{VERSION_MANIFEST_FILE, VersionManifest},
+    models::KaguyaError,
+};
+
+/// Signature of the manifest, inside its version directory
+pub const SIGNATURE_FILE: &str = "kaguya-manifest.toml.sig";
+
+/// Hex encoded public key of a signing key, as recorded in manifests
+pub fn public_key_hex(key: &VerifyingKey) -> String {
+    hex::encode(key.as_bytes())
+}
+
+/// Read a signing key file, holding the hex encoded 32-byte secret key
+pub fn load_signing_key(path: &impl AsRef<Path>) -> Result<SigningKey, KaguyaError> {
+    let path = path.as_ref();
+    if !path.is_file() {
+        return Err(KaguyaError::PathNotFound(
+            path.to_string_lossy().to_string(),
+        ));
+    }
+
+    let invalid =
+        || KaguyaError::Signature(format!("Invalid signing key file '{}'", path.display()));
+    let bytes = hex::decode(fs::read_to_string(path)?.trim()).map_err(|_| invalid())?;
+    let secret: [u8; 32] = bytes.try_into().map_err(|_| invalid())?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// Generate a new signing key and write it to `path`, readable by the owner only.
+/// An existing file is never overwritten.
+pub fn create_signing_key(path: &impl AsRef<Path>) -> Result<SigningKey, KaguyaError> {
+    let key = SigningKey::from_bytes(&rand::random());
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    writeln!(file, "{}", hex::encode(key.to_bytes()))?;
+    file.sync_all()?;
+    Ok(key)
+}
+
+/// Whether the manifest of `version_dir` is signed
+pub fn is_signed(version_dir: &impl AsRef<Path>) -> bool {
+    version_dir.as_ref().join(SIGNATURE_FILE).is_file()
+}
+
+/// Sign the manifest `content` of `version_dir` with `key`, and write the signature
+/// next to its final path, then rename it into place.
+pub fn write_signature(
+    version_dir: &impl AsRef<Path>,
+    content: &str,
+    key: &SigningKey,
+) -> Result<(), KaguyaError> {
+    let path = version_dir.as_ref().join(SIGNATURE_FILE);
+    let temp_path = path.with_extension("sig.tmp");
+    let signature = key.sign(content.as_bytes());
+
+    if let Err(e) = fs::write(
+        &temp_path,
+        format!("{}\n", hex::encode(signature.to_bytes())),
+    ) {
+        remove_file(&temp_path).ok();
+        return Err(e.into());
+    }
+    rename(&temp_path, &path)?;
+    Ok(())
+}
+
+/// Read the manifest in `version_dir` and verify its signature with `key`.
+/// Returns `None` if the version has no manifest.
+pub fn read_signed_manifest(
+    version_dir: &impl AsRef<Path>,
+    key: &VerifyingKey,
+) -> Result<Option<VersionManifest>, KaguyaError> {
+    let version_dir = version_dir.as_ref();
+    let manifest_path = version_dir.join(VERSION_MANIFEST_FILE);
+    if !manifest_path.is_file() {
+        return Ok(None);
+    }
+    let signature_path = version_dir.join(SIGNATURE_FILE);
+    if !signature_path.is_file() {
+        return Err(KaguyaError::Signature(
+            "the manifest signature is missing".to_string(),
+        ));
+    }
+
+    let content = fs::read(&manifest_path)?;
+    let signature = hex::decode(fs::read_to_string(&signature_path)?.trim())
+        .ok()
+        .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+        .map(|bytes| Signature::from_bytes(&bytes))
+        .ok_or_else(|| KaguyaError::Signature("the manifest signature is malformed".to_string()))?;
+    key.verify(&content, &signature)
+        .map_err(|_| KaguyaError::Signature("the manifest signature doesn't verify".to_string()))?;
+
+    let manifest: VersionManifest = toml::from_str(&String::from_utf8_lossy(&content))?;
+    let signer = public_key_hex(key);
+    if manifest.signer.as_ref() != Some(&signer) {
+        return Err(KaguyaError::Signature(format!(
+            "the manifest names another signer '{}'",
+            manifest.signer.unwrap_or_default()
+        )));
+    }
+
+    Ok(Some(manifest))
+}
+
+/// Signing of every vault, kept outside the vaults next to the global config
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SigningRecord {
+    #[serde(default)]
+    pub vaults: Vec<VaultSigning>,
+}
+
+/// Signing of a single vault
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VaultSigning {
+    /// Absolute path of the vault directory
+    pub vault: PathBuf,
+    /// Hex encoded public key every signed manifest of the vault is signed with
+    pub public_key: String,
+    /// First signed version of every game, later versions must all be signed
+    #[serde(default)]
+    pub games: BTreeMap<String, String>,
+}
+
+impl SigningRecord {
+    /// Read the record at `path`, an empty one if it doesn't exist
+    pub fn load(path: &impl AsRef<Path>) -> Result<Self, KaguyaError> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the record to `path`, next to it first and then renamed into place
+    pub fn save(&self, path: &impl AsRef<Path>) -> Result<(), KaguyaError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let temp_path = path.with_extension("toml.tmp");
+        if let Err(e) = fs::write(&temp_path, toml::to_string_pretty(self)?) {
+            remove_file(&temp_path).ok();
+            return Err(e.into());
+        }
+        rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Signing of the vault at `vault_dir`, `None` if it was never signed
+    pub fn vault(&self, vault_dir: &Path) -> Option<&VaultSigning> {
+        self.vaults.iter().find(|vault| vault.vault == vault_dir)
+    }
+
+    /// Signing of the vault at `vault_dir`, recorded with `public_key` if it was never signed
+    pub fn vault_mut(&mut self, vault_dir: &Path, public_key: &str) -> &mut VaultSigning {
+        match self
+            .vaults
+            .iter()
+            .position(|vault| vault.vault == vault_dir)
+        {
+            Some(index) => &mut self.vaults[index],
+            None => {
+                self.vaults.push(VaultSigning {
+                    vault: vault_dir.to_path_buf(),
+                    public_key: public_key.to_string(),
+                    games: BTreeMap::new(),
+                });
+                self.vaults
+                    .last_mut()
+                    .expect("Vault should be just pushed.")
+            }
+        }
+    }
+}
+
+impl VaultSigning {
+    /// Fails if `key` is not the key the vault was signed with
+    pub fn check_key(&self, key: &VerifyingKey) -> Result<(), KaguyaError> {
+        let public_key = public_key_hex(key);
+        if public_key != self.public_key {
+            return Err(KaguyaError::Signature(format!(
+                "the vault was signed with the key {}, but 'signing_key' is the key {}",
+                self.public_key, public_key
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+//! Storage modes of backups: compressed archives, the content-addressed object store
+//! with whole files or content-defined chunks as objects, hard-link mirrors,
+//! or binary delta chains
+
+use std::{fmt, io, path::Path, str::FromStr};
+
+use crate::{
+    fs_utils::{
+        archive::decompress_archive,
+        codec::ArchiveCodec,
+        crypto::{DataKey, verify_encrypted},
+        delta::{DeltaFile, unpack_delta, verify_delta},
+        hash::calculate_entry_checksum,
+        mirror::{copy_mirror, verify_mirror},
+        objects::{ObjectManifest, ObjectStore, unpack_manifest},
+    },
+    models::KaguyaError,
+};
+
+/// Codec name recorded in `backup_file.codec` for object manifests
+pub const OBJECTS_CODEC: &str = "objects";
+
+/// Codec name recorded in `backup_file.codec` for manifests of chunked files
+pub const CHUNKS_CODEC: &str = "chunks";
+
+/// Codec name recorded in `backup_file.codec` for hard-link mirrors
+pub const MIRROR_CODEC: &str = "mirror";
+
+/// Codec name recorded in `backup_file.codec` for binary delta files
+pub const DELTA_CODEC: &str = "delta";
+
+/// How new backups of a game are stored in the vault
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageMode {
+    /// A full archive of every changed path per version
+    #[default]
+    Archive,
+    /// Files stored once in the object store, with a manifest per version
+    Objects,
+    /// Like `Objects`, with files split into content-defined chunks
+    Chunks,
+    /// A plain copy of every path per version, unchanged files hard-linked to the previous one
+    Mirror,
+    /// A binary delta against the previous version per path, with periodic keyframes
+    Delta,
+}
+
+impl StorageMode {
+    pub const ALL: [StorageMode; 5] = [
+        Self::Archive,
+        Self::Objects,
+        Self::Chunks,
+        Self::Mirror,
+        Self::Delta,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Archive => "archive",
+            Self::Objects => OBJECTS_CODEC,
+            Self::Chunks => CHUNKS_CODEC,
+            Self::Mirror => MIRROR_CODEC,
+            Self::Delta => DELTA_CODEC,
+        }
+    }
+}
+
+impl fmt::Display for StorageMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for StorageMode {
+    type Err = KaguyaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|m| m.name()).collect();
+                KaguyaError::InvalidInput(format!(
+                    "Unsupported storage mode '{}', expected one of: {}",
+                    s,
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+/// Format of a single stored backup file, resolved from its recorded codec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackupFormat {
+    Archive(ArchiveCodec),
+    /// Archive encrypted with the vault key
+    EncryptedArchive(ArchiveCodec, DataKey),
+    /// Manifest of objects or chunks in the given store
+    Objects(ObjectStore),
+    /// Plain directory tree or file
+    Mirror,
+    /// Delta file on top of the given chain of bases, keyframe first
+    Delta(Vec<DeltaFile>),
+}
+
+impl BackupFormat {
+    /// Format of an existing backup file, see [`ArchiveCodec::resolve`] for archives.
+    /// Delta files need their chain of bases from the DB, and encrypted archives the vault key,
+    /// so they are not resolved here.
+    pub fn resolve(
+        recorded: Option<&str>,
+        archive_path: &impl AsRef<Path>,
+        store: &ObjectStore,
+    ) -> Result<Self, KaguyaError> {
+        match recorded {
+            Some(OBJECTS_CODEC | CHUNKS_CODEC) => Ok(Self::Objects(store.clone())),
+            Some(MIRROR_CODEC) => Ok(Self::Mirror),
+            Some(DELTA_CODEC) => Err(KaguyaError::InvalidInput(
+                "Delta backup files are resolved with their chain of bases".to_string(),
+            )),
+            _ => Ok(Self::Archive(ArchiveCodec::resolve(
+                recorded,
+                archive_path,
+            )?)),
+        }
+    }
+}
+
+/// Unpack a backup file of any format into the directory `dst`,
+/// verifying it against `expected_checksum` if given.
+pub fn unpack_backup(
+    src: &impl AsRef<Path>,
+    dst: &impl AsRef<Path>,
+    format: &BackupFormat,
+    expected_checksum: Option<&str>,
+) -> Result<(), KaguyaError> {
+    match format {
+        BackupFormat::Archive(codec) => {
+            decompress_archive(src, dst, *codec, expected_checksum, None)
+        }
+        BackupFormat::EncryptedArchive(codec, key) => {
+            decompress_archive(src, dst, *codec, expected_checksum, Some(key))
+        }
+        BackupFormat::Objects(store) => unpack_manifest(src, store, dst, expected_checksum),
+        BackupFormat::Mirror => copy_mirror(src, dst, expected_checksum),
+        BackupFormat::Delta(bases) => unpack_delta(src, bases, dst, expected_checksum),
+    }
+}
+
+/// Verify a backup file of any format against its recorded checksum without unpacking it.
+/// Objects referenced by a manifest are checked to exist and match their hashes,
+/// delta files are reconstructed from their whole chain, and every chunk of
+/// encrypted archives is authenticated.
+pub fn verify_backup(
+    src: &impl AsRef<Path>,
+    format: &BackupFormat,
+    expected_checksum: &str,
+) -> Result<(), KaguyaError> {
+    let src = src.as_ref();
+    match format {
+        BackupFormat::Archive(_) => verify_archive(src, expected_checksum),
+        BackupFormat::EncryptedArchive(_, key) => {
+            verify_archive(src, expected_checksum)?;
+            verify_encrypted(&src, key)
+        }
+        BackupFormat::Objects(store) => {
+            let manifest = ObjectManifest::read(&src, Some(expected_checksum))?;
+            for (object, _) in manifest.objects() {
+                store.read_object(object, &mut io::sink())?;
+            }
+            Ok(())
+        }
+        BackupFormat::Mirror => verify_mirror(&src, Some(expected_checksum)),
+        BackupFormat::Delta(bases) => verify_delta(&src, bases, expected_checksum),
+    }
+}
+
+// Verify an archive file against its recorded checksum
+fn verify_archive(src: &Path, expected_checksum: &str) -> Result<(), KaguyaError> {
+    if !src.is_file() {
+        return Err(KaguyaError::PathNotFound(src.to_string_lossy().to_string()));
+    }
+    if calculate_entry_checksum(src)? != expected_checksum {
+        return Err(KaguyaError::ChecksumMismatch(
+            src.to_string_lossy().to_string(),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Self-describing manifests of backup versions.
+//!
+//! Every version directory holds a `kaguya-manifest.toml` describing the game and
+//! every file of the version: the original path it was backed up from, the name of
+//! its archive, checksums and codec. The database is only an index of the vault,
+//! with the manifests the archives can still be restored to the right places.
+//!
+//! Manifests are signed if the vault has a signing key, see [`signature`](super::signature).
+
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, remove_file, rename},
+    path::Path,
+};
+
+use crate::{
+    fs_utils::signature::write_signature,
+    models::{KaguyaError, db::BackupFile},
+};
+
+/// Manifest file of a version, inside its version directory
+pub const VERSION_MANIFEST_FILE: &str = "kaguya-manifest.toml";
+
+/// Game and files of a backup version
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionManifest {
+    pub game_id: String,
+    #[serde(default)]
+    pub name: String,
+    pub version: String,
+    pub timestamp: String,
+    /// Version of kaguya that wrote the manifest
+    #[serde(default)]
+    pub kaguya_version: String,
+    /// Hash of the manifest of the previous signed version, `None` for the first one
+    /// or an unsigned manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+    /// Public key the manifest is signed with, hex encoded, `None` if it is not signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+    #[serde(default)]
+    pub files: Vec<ManifestEntry>,
+}
+
+/// A backup file listed in a [`VersionManifest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub original_path: String,
+    /// File name of the archive, mirror or object manifest in the version directory
+    pub archive: String,
+    pub size_bytes: i64,
+    pub checksum: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_checksum: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_size_bytes: Option<i64>,
+    /// Position of a delta file in its chain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta: Option<ManifestDelta>,
+}
+
+/// Chain position of a delta file listed in a [`VersionManifest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestDelta {
+    /// Base of the delta, as '<version>/<file name>' in the game directory,
+    /// `None` for a keyframe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<String>,
+    pub depth: u32,
+    pub keyframe_interval: u32,
+}
+
+impl ManifestEntry {
+    pub fn new(
+        backup_file: &BackupFile,
+        delta: Option<ManifestDelta>,
+    ) -> Result<Self, KaguyaError> {
+        let archive = Path::new(&backup_file.archive_path)
+            .file_name()
+            .ok_or_else(|| KaguyaError::FileNameError(backup_file.archive_path.clone()))?;
+
+        Ok(Self {
+            original_path: backup_file.original_path.clone(),
+            archive: archive.to_string_lossy().to_string(),
+            size_bytes: backup_file.size_bytes,
+            checksum: backup_file.checksum.clone(),
+            source_checksum: backup_file.source_checksum.clone(),
+            codec: backup_file.codec.clone(),
+            original_size_bytes: backup_file.original_size_bytes,
+            delta,
+        })
+    }
+
+    /// Whether this listed entry describes the `recorded` one.
+    /// Fields a manifest written by an older version doesn't have are not compared.
+    pub fn matches(&self, recorded: &ManifestEntry) -> bool {
+        self.original_path == recorded.original_path
+            && self.archive == recorded.archive
+            && self.size_bytes == recorded.size_bytes
+            && self.checksum == recorded.checksum
+            && self.source_checksum == recorded.source_checksum
+            && self.codec == recorded.codec
+            && (self.original_size_bytes.is_none()
+                || self.original_size_bytes == recorded.original_size_bytes)
+            && (self.delta.is_none() || self.delta == recorded.delta)
+    }
+}
+
+/// Write the manifest of `version_dir`, signed with `key` if given.
+/// Files are written next to their final paths, then renamed into place.
+///
+/// Returns the hash of the written manifest.
+pub fn write_version_manifest(
+    version_dir: &impl AsRef<Path>,
+    manifest: &VersionManifest,
+    key: Option<&SigningKey>,
+) -> Result<String, KaguyaError> {
+    let version_dir = version_dir.as_ref();
+    let content = toml::to_string(manifest)?;
+    if let Some(key) = key {
+        write_signature(&version_dir, &content, key)?;
+    }
+
+    let path = version_dir.join(VERSION_MANIFEST_FILE);
+    let temp_path = path.with_extension("toml.tmp");
+    if let Err(e) = fs::write(&temp_path, &content) {
+        remove_file(&temp_path).ok();
+        return Err(e.into());
+    }
+    rename(&temp_path, &path)?;
+
+    Ok(hex::encode(Sha256::digest(content.as_bytes())))
+}
+
+/// Read the manifest of `version_dir`, `None` if the version has none
+pub fn read_version_manifest(
+    version_dir: &impl AsRef<Path>,
+) -> Result<Option<VersionManifest>, KaguyaError> {
+    let path = version_dir.as_ref().join(VERSION_MANIFEST_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    Ok(Some(toml::from_str(&fs::read_to_string(path)?)?))
+}
+
+/// Hash of the manifest of `version_dir`, `None` if the version has none
+pub fn manifest_hash(version_dir: &impl AsRef<Path>) -> Result<Option<String>, KaguyaError> {
+    let path = version_dir.as_ref().join(VERSION_MANIFEST_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    Ok(Some(hex::encode(Sha256::digest(fs::read(path)?))))
+}
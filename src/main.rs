@@ -20,6 +20,10 @@ fn main() -> Result<(), KaguyaError> {
         Commands::Vault(subcommand) => {
             cli::handle_vault(subcommand, &context)?;
         }
+
+        Commands::Db(subcommand) => {
+            cli::handle_db(subcommand, &context)?;
+        }
     }
 
     Ok(())
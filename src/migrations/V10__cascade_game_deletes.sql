@@ -0,0 +1,53 @@
+-- =====================================
+-- Kaguya Database Migration
+-- Version: 10
+-- =====================================
+
+-- Deleting a game deletes its backups and events in a cascade, and deleting a backup
+-- deletes its events, so pruning a game from the vault config leaves no orphan rows.
+-- SQLite can't alter a foreign key, so both tables are created again and filled with
+-- their rows, the migration runner disables foreign keys meanwhile.
+CREATE TABLE backup_new (
+    id INTEGER PRIMARY KEY,
+    game_id INTEGER NOT NULL,                         -- Associated game ID
+    version TEXT NOT NULL,                            -- Backup version (A formatted local time string, e.g., 2025-12-25_10-00-00)
+    timestamp TEXT NOT NULL,                          -- Timestamp of when the backup was created
+
+    -- Foreign key constraint: If a game is deleted, all its backups are also deleted in a cascade.
+    -- Backup files in the vault are kept, 'vault reindex' indexes them again.
+    FOREIGN KEY (game_id) REFERENCES game(id) ON DELETE CASCADE,
+
+    -- Composite unique constraint: Ensures that the version string is unique for each game.
+    UNIQUE(game_id, version)
+);
+
+INSERT INTO backup_new (id, game_id, version, timestamp)
+    SELECT id, game_id, version, timestamp FROM backup;
+DROP TABLE backup;
+ALTER TABLE backup_new RENAME TO backup;
+
+CREATE INDEX idx_backup_game_id ON backup(game_id);
+CREATE INDEX idx_backup_game_version ON backup(game_id, version);
+
+CREATE TABLE event_new (
+    id INTEGER PRIMARY KEY,
+    event_type TEXT NOT NULL,                         -- Type of the event (e.g., 'backup', 'restore', 'prune')
+    game_id INTEGER NOT NULL,                         -- Associated game ID
+    backup_id INTEGER,                                -- Associated backup ID (can be NULL, as not all events are related to a specific backup)
+    timestamp TEXT NOT NULL,                          -- Timestamp of when the event occurred
+
+    -- Foreign key constraint: Events of a deleted game or backup are also deleted in a cascade.
+    FOREIGN KEY (game_id) REFERENCES game(id) ON DELETE CASCADE,
+    FOREIGN KEY (backup_id) REFERENCES backup(id) ON DELETE CASCADE
+);
+
+INSERT INTO event_new (id, event_type, game_id, backup_id, timestamp)
+    SELECT id, event_type, game_id, backup_id, timestamp FROM event;
+DROP TABLE event;
+ALTER TABLE event_new RENAME TO event;
+
+CREATE INDEX idx_event_game_id ON event(game_id);
+CREATE INDEX idx_event_backup_id ON event(backup_id);
+CREATE INDEX idx_event_timestamp ON event(timestamp);
+
+UPDATE meta SET value = '10' WHERE key = 'schema_version';
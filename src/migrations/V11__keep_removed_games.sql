@@ -0,0 +1,34 @@
+-- =====================================
+-- Kaguya Database Migration
+-- Version: 11
+-- =====================================
+
+-- A game removed from the vault config is marked removed instead of being deleted, so its
+-- backups stay indexed, and its objects referenced, until they are purged explicitly.
+ALTER TABLE game ADD COLUMN removed_at TEXT;          -- When the game was removed from the vault config, NULL while it's in it
+
+-- Backups no longer go away with their game, purging a game deletes them first.
+-- SQLite can't alter a foreign key, so the table is created again and filled with
+-- its rows, the migration runner disables foreign keys meanwhile.
+CREATE TABLE backup_new (
+    id INTEGER PRIMARY KEY,
+    game_id INTEGER NOT NULL,                         -- Associated game ID
+    version TEXT NOT NULL,                            -- Backup version (A formatted local time string, e.g., 2025-12-25_10-00-00)
+    timestamp TEXT NOT NULL,                          -- Timestamp of when the backup was created
+
+    -- Foreign key constraint: If a game is deleted, all its backups are NOT deleted in a cascade.
+    FOREIGN KEY (game_id) REFERENCES game(id),
+
+    -- Composite unique constraint: Ensures that the version string is unique for each game.
+    UNIQUE(game_id, version)
+);
+
+INSERT INTO backup_new (id, game_id, version, timestamp)
+    SELECT id, game_id, version, timestamp FROM backup;
+DROP TABLE backup;
+ALTER TABLE backup_new RENAME TO backup;
+
+CREATE INDEX idx_backup_game_id ON backup(game_id);
+CREATE INDEX idx_backup_game_version ON backup(game_id, version);
+
+UPDATE meta SET value = '11' WHERE key = 'schema_version';
@@ -0,0 +1,11 @@
+-- =====================================
+-- Kaguya Database Migration
+-- Version: 2
+-- =====================================
+
+-- Checksum of the original save file or directory at the time of backup.
+-- Used by 'vault status' to detect saves modified since the latest backup.
+-- NULL for backups created before this column existed.
+ALTER TABLE backup_file ADD COLUMN source_checksum TEXT;
+
+UPDATE meta SET value = '2' WHERE key = 'schema_version';
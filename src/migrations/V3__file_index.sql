@@ -0,0 +1,23 @@
+-- =====================================
+-- Kaguya Database Migration
+-- Version: 3
+-- =====================================
+
+-- Caches the content hash of every file under a configured path.
+-- A file whose size, mtime and inode are unchanged reuses its cached hash,
+-- so change detection does not have to re-read large save directories.
+CREATE TABLE file_index (
+    id INTEGER PRIMARY KEY,
+    root_path TEXT NOT NULL,                          -- The configured save file or directory
+    rel_path TEXT NOT NULL,                           -- Path relative to root_path ('' if root_path is a file)
+    size_bytes INTEGER NOT NULL,                      -- File size when hashed
+    mtime_ns INTEGER NOT NULL,                        -- Modification time in nanoseconds when hashed
+    inode INTEGER NOT NULL,                           -- Inode number when hashed
+    hash TEXT NOT NULL,                               -- SHA-256 of the file content
+
+    UNIQUE(root_path, rel_path)
+);
+
+CREATE INDEX idx_file_index_root_path ON file_index(root_path);
+
+UPDATE meta SET value = '3' WHERE key = 'schema_version';
@@ -0,0 +1,11 @@
+-- =====================================
+-- Kaguya Database Migration
+-- Version: 4
+-- =====================================
+
+-- Archive format of each backup file (e.g., 'tar.gz', 'tar.zst'),
+-- so old archives stay restorable after the compression setting changes.
+-- NULL for backups created before this column existed, which are always 'tar.gz'.
+ALTER TABLE backup_file ADD COLUMN codec TEXT;
+
+UPDATE meta SET value = '4' WHERE key = 'schema_version';
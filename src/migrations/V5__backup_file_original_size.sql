@@ -0,0 +1,10 @@
+-- =====================================
+-- Kaguya Database Migration
+-- Version: 5
+-- =====================================
+
+-- Total size of the original files, to report the compression ratio of each backup file.
+-- NULL for backups created before this column existed.
+ALTER TABLE backup_file ADD COLUMN original_size_bytes INTEGER;
+
+UPDATE meta SET value = '5' WHERE key = 'schema_version';
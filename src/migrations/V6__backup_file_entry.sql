@@ -0,0 +1,24 @@
+-- =====================================
+-- Kaguya Database Migration
+-- Version: 6
+-- =====================================
+
+-- Records the real metadata of every file and directory inside a backup file.
+-- Archives are written with normalized headers to be reproducible,
+-- so modification times and permissions are restored from here.
+CREATE TABLE backup_file_entry (
+    id INTEGER PRIMARY KEY,
+    backup_file_id INTEGER NOT NULL,                  -- Associated backup file ID
+    rel_path TEXT NOT NULL,                           -- Path relative to the original path ('' for itself)
+    mtime_ns INTEGER NOT NULL,                        -- Modification time in nanoseconds since the Unix epoch
+    mode INTEGER NOT NULL,                            -- Unix permission bits
+
+    -- Foreign key constraint: If a backup file is deleted, its entries are also deleted in a cascade.
+    FOREIGN KEY (backup_file_id) REFERENCES backup_file(id) ON DELETE CASCADE,
+
+    UNIQUE(backup_file_id, rel_path)
+);
+
+CREATE INDEX idx_backup_file_entry_backup_file_id ON backup_file_entry(backup_file_id);
+
+UPDATE meta SET value = '6' WHERE key = 'schema_version';
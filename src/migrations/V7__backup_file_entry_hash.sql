@@ -0,0 +1,10 @@
+-- =====================================
+-- Kaguya Database Migration
+-- Version: 7
+-- =====================================
+
+-- SHA-256 of each file inside a backup file, NULL for directories.
+-- Lets mirror backups hard-link files unchanged since the previous version.
+ALTER TABLE backup_file_entry ADD COLUMN hash TEXT;
+
+UPDATE meta SET value = '7' WHERE key = 'schema_version';
@@ -0,0 +1,22 @@
+-- =====================================
+-- Kaguya Database Migration
+-- Version: 8
+-- =====================================
+
+-- Records the position of every delta backup file in its chain.
+-- A delta file can only be reconstructed on top of its base, so prune rebases
+-- the successor of a deleted file, and 'vault check' validates the chain from here.
+CREATE TABLE backup_file_delta (
+    backup_file_id INTEGER PRIMARY KEY,               -- Associated backup file ID
+    base_file_id INTEGER,                             -- Backup file this one is a delta against, NULL for a keyframe
+    depth INTEGER NOT NULL,                           -- Number of deltas since the keyframe (0 for the keyframe)
+    keyframe_interval INTEGER NOT NULL,               -- Keyframe interval in effect when the file was stored
+
+    -- Foreign key constraint: If a backup file is deleted, its chain link is also deleted in a cascade.
+    FOREIGN KEY (backup_file_id) REFERENCES backup_file(id) ON DELETE CASCADE,
+    FOREIGN KEY (base_file_id) REFERENCES backup_file(id)
+);
+
+CREATE INDEX idx_backup_file_delta_base_file_id ON backup_file_delta(base_file_id);
+
+UPDATE meta SET value = '8' WHERE key = 'schema_version';
@@ -0,0 +1,32 @@
+//! Integrity of backups, produced by `kaguya vault check` and `kaguya vault repair`
+
+/// Result of verifying a single backup file
+#[derive(Debug)]
+pub struct BackupCheck {
+    pub game_id: String,
+    pub version: String,
+    pub original_path: String,
+    pub archive_path: String,
+    /// What is wrong with the backup file, `None` if it is intact
+    pub problem: Option<String>,
+}
+
+/// Result of repairing a damaged backup file from its parity
+#[derive(Debug)]
+pub struct BackupRepair {
+    pub game_id: String,
+    pub version: String,
+    pub original_path: String,
+    pub archive_path: String,
+    pub outcome: RepairOutcome,
+}
+
+#[derive(Debug)]
+pub enum RepairOutcome {
+    /// Damaged blocks of the file were rebuilt
+    Repaired { blocks: usize },
+    /// The file was intact, only its damaged parity was written again
+    ParityRewritten,
+    /// The file could not be repaired
+    Failed(String),
+}
@@ -0,0 +1,42 @@
+//! Problems of the vault config, found by `kaguya config check`
+
+/// Result of checking every game of the vault config
+#[derive(Debug, Default)]
+pub struct ConfigCheckReport {
+    pub games: usize,
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl ConfigCheckReport {
+    pub fn errors(&self) -> usize {
+        self.count(IssueLevel::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(IssueLevel::Warning)
+    }
+
+    fn count(&self, level: IssueLevel) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| issue.level == level)
+            .count()
+    }
+}
+
+/// A problem of the vault config, with what to do about it
+#[derive(Debug)]
+pub struct ConfigIssue {
+    pub level: IssueLevel,
+    /// Game the problem is about, `None` for a file of the vault config that can't be read
+    pub game_id: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueLevel {
+    /// Backups work, but likely not as intended
+    Warning,
+    /// Backups of the game fail or are unsafe
+    Error,
+}
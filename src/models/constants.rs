@@ -1,11 +1,22 @@
 pub const DEFAULT_CONFIG_DIR: &str = "kaguya";
 pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+pub const SIGNING_RECORD_FILE: &str = "signatures.toml";
 pub const DEFAULT_VAULT_DIR: &str = "kaguya";
 pub const DEFAULT_VAULT_SUBDIR: &str = "vault";
 
 pub const VAULT_CONFIG_FILE: &str = "vault.toml";
+pub const GAMES_DIR: &str = "games.d";
 pub const DB_FILE: &str = "kaguya.db";
+pub const LOCK_FILE: &str = "kaguya.lock";
 pub const BACKUP_DIR: &str = "backups";
+pub const OBJECTS_DIR: &str = "objects";
+pub const KEY_ENVELOPE_FILE: &str = "kaguya-key.toml";
 
 pub const KEY_VAULT_CONFIG_HASH: &str = "vault_config_hash";
 pub const KEY_SCHEMA_VERSION: &str = "schema_version";
+pub const KEY_ENCRYPTION: &str = "encryption_key";
+
+pub const PASSPHRASE_ENV: &str = "KAGUYA_PASSPHRASE";
+pub const NEW_PASSPHRASE_ENV: &str = "KAGUYA_NEW_PASSPHRASE";
+pub const VISUAL_ENV: &str = "VISUAL";
+pub const EDITOR_ENV: &str = "EDITOR";
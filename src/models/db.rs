@@ -11,6 +11,8 @@ pub struct Game {
     pub keep_versions: Option<i64>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// When the game was removed from the vault config, its backups are kept until purged
+    pub removed_at: Option<DateTime<Utc>>,
 }
 
 impl From<&GameConfig> for Game {
@@ -26,6 +28,7 @@ impl From<&GameConfig> for Game {
             keep_versions: game.keep_versions,
             created_at,
             updated_at,
+            removed_at: None,
         }
     }
 }
@@ -53,14 +56,54 @@ pub struct Backup {
     pub timestamp: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct BackupFile {
     pub id: i64,
     pub backup_id: i64,
     pub original_path: String,
+    /// Archive file, or object manifest, in the vault
     pub archive_path: String,
+    /// Size of the archive, or of the manifest and the new objects it added to the store
     pub size_bytes: i64,
     pub checksum: String,
+    pub source_checksum: Option<String>,
+    pub codec: Option<String>,
+    /// Total size of the backed up files, `None` for backups made before it was recorded
+    pub original_size_bytes: Option<i64>,
+}
+
+impl BackupFile {
+    /// Archive size as a percentage of the original size
+    pub fn compression_ratio(&self) -> Option<f64> {
+        match self.original_size_bytes {
+            Some(original) if original > 0 => {
+                Some(self.size_bytes as f64 / original as f64 * 100.0)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Real metadata of a file or directory inside a backup file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupFileEntry {
+    /// Path relative to the original path, '' for the original path itself
+    pub rel_path: String,
+    pub mtime_ns: i64,
+    pub mode: u32,
+    /// SHA-256 of the file content, `None` for directories
+    pub hash: Option<String>,
+}
+
+/// Position of a delta backup file in its chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaLink {
+    /// Backup file this one is a delta against, `None` for a keyframe
+    pub base_file_id: Option<i64>,
+    /// Number of deltas since the keyframe, 0 for the keyframe itself
+    pub depth: u32,
+    /// Keyframe interval in effect when the file was stored
+    pub keyframe_interval: u32,
 }
 
 #[derive(Debug)]
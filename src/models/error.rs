@@ -13,6 +13,10 @@ pub enum KaguyaError {
     #[error("Could not parse config file: {0}")]
     TomlParseError(#[from] toml::de::Error),
 
+    /// Represents an error from parsing a TOML configuration file to edit it.
+    #[error("Could not parse config file: {0}")]
+    TomlEditError(#[from] toml_edit::TomlError),
+
     /// Represents an error from serializing to TOML.
     #[error("Could not serialize data to TOML: {0}")]
     TomlSerializeError(#[from] toml::ser::Error),
@@ -25,6 +29,10 @@ pub enum KaguyaError {
     #[error("Cound not determine file name from {0}")]
     FileNameError(String),
 
+    /// Represents an error from reading or writing a zip archive.
+    #[error("Zip archive error: {0}")]
+    Zip(#[from] zip::result::ZipError),
+
     #[error("Database error: {0}")]
     Database(#[from] rusqlite::Error),
 
@@ -35,6 +43,47 @@ pub enum KaguyaError {
     #[error("Backup with ID '{0}' not found")]
     BackupNotFound(i64),
 
+    /// An archive in the vault doesn't match the checksum recorded at backup time.
+    #[error("Checksum mismatch, archive may be corrupted: {0}")]
+    ChecksumMismatch(String),
+
+    /// `vault check` found damaged or missing backups.
+    #[error("Vault check found {0} problem(s)")]
+    CheckFailed(usize),
+
+    /// `config check` found errors in the vault config.
+    #[error("Config check found {0} error(s)")]
+    ConfigCheckFailed(usize),
+
+    /// Encrypting or decrypting backups failed, or the vault key is unusable.
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+
+    /// A parity file is unusable, or has too little parity left to repair its file.
+    #[error("Parity error: {0}")]
+    Parity(String),
+
+    /// `vault repair` could not repair some backups.
+    #[error("Vault repair could not repair {0} file(s)")]
+    RepairFailed(usize),
+
+    /// A signed version manifest is missing, malformed or doesn't verify.
+    #[error("Signature error: {0}")]
+    Signature(String),
+
+    /// The database was created or migrated by a newer kaguya.
+    #[error(
+        "Database schema version {0} is newer than version {1} supported by this kaguya, upgrade kaguya to use this vault"
+    )]
+    SchemaTooNew(u32, u32),
+
+    /// Another kaguya run holds the vault lock.
+    #[error("Vault is in use by {0}, try again once it finishes")]
+    VaultLocked(String),
+
+    #[error("Wrong passphrase, could not unlock the vault key")]
+    WrongPassphrase,
+
     #[error("No paths configured for game with external_id '{0}'")]
     NoPathsConfigured(String),
 
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{fmt, path::PathBuf};
 
 /// Represents a single event that occurred during a backup operation.
 #[derive(Debug, Clone)]
@@ -15,9 +15,12 @@ pub enum BackupEvent {
         original_path: PathBuf,
         archive_path: PathBuf,
         size_bytes: u64,
+        codec: String,
+        /// Archive size as a percentage of the original size
+        compression_ratio: Option<f64>,
     },
 
-    /// A file was skipped because it was not found.
+    /// A file was skipped because it was not found or has not changed.
     FileSkipped {
         original_path: PathBuf,
         reason: String,
@@ -28,3 +31,52 @@ pub enum BackupEvent {
         error_string: String,
     },
 }
+
+impl fmt::Display for BackupEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Created {
+                external_id,
+                total_files,
+                total_size_bytes,
+            } => write!(
+                f,
+                "Created backup of '{}' with {} file(s), {} bytes",
+                external_id, total_files, total_size_bytes
+            ),
+            Self::FileBackedUp {
+                original_path,
+                archive_path,
+                size_bytes,
+                codec,
+                compression_ratio,
+            } => {
+                write!(
+                    f,
+                    "Backed up '{}' to '{}' ({}, {} bytes",
+                    original_path.display(),
+                    archive_path.display(),
+                    codec,
+                    size_bytes
+                )?;
+                match compression_ratio {
+                    Some(ratio) => write!(f, ", {:.1}% of original)", ratio),
+                    None => write!(f, ")"),
+                }
+            }
+            Self::FileSkipped {
+                original_path,
+                reason,
+            } => write!(f, "Skipped '{}': {}", original_path.display(), reason),
+            Self::Error {
+                original_path,
+                error_string,
+            } => write!(
+                f,
+                "Failed to back up '{}': {}",
+                original_path.display(),
+                error_string
+            ),
+        }
+    }
+}
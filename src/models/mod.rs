@@ -1,15 +1,27 @@
 //! Config / Request / Service struct, constants, and custom error type
 
+pub use check::{BackupCheck, BackupRepair, RepairOutcome};
+pub use config_check::{ConfigCheckReport, ConfigIssue, IssueLevel};
 pub use constants::*;
 pub use db::{Game, GamePath};
 pub use error::KaguyaError;
-pub use requests::{AddGameRequest, BackupRequest, ListGameRequest, RmGameRequest};
-pub use vault_config::{GameConfig, VaultConfig};
+pub use reindex::{GameReindex, ReindexIssue, ReindexReport};
+pub use requests::{
+    AddGameRequest, BackupRequest, EditConfigRequest, ListGameRequest, RmGameRequest, StatusRequest,
+};
+pub use stats::{GameStats, VaultStats};
+pub use status::{GameStatus, PathStatus, PathStatusKind};
+pub use vault_config::{BackupSettings, GameConfig, VaultConfig};
 
+pub mod check;
+pub mod config_check;
 pub mod constants;
 pub mod db;
 pub mod error;
 pub mod events;
 pub mod global_config;
+pub mod reindex;
 pub mod requests;
+pub mod stats;
+pub mod status;
 pub mod vault_config;
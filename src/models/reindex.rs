@@ -0,0 +1,48 @@
+//! Backup records rebuilt from the vault directory, produced by `kaguya vault reindex`
+
+use std::path::PathBuf;
+
+/// Outcome of rebuilding the backup records of the vault
+#[derive(Debug, Default)]
+pub struct ReindexReport {
+    pub games: Vec<GameReindex>,
+    /// Files and directories that could not be attributed to a game, version or path
+    pub unattributed: Vec<ReindexIssue>,
+    /// Backup files that were indexed, but don't match their version manifest
+    pub mismatched: Vec<ReindexIssue>,
+}
+
+/// Records rebuilt for a single game
+#[derive(Debug)]
+pub struct GameReindex {
+    pub id: String,
+    pub versions: usize,
+    pub files: usize,
+    /// Versions read from their manifest, the others were inferred from their layout
+    pub from_manifests: usize,
+    /// Versions the database had before
+    pub previous_versions: usize,
+}
+
+/// A file or directory of the vault, with what is wrong with it
+#[derive(Debug)]
+pub struct ReindexIssue {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl ReindexReport {
+    pub fn unattributed(&mut self, path: impl Into<PathBuf>, reason: impl Into<String>) {
+        self.unattributed.push(ReindexIssue {
+            path: path.into(),
+            reason: reason.into(),
+        });
+    }
+
+    pub fn mismatched(&mut self, path: impl Into<PathBuf>, reason: impl Into<String>) {
+        self.mismatched.push(ReindexIssue {
+            path: path.into(),
+            reason: reason.into(),
+        });
+    }
+}
@@ -24,11 +24,20 @@ pub struct RmGameRequest {
     pub purge: bool,
 }
 
+/// Represents a request to edit the vault config, coming directly from [`ConfigSubcommands`]
+#[derive(Debug)]
+pub struct EditConfigRequest {
+    pub id: Option<String>,
+}
+
 /// Represents a request to action backup, coming directly from the CLI
 #[derive(Debug)]
 pub struct BackupRequest {
     pub id: Option<String>,
     pub paths: Option<Vec<PathBuf>>,
+    pub force: bool,
+    pub paranoid: bool,
+    pub jobs: Option<usize>,
 }
 
 /// Represents a request to action restore, coming directly from the CLI
@@ -38,3 +47,52 @@ pub struct RestoreRequest {
     pub version: Option<String>,
     pub paths: Option<Vec<PathBuf>>,
 }
+
+/// Represents a request to show backup status, coming directly from the CLI
+#[derive(Debug)]
+pub struct StatusRequest {
+    pub id: Option<String>,
+    pub paranoid: bool,
+}
+
+/// Represents a request to export a backup version as a single archive, coming directly from the CLI
+#[derive(Debug)]
+pub struct ExportRequest {
+    pub id: String,
+    pub version: Option<String>,
+    pub format: String,
+    pub output: Option<PathBuf>,
+}
+
+/// Represents a request to prune old backups, coming directly from the CLI
+#[derive(Debug)]
+pub struct PruneRequest {
+    pub id: Option<String>,
+    pub version: Option<String>,
+    pub purge: bool,
+}
+
+/// Represents a request to check integrity of backups, coming directly from the CLI
+#[derive(Debug)]
+pub struct CheckRequest {
+    pub id: Option<String>,
+    pub verify_signatures: bool,
+}
+
+/// Represents a request to repair damaged backups from their parity, coming directly from the CLI
+#[derive(Debug)]
+pub struct RepairRequest {
+    pub id: Option<String>,
+}
+
+/// Represents a request to change the passphrase of the vault key, coming directly from the CLI
+#[derive(Debug)]
+pub struct RekeyRequest {
+    pub new_key_file: Option<PathBuf>,
+}
+
+/// Represents a request to rebuild backup records from the vault directory, coming directly from the CLI
+#[derive(Debug)]
+pub struct ReindexRequest {
+    pub id: Option<String>,
+}
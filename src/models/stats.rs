@@ -0,0 +1,46 @@
+//! Space usage of the vault, produced by `kaguya vault stats`
+
+/// Space usage of a single game in the vault config
+#[derive(Debug)]
+pub struct GameStats {
+    pub id: String,
+    pub name: String,
+    pub versions: usize,
+    /// Number of backup files over all versions
+    pub files: usize,
+    /// Bytes the backups of this game added to the vault
+    pub stored_bytes: u64,
+    /// Total size of the backed up files
+    pub original_bytes: u64,
+}
+
+/// Space usage of the whole vault
+#[derive(Debug, Default)]
+pub struct VaultStats {
+    pub games: Vec<GameStats>,
+    /// Size of all archive files, and of the files copied into mirrors
+    pub archive_bytes: u64,
+    /// Number of object manifests, whole-file and chunked
+    pub manifests: usize,
+    /// Total size of the files referenced by all manifests, as if every version were a full copy
+    pub logical_bytes: u64,
+    /// Uncompressed size of the distinct objects referenced by manifests
+    pub unique_bytes: u64,
+    /// Number of objects in the object store
+    pub objects: usize,
+    /// Size of the object store on disk
+    pub object_store_bytes: u64,
+}
+
+impl VaultStats {
+    /// How many times smaller manifest content is thanks to deduplication alone
+    pub fn dedup_ratio(&self) -> Option<f64> {
+        (self.unique_bytes > 0).then(|| self.logical_bytes as f64 / self.unique_bytes as f64)
+    }
+
+    /// How many times smaller manifest content is on disk, with compression of objects
+    pub fn storage_ratio(&self) -> Option<f64> {
+        (self.object_store_bytes > 0)
+            .then(|| self.logical_bytes as f64 / self.object_store_bytes as f64)
+    }
+}
@@ -0,0 +1,53 @@
+//! Backup status of games and paths, produced by `kaguya vault status`
+
+use std::{fmt, path::PathBuf};
+
+use crate::models::db::Backup;
+
+/// Backup status of a single game in the vault config
+#[derive(Debug)]
+pub struct GameStatus {
+    pub id: String,
+    pub name: String,
+    /// Latest backup of the game, `None` if it has never been backed up
+    pub last_backup: Option<Backup>,
+    pub paths: Vec<PathStatus>,
+}
+
+/// Backup status of a single configured path
+#[derive(Debug)]
+pub struct PathStatus {
+    pub path: PathBuf,
+    pub kind: PathStatusKind,
+}
+
+/// Compares the current content of a path with its latest backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathStatusKind {
+    /// Content is identical to the latest backup.
+    UpToDate { version: String },
+
+    /// Content has changed since the latest backup.
+    Modified { version: String },
+
+    /// The latest backup has no source checksum recorded (created by an older kaguya).
+    Unknown { version: String },
+
+    /// No backup of this path exists.
+    NeverBackedUp,
+
+    /// The path does not exist on disk.
+    Missing,
+}
+
+impl fmt::Display for PathStatusKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UpToDate { .. } => write!(f, "up to date"),
+            Self::Modified { version } => write!(f, "modified since {}", version),
+            Self::Unknown { version } => write!(f, "unknown (no checksum recorded in {})", version),
+            Self::NeverBackedUp => write!(f, "never backed up"),
+            Self::Missing => write!(f, "path missing"),
+        }
+    }
+}
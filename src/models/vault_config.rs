@@ -1,4 +1,4 @@
-use crate::models::AddGameRequest;
+use crate::models::{AddGameRequest, Game};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -21,6 +21,18 @@ pub struct GameConfig {
     /// How many versions to keep when acting prune, cover global config
     #[serde(skip_serializing_if = "Option::is_none")]
     pub keep_versions: Option<i64>,
+
+    /// Archive format of this game, cover vault config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+
+    /// Compression level of this game's archive format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<i32>,
+
+    /// Storage mode of this game, cover vault config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<String>,
 }
 
 impl From<&AddGameRequest> for GameConfig {
@@ -34,6 +46,9 @@ impl From<&AddGameRequest> for GameConfig {
             paths: request.paths.clone().unwrap_or_default(),
             comment: request.comment.clone(),
             keep_versions: None,
+            compression: None,
+            compression_level: None,
+            storage: None,
         }
     }
 }
@@ -46,13 +61,40 @@ impl From<AddGameRequest> for GameConfig {
             paths: request.paths.unwrap_or_default(),
             comment: request.comment,
             keep_versions: None,
+            compression: None,
+            compression_level: None,
+            storage: None,
+        }
+    }
+}
+
+impl From<&Game> for GameConfig {
+    /// Config of a game as recorded in the database, e.g. once removed from the vault config
+    fn from(game: &Game) -> Self {
+        Self {
+            id: game.external_id.clone(),
+            name: game.name.clone(),
+            paths: Vec::new(),
+            comment: game.comment.clone(),
+            keep_versions: game.keep_versions,
+            compression: None,
+            compression_level: None,
+            storage: None,
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct VaultConfig {
+    /// More files with '[[games]]' tables, or directories with one game per file,
+    /// relative to the vault config
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+
     pub backup: BackupSettings,
+
+    /// Games of every file of the vault config once read
+    #[serde(default)]
     pub games: Vec<GameConfig>,
 }
 
@@ -60,7 +102,51 @@ pub struct VaultConfig {
 pub struct BackupSettings {
     pub auto_prune: bool,
     pub keep_versions: u32,
+
+    /// Archive format of new backups: 'tar.gz', 'tar.zst', 'tar.xz' or 'tar'
     pub compression: String,
+
+    /// Compression level, defaults to the format's own default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<i32>,
+
+    /// Store already compressed files (images, audio, archives...) without compressing them again
+    #[serde(default = "default_adaptive_compression")]
+    pub adaptive_compression: bool,
+
+    /// Storage mode of new backups: 'archive', 'objects', 'chunks', 'mirror' or 'delta'
+    #[serde(default = "default_storage")]
+    pub storage: String,
+
+    /// Number of versions per delta chain, a full keyframe is stored every that many backups
+    #[serde(default = "default_delta_keyframe_interval")]
+    pub delta_keyframe_interval: u32,
+
+    /// Largest path stored as a delta, in MiB. Deltas are built in memory, larger paths
+    /// are stored as full archives instead
+    #[serde(default = "default_delta_max_size_mb")]
+    pub delta_max_size_mb: u64,
+
+    /// Encrypt new archives with the vault key, unlocked by a passphrase
+    #[serde(default)]
+    pub encryption: bool,
+
+    /// File holding the passphrase, instead of 'KAGUYA_PASSPHRASE' or a prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<PathBuf>,
+
+    /// Parity added to every backup file, in percent of its size, so 'vault repair' can
+    /// fix bit rot (0 disables)
+    #[serde(default)]
+    pub parity: u32,
+
+    /// ed25519 key file new versions are signed with, created on first use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<PathBuf>,
+
+    /// Number of paths backed up concurrently, overridden by '--jobs'
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<usize>,
 }
 
 impl Default for BackupSettings {
@@ -69,6 +155,32 @@ impl Default for BackupSettings {
             auto_prune: false,
             keep_versions: 0,
             compression: "tar.gz".to_string(),
+            compression_level: None,
+            adaptive_compression: true,
+            storage: default_storage(),
+            delta_keyframe_interval: default_delta_keyframe_interval(),
+            delta_max_size_mb: default_delta_max_size_mb(),
+            encryption: false,
+            key_file: None,
+            parity: 0,
+            signing_key: None,
+            jobs: None,
         }
     }
 }
+
+fn default_adaptive_compression() -> bool {
+    true
+}
+
+fn default_storage() -> String {
+    "archive".to_string()
+}
+
+fn default_delta_keyframe_interval() -> u32 {
+    10
+}
+
+fn default_delta_max_size_mb() -> u64 {
+    256
+}
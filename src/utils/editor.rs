@@ -0,0 +1,64 @@
+//! Open files in the user's editor, with notes to the user on top of them
+
+use std::{env, path::Path, process::Command};
+
+use crate::models::{EDITOR_ENV, KaguyaError, VISUAL_ENV};
+
+// Lines starting with this are notes on top of an edited file, as TOML comments
+const EDIT_NOTE_PREFIX: &str = "# kaguya: ";
+// Last line of the notes, so lines of the file looking like notes are never taken for them
+const EDIT_NOTES_END: &str = "# kaguya: end of notes";
+
+/// Open `path` in the editor set by the 'VISUAL' or 'EDITOR' environment variable,
+/// 'vi' if neither is set, and wait for it to exit.
+pub fn open_in_editor(path: &Path) -> Result<(), KaguyaError> {
+    let editor = [VISUAL_ENV, EDITOR_ENV]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+
+    // Run by the shell, so editors set with arguments work, e.g. 'code --wait'
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(KaguyaError::InvalidInput(format!(
+            "Editor '{}' exited with {}",
+            editor, status
+        )));
+    }
+    Ok(())
+}
+
+/// `text` with a line of `notes` on top, as '# kaguya: ' comments
+pub fn add_edit_notes(text: &str, notes: &[&str]) -> String {
+    let mut noted: String = notes
+        .iter()
+        .flat_map(|note| note.lines())
+        .map(|line| format!("{}{}\n", EDIT_NOTE_PREFIX, line))
+        .collect();
+    noted.push_str(EDIT_NOTES_END);
+    noted.push('\n');
+    noted.push_str(text);
+    noted
+}
+
+/// `text` without the notes added on top of it by [`add_edit_notes`].
+/// Other lines are kept, even if they look like notes.
+pub fn strip_edit_notes(text: &str) -> String {
+    let mut stripped = 0;
+    for line in text.split_inclusive('\n') {
+        if !line.starts_with(EDIT_NOTE_PREFIX) {
+            return text.to_string();
+        }
+        stripped += line.len();
+        if line.trim_end() == EDIT_NOTES_END {
+            return text[stripped..].to_string();
+        }
+    }
+    text.to_string()
+}
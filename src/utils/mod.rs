@@ -1,4 +1,8 @@
-//! Some auxiliary functions for getting path and time string
+//! Some auxiliary functions for getting path, time string and passphrase, asking the user
+//! to confirm, and opening the editor
 
+pub mod editor;
+pub mod passphrase;
 pub mod path;
+pub mod prompt;
 pub mod time;
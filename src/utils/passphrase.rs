@@ -0,0 +1,71 @@
+//! Read the passphrase of the vault encryption key
+//!
+//! Passphrases are taken from a key file if one is set, then from an environment
+//! variable, and prompted for otherwise.
+
+use std::{fs::read_to_string, path::Path};
+
+use crate::{
+    models::{KaguyaError, NEW_PASSPHRASE_ENV, PASSPHRASE_ENV},
+    utils::path::expand_path,
+};
+
+/// Passphrase of the vault key, taken from `key_file`,
+/// then from the 'KAGUYA_PASSPHRASE' environment variable, and prompted for otherwise.
+/// A prompted passphrase is asked twice if `confirm` is set, e.g. when it is a new one.
+pub fn read_passphrase(key_file: Option<&Path>, confirm: bool) -> Result<String, KaguyaError> {
+    read_from(key_file, PASSPHRASE_ENV, "Vault passphrase: ", confirm)
+}
+
+/// New passphrase of the vault key, taken from `key_file`,
+/// then from the 'KAGUYA_NEW_PASSPHRASE' environment variable, and prompted for twice otherwise.
+pub fn read_new_passphrase(key_file: Option<&Path>) -> Result<String, KaguyaError> {
+    read_from(key_file, NEW_PASSPHRASE_ENV, "New vault passphrase: ", true)
+}
+
+// Passphrase from `key_file`, then from the environment variable `env`, then from a prompt
+fn read_from(
+    key_file: Option<&Path>,
+    env: &str,
+    prompt: &str,
+    confirm: bool,
+) -> Result<String, KaguyaError> {
+    if let Some(path) = key_file {
+        return read_key_file(path);
+    }
+    if let Ok(passphrase) = std::env::var(env) {
+        return non_empty(passphrase);
+    }
+    prompt_passphrase(prompt, confirm)
+}
+
+// Passphrase stored in a key file, without its trailing newline
+fn read_key_file(path: &Path) -> Result<String, KaguyaError> {
+    let path = expand_path(&path)?;
+    if !path.is_file() {
+        return Err(KaguyaError::PathNotFound(
+            path.to_string_lossy().to_string(),
+        ));
+    }
+    let content = read_to_string(&path)?;
+    non_empty(content.trim_end_matches(['\n', '\r']).to_string())
+}
+
+fn prompt_passphrase(prompt: &str, confirm: bool) -> Result<String, KaguyaError> {
+    let passphrase = non_empty(rpassword::prompt_password(prompt)?)?;
+    if confirm && rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+        return Err(KaguyaError::InvalidInput(
+            "Passphrases do not match".to_string(),
+        ));
+    }
+    Ok(passphrase)
+}
+
+fn non_empty(passphrase: String) -> Result<String, KaguyaError> {
+    if passphrase.is_empty() {
+        return Err(KaguyaError::InvalidInput(
+            "Passphrase must not be empty".to_string(),
+        ));
+    }
+    Ok(passphrase)
+}
@@ -16,14 +16,14 @@ use std::{
 /// # Examples
 ///
 /// ```
+/// # use kaguya::utils::path::shrink_path;
 /// # use std::path::PathBuf;
-/// let home = dir::home_dir().unwrap();
+/// let home = dirs::home_dir().unwrap();
 /// let path = home.join("Documents");
-/// assert_eq!(shrink_path(&path), PathBuf::from("~/Documents"));
+/// assert_eq!(shrink_path(&path).unwrap(), PathBuf::from("~/Documents"));
 ///
-/// let config_home = dirs::config_dir().unwrap();
-/// let path = config_home.join("kaguya");
-/// assert_eq!(shrink_path(&path), PathBuf::from("~/.config/kaguya"));
+/// let path = PathBuf::from("/opt/games");
+/// assert_eq!(shrink_path(&path).unwrap(), PathBuf::from("/opt/games"));
 /// ```
 pub fn shrink_path<P>(path: &P) -> Result<PathBuf, KaguyaError>
 where
@@ -93,11 +93,18 @@ where
 /// # Examples
 ///
 /// ```
+/// # use kaguya::utils::path::{shrink_path, to_absolute_path, transform_paths_option};
+/// # use std::path::PathBuf;
+/// # fn main() -> Result<(), kaguya::models::KaguyaError> {
+/// let paths = Some(vec![PathBuf::from("saves")]);
+///
 /// // Convert to absolute paths
-/// let abs_paths = transform_paths_option(paths, to_absolute_path)?;
+/// let abs_paths = transform_paths_option(paths.clone(), to_absolute_path)?;
 ///
 /// // Shrink paths (replace home dir with ~)
-/// let short_paths = transform_paths_option(paths, shrink_path)?;
+/// let short_paths = transform_paths_option(paths, |path| shrink_path(path))?;
+/// # Ok(())
+/// # }
 /// ```
 pub fn transform_paths_option<F>(
     paths: Option<Vec<PathBuf>>,
@@ -158,6 +165,16 @@ pub fn get_file_name(path: impl AsRef<Path>) -> Option<String> {
         .map(|f| f.to_string_lossy().to_string())
 }
 
+/// Whether a game ID can be used as a directory name, under `backups/` or in `games.d/`:
+/// letters, digits, '-', '_' and '.', not starting with '.'
+pub fn is_valid_game_id(id: &str) -> bool {
+    !id.is_empty()
+        && !id.starts_with('.')
+        && id
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
 /// Finds a game by ID in the game list and return a mutable reference.
 pub fn find_game_mut<'a>(games: &'a mut [GameConfig], id: &str) -> Option<&'a mut GameConfig> {
     games.iter_mut().find(|g| g.id == id)
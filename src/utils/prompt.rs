@@ -0,0 +1,16 @@
+//! Ask the user to confirm an action
+
+use std::io::{Write, stdin, stdout};
+
+use crate::models::KaguyaError;
+
+/// Ask a yes/no `question` on the terminal, `false` unless answered with 'y' or 'yes'.
+/// Without an answer, e.g. when stdin is closed, the action is not confirmed.
+pub fn confirm(question: &str) -> Result<bool, KaguyaError> {
+    print!("{} [y/N] ", question);
+    stdout().flush()?;
+
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
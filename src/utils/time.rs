@@ -1,10 +1,42 @@
-use chrono::offset::{Local, Utc};
+use chrono::{
+    NaiveDateTime,
+    offset::{Local, Utc},
+};
+
+// Format of backup version names
+const VERSION_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
 
 /// Get formattary time string
 pub fn get_time_string() -> String {
-    Local::now().format("%Y-%m-%d_%H-%M-%S").to_string()
+    Local::now().format(VERSION_FORMAT).to_string()
+}
+
+/// Timestamp of a version named by [`get_time_string`], `None` if it isn't such a name
+pub fn version_timestamp(version: &str) -> Option<String> {
+    NaiveDateTime::parse_from_str(version, VERSION_FORMAT)
+        .ok()?
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|time| time.timestamp().to_string())
 }
 
 pub fn get_timestamp() -> String {
     Utc::now().timestamp().to_string()
 }
+
+/// Describe how long ago a timestamp created by [`get_timestamp`] was, e.g. "3 hours ago"
+pub fn format_time_ago(timestamp: &str) -> String {
+    let Ok(then) = timestamp.parse::<i64>() else {
+        return "unknown time ago".to_string();
+    };
+    let seconds = (Utc::now().timestamp() - then).max(0);
+
+    let (value, unit) = match seconds {
+        0..60 => return "just now".to_string(),
+        60..3600 => (seconds / 60, "minute"),
+        3600..86400 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    let plural = if value == 1 { "" } else { "s" };
+    format!("{} {}{} ago", value, unit, plural)
+}
@@ -0,0 +1,210 @@
+//! Writing, reading and reproducing archives of every codec.
+
+use filetime::{FileTime, set_file_mtime};
+use kaguya::{
+    fs_utils::{
+        archive::{ArchiveInfo, compress_archive, decompress_archive},
+        codec::{ArchiveCodec, CompressionSettings},
+        crypto::DataKey,
+    },
+    models::KaguyaError,
+};
+use std::{
+    collections::BTreeMap,
+    fs::{File, Permissions, create_dir_all, read, read_dir, set_permissions, write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use zip::{CompressionMethod, ZipArchive};
+
+mod common;
+use common::TestDir;
+
+// Codecs of compressed tar streams, and the plain tar
+const TAR_CODECS: [ArchiveCodec; 4] = [
+    ArchiveCodec::Tar,
+    ArchiveCodec::TarGz,
+    ArchiveCodec::TarZst,
+    ArchiveCodec::TarXz,
+];
+
+// Pseudo-random content, the same for the same seed
+fn random_content(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+// A save directory with text, binary and already compressed files, one of them nested
+fn write_saves(dir: &TestDir) -> PathBuf {
+    let saves = dir.join("saves");
+    create_dir_all(saves.join("slots")).unwrap();
+    write(saves.join("options.ini"), "volume = 80\n".repeat(200)).unwrap();
+    write(saves.join("slots/slot1.sav"), random_content(1, 20_000)).unwrap();
+    write(saves.join("screenshot.png"), random_content(2, 20_000)).unwrap();
+    saves
+}
+
+// Every file under `root` by relative path, with its content
+fn read_tree(root: &Path) -> BTreeMap<String, Vec<u8>> {
+    fn walk(root: &Path, dir: &Path, files: &mut BTreeMap<String, Vec<u8>>) {
+        for entry in read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                walk(root, &path, files);
+            } else {
+                let rel_path = path.strip_prefix(root).unwrap();
+                files.insert(rel_path.display().to_string(), read(&path).unwrap());
+            }
+        }
+    }
+    let mut files = BTreeMap::new();
+    walk(root, root, &mut files);
+    files
+}
+
+// Set the mtime of every entry under `root`, and make its files private
+fn touch_tree(root: &Path, secs: i64) {
+    for entry in read_dir(root).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            touch_tree(&path, secs);
+        } else {
+            set_permissions(&path, Permissions::from_mode(0o600)).unwrap();
+        }
+        set_file_mtime(&path, FileTime::from_unix_time(secs, 0)).unwrap();
+    }
+    set_file_mtime(root, FileTime::from_unix_time(secs, 0)).unwrap();
+}
+
+fn compress(src: &Path, dst: &Path, codec: ArchiveCodec, key: Option<&DataKey>) -> ArchiveInfo {
+    let settings = CompressionSettings::new(codec.extension(), None).unwrap();
+    compress_archive(&src, &dst, &settings, key).unwrap()
+}
+
+#[test]
+fn identical_content_gives_identical_archives() {
+    let dir = TestDir::new("archive-reproducible");
+    let saves = write_saves(&dir);
+
+    for codec in ArchiveCodec::ALL {
+        let archive = |name: &str| dir.join(format!("{}.{}", name, codec.extension()));
+
+        touch_tree(&saves, 1_000_000_000);
+        let first = compress(&saves, &archive("first"), codec, None);
+        touch_tree(&saves, 1_500_000_000);
+        let second = compress(&saves, &archive("second"), codec, None);
+
+        assert_eq!(first, second, "{}", codec);
+        assert!(read(archive("first")).unwrap() == read(archive("second")).unwrap());
+
+        // While any change of the content changes it
+        write(saves.join("options.ini"), "volume = 81\n".repeat(200)).unwrap();
+        let changed = compress(&saves, &archive("changed"), codec, None);
+        assert_ne!(first.checksum, changed.checksum, "{}", codec);
+        write(saves.join("options.ini"), "volume = 80\n".repeat(200)).unwrap();
+    }
+}
+
+#[test]
+fn every_tar_codec_round_trips() {
+    let dir = TestDir::new("archive-round-trip");
+    let saves = write_saves(&dir);
+    let key = DataKey::generate();
+
+    for codec in TAR_CODECS {
+        for key in [None, Some(&key)] {
+            let archive = dir.join(format!("saves.{}", codec.extension()));
+            let out = dir.join(format!("out-{}-{}", codec.extension(), key.is_some()));
+            let info = compress(&saves, &archive, codec, key);
+
+            decompress_archive(&archive, &out, codec, Some(&info.checksum), key).unwrap();
+            assert!(
+                read_tree(&out.join("saves")) == read_tree(&saves),
+                "{} (encrypted: {})",
+                codec,
+                key.is_some()
+            );
+        }
+    }
+}
+
+#[test]
+fn tar_archives_are_verified_while_unpacking() {
+    let dir = TestDir::new("archive-verify");
+    let saves = write_saves(&dir);
+
+    for codec in TAR_CODECS {
+        let archive = dir.join(format!("saves.{}", codec.extension()));
+        compress(&saves, &archive, codec, None);
+        let error = decompress_archive(
+            &archive,
+            &dir.join("out"),
+            codec,
+            Some("not the checksum"),
+            None,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(error, KaguyaError::ChecksumMismatch(_)),
+            "{}: {:?}",
+            codec,
+            error
+        );
+    }
+}
+
+#[test]
+fn zip_archives_round_trip() {
+    let dir = TestDir::new("archive-zip");
+    let saves = write_saves(&dir);
+    let key = DataKey::generate();
+
+    for key in [None, Some(&key)] {
+        let archive = dir.join("saves.zip");
+        let out = dir.join(format!("out-{}", key.is_some()));
+        let info = compress(&saves, &archive, ArchiveCodec::Zip, key);
+
+        decompress_archive(&archive, &out, ArchiveCodec::Zip, Some(&info.checksum), key).unwrap();
+        assert!(read_tree(&out.join("saves")) == read_tree(&saves));
+        // An encrypted zip is decrypted next to its content, and removed once unpacked
+        assert_eq!(read_dir(&out).unwrap().count(), 1);
+    }
+}
+
+#[test]
+fn compressed_files_are_stored_in_zip_archives() {
+    let dir = TestDir::new("archive-zip-stored");
+    let saves = write_saves(&dir);
+
+    for adaptive in [true, false] {
+        let archive = dir.join(format!("saves-{}.zip", adaptive));
+        let mut settings = CompressionSettings::new("zip", None).unwrap();
+        settings.adaptive = adaptive;
+        compress_archive(&saves, &archive, &settings, None).unwrap();
+
+        let mut zip = ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let method = |zip: &mut ZipArchive<File>, name: &str| {
+            zip.by_name(&format!("saves/{}", name))
+                .unwrap()
+                .compression()
+        };
+        assert_eq!(method(&mut zip, "options.ini"), CompressionMethod::Deflated);
+        // A png by its extension, a random save by its entropy
+        for name in ["screenshot.png", "slots/slot1.sav"] {
+            let expected = if adaptive {
+                CompressionMethod::Stored
+            } else {
+                CompressionMethod::Deflated
+            };
+            assert_eq!(method(&mut zip, name), expected, "{}", name);
+        }
+    }
+}
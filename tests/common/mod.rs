@@ -0,0 +1,41 @@
+//! Fixtures shared by the integration tests.
+
+// Every test binary includes this module, and uses only part of it
+#![allow(dead_code)]
+
+use std::{
+    env::temp_dir,
+    fs::{create_dir_all, remove_dir_all},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// A fresh directory of a test, removed when dropped.
+///
+/// Named after the test and the process, so tests running in parallel don't share it.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let dir = temp_dir().join(format!("kaguya-test-{}-{}", process::id(), name));
+        if dir.exists() {
+            remove_dir_all(&dir).unwrap();
+        }
+        create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        remove_dir_all(&self.0).ok();
+    }
+}
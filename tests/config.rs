@@ -0,0 +1,488 @@
+//! Reading and editing the vault config split over several files.
+
+use kaguya::{
+    cli::AppContext,
+    core::ConfigService,
+    db_manager::toml::{
+        ConfigFile, add_or_update_game_to_file, check_edited_config, duplicate_game_ids,
+        editable_config, read_config_files, read_vault_config, rm_game_in_vault_config,
+        save_edited_config, vault_config_files,
+    },
+    models::{
+        AddGameRequest, BACKUP_DIR, DB_FILE, GAMES_DIR, IssueLevel, KaguyaError, OBJECTS_DIR,
+        VAULT_CONFIG_FILE,
+    },
+    utils::{
+        editor::{add_edit_notes, strip_edit_notes},
+        path::is_valid_game_id,
+    },
+};
+use std::{
+    fs::{create_dir_all, read_to_string, write},
+    path::PathBuf,
+};
+
+mod common;
+use common::TestDir;
+
+const BACKUP_TABLE: &str =
+    "[backup]\nauto_prune = false\nkeep_versions = 0\ncompression = \"tar.gz\"\n";
+
+// A vault config with comments and blank lines, two games in one array of tables
+const VAULT_CONFIG: &str = r#"# Vault of the test
+[backup]
+auto_prune = false # pruned by hand
+keep_versions = 0
+compression = "tar.gz"
+
+# First game
+[[games]]
+id = "a"
+name = "Game A" # shown in lists
+paths = [
+    "/saves/a/one",
+    "/saves/a/two",
+]
+
+# Second game
+
+[[games]]
+id = "b"
+name = "Game B"
+paths = ["/saves/b"]   # a single path
+"#;
+
+// A directory holding a vault config, removed when dropped
+struct TestConfig(TestDir);
+
+impl TestConfig {
+    fn new(name: &str, vault_config: &str) -> Self {
+        let config = Self(TestDir::new(name));
+        config.write(VAULT_CONFIG_FILE, vault_config);
+        config
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    fn vault_config(&self) -> PathBuf {
+        self.path(VAULT_CONFIG_FILE)
+    }
+
+    fn write(&self, name: &str, content: &str) {
+        let path = self.path(name);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, content).unwrap();
+    }
+
+    fn read(&self, name: &str) -> String {
+        read_to_string(self.path(name)).unwrap()
+    }
+
+    // Issues found by 'config check' as (game, level, message), the vault in 'vault/'
+    fn check(&self) -> Vec<(Option<String>, IssueLevel, String)> {
+        let vault_dir = self.path("vault");
+        let context = AppContext {
+            global_config_path: self.path("config.toml"),
+            vault_config_path: self.vault_config(),
+            backup_dir: vault_dir.join(BACKUP_DIR),
+            objects_dir: vault_dir.join(OBJECTS_DIR),
+            db_path: vault_dir.join(DB_FILE),
+            vault_dir,
+            dry_run: false,
+        };
+        ConfigService::new(context)
+            .check()
+            .unwrap()
+            .issues
+            .into_iter()
+            .map(|issue| (issue.game_id, issue.level, issue.message))
+            .collect()
+    }
+
+    fn game_ids(&self) -> Vec<String> {
+        read_vault_config(&self.vault_config())
+            .unwrap()
+            .games
+            .into_iter()
+            .map(|game| game.id)
+            .collect()
+    }
+}
+
+// A file with a single game
+fn game_file(id: &str) -> String {
+    format!(
+        "id = \"{}\"\nname = \"Game {}\"\npaths = [\"/saves/{}\"]\n",
+        id, id, id
+    )
+}
+
+fn request(id: &str, name: Option<&str>, paths: &[&str]) -> AddGameRequest {
+    AddGameRequest {
+        id: id.to_string(),
+        name: name.map(str::to_string),
+        paths: Some(paths.iter().map(PathBuf::from).collect()),
+        comment: None,
+    }
+}
+
+#[test]
+fn updates_only_change_the_table_of_the_game() {
+    let config = TestConfig::new("config-update", VAULT_CONFIG);
+
+    // A known path is not added twice, a new one is written like the others
+    add_or_update_game_to_file(
+        &config.vault_config(),
+        request("a", Some("Game A2"), &["/saves/a/two", "/saves/a/three"]),
+    )
+    .unwrap();
+
+    let expected = VAULT_CONFIG
+        .replace(
+            "name = \"Game A\" # shown in lists",
+            "name = \"Game A2\" # shown in lists",
+        )
+        .replace(
+            "    \"/saves/a/two\",\n",
+            "    \"/saves/a/two\",\n    \"/saves/a/three\",\n",
+        );
+    assert_eq!(config.read(VAULT_CONFIG_FILE), expected);
+    assert_eq!(config.read("vault.toml.bak"), VAULT_CONFIG);
+}
+
+#[test]
+fn inline_games_arrays_become_tables() {
+    let config = TestConfig::new("config-inline", &format!("games = []\n\n{}", BACKUP_TABLE));
+
+    add_or_update_game_to_file(&config.vault_config(), request("a", None, &["/saves/a"])).unwrap();
+
+    let content = config.read(VAULT_CONFIG_FILE);
+    assert!(!content.contains("games = []"), "{}", content);
+    assert!(content.contains("[[games]]"), "{}", content);
+    assert_eq!(config.game_ids(), vec!["a"]);
+
+    // An inline array with games can't be turned into tables as written
+    config.write(VAULT_CONFIG_FILE, &format!("games = [1]\n{}", BACKUP_TABLE));
+    assert!(
+        add_or_update_game_to_file(&config.vault_config(), request("b", None, &["/saves/b"]))
+            .is_err()
+    );
+}
+
+#[test]
+fn games_are_removed_from_the_file_defining_them() {
+    let config = TestConfig::new("config-rm", VAULT_CONFIG);
+    config.write(&format!("{}/c.toml", GAMES_DIR), &game_file("c"));
+
+    rm_game_in_vault_config(&config.vault_config(), "a").unwrap();
+    let expected = VAULT_CONFIG.replace(
+        "# First game\n[[games]]\nid = \"a\"\nname = \"Game A\" # shown in lists\npaths = [\n    \"/saves/a/one\",\n    \"/saves/a/two\",\n]\n\n",
+        "",
+    );
+    assert_ne!(expected, VAULT_CONFIG);
+    assert_eq!(config.read(VAULT_CONFIG_FILE), expected);
+
+    // A file holding only the game is set aside
+    rm_game_in_vault_config(&config.vault_config(), "c").unwrap();
+    assert!(!config.path("games.d/c.toml").exists());
+    assert_eq!(config.read("games.d/c.toml.bak"), game_file("c"));
+
+    assert_eq!(config.game_ids(), vec!["b"]);
+    assert!(matches!(
+        rm_game_in_vault_config(&config.vault_config(), "a"),
+        Err(KaguyaError::GameNotFound(_))
+    ));
+}
+
+#[test]
+fn config_files_are_read_in_order() {
+    let config = TestConfig::new(
+        "config-files",
+        &format!(
+            "include = [\"more.toml\", \"extra\"]\n{}\n[[games]]\nid = \"a\"\nname = \"A\"\npaths = [\"/saves/a\"]\n",
+            BACKUP_TABLE
+        ),
+    );
+    config.write("games.d/b.toml", &game_file("b"));
+    config.write("games.d/notes.txt", "not a game");
+    config.write("more.toml", &format!("[[games]]\n{}", game_file("c")));
+    config.write("extra/e.toml", &game_file("e"));
+    config.write("extra/d.toml", &game_file("d"));
+
+    let (_, files) = read_config_files(&config.vault_config()).unwrap();
+    let expected = vec![
+        ConfigFile::Tables(config.vault_config()),
+        ConfigFile::Game(config.path("games.d/b.toml")),
+        ConfigFile::Tables(config.path("more.toml")),
+        ConfigFile::Game(config.path("extra/d.toml")),
+        ConfigFile::Game(config.path("extra/e.toml")),
+    ];
+    assert_eq!(
+        files
+            .iter()
+            .map(|(file, _)| file.clone())
+            .collect::<Vec<_>>(),
+        expected
+    );
+    assert_eq!(
+        vault_config_files(&config.vault_config()).unwrap(),
+        expected
+            .iter()
+            .map(|file| file.path().to_path_buf())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(config.game_ids(), vec!["a", "b", "c", "d", "e"]);
+
+    // New games go to 'games.d/' once it exists
+    add_or_update_game_to_file(&config.vault_config(), request("f", None, &["/saves/f"])).unwrap();
+    assert!(config.path("games.d/f.toml").is_file());
+
+    // An include that is gone is an error, not a config without its games
+    config.write(
+        VAULT_CONFIG_FILE,
+        &format!("include = [\"missing.toml\"]\n{}", BACKUP_TABLE),
+    );
+    assert!(matches!(
+        read_config_files(&config.vault_config()),
+        Err(KaguyaError::PathNotFound(_))
+    ));
+}
+
+#[test]
+fn duplicate_ids_across_files_are_rejected() {
+    let config = TestConfig::new("config-duplicates", VAULT_CONFIG);
+    config.write("games.d/a.toml", &game_file("a"));
+
+    let (_, files) = read_config_files(&config.vault_config()).unwrap();
+    assert_eq!(
+        duplicate_game_ids(&files),
+        vec![(
+            "a".to_string(),
+            config.vault_config(),
+            config.path("games.d/a.toml")
+        )]
+    );
+    assert!(matches!(
+        read_vault_config(&config.vault_config()),
+        Err(KaguyaError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn edited_games_are_spliced_back_into_their_file() {
+    let config = TestConfig::new("config-edit", VAULT_CONFIG);
+    config.write("games.d/c.toml", &game_file("c"));
+
+    let editable = editable_config(&config.vault_config(), Some("b")).unwrap();
+    assert_eq!(editable.path, config.vault_config());
+    assert_eq!(editable.game_id.as_deref(), Some("b"));
+    assert!(!editable.content.contains("Game A"), "{}", editable.content);
+
+    let edited = editable.content.replace("Game B", "Game B2");
+    let (content, vault_config) =
+        check_edited_config(&config.vault_config(), &editable, &edited).unwrap();
+    assert_eq!(content, VAULT_CONFIG.replace("Game B", "Game B2"));
+    assert_eq!(
+        vault_config
+            .games
+            .iter()
+            .map(|game| (game.id.as_str(), game.name.as_str()))
+            .collect::<Vec<_>>(),
+        vec![("a", "Game A"), ("b", "Game B2"), ("c", "Game c")]
+    );
+
+    // An edit reusing the ID of a game in another file is refused
+    let edited = editable.content.replace("\"b\"", "\"c\"");
+    assert!(check_edited_config(&config.vault_config(), &editable, &edited).is_err());
+    assert!(check_edited_config(&config.vault_config(), &editable, "id = ").is_err());
+
+    save_edited_config(&editable, &content).unwrap();
+    assert_eq!(config.read(VAULT_CONFIG_FILE), content);
+    assert_eq!(config.read("vault.toml.bak"), VAULT_CONFIG);
+}
+
+#[test]
+fn files_changed_during_an_edit_are_not_replaced() {
+    let config = TestConfig::new("config-edit-race", VAULT_CONFIG);
+
+    let editable = editable_config(&config.vault_config(), Some("a")).unwrap();
+    let edited = editable.content.replace("Game A", "Game A2");
+    let (content, _) = check_edited_config(&config.vault_config(), &editable, &edited).unwrap();
+
+    // Another game is added meanwhile
+    add_or_update_game_to_file(&config.vault_config(), request("c", None, &["/saves/c"])).unwrap();
+    let changed = config.read(VAULT_CONFIG_FILE);
+
+    assert!(matches!(
+        save_edited_config(&editable, &content),
+        Err(KaguyaError::InvalidInput(_))
+    ));
+    assert_eq!(config.read(VAULT_CONFIG_FILE), changed);
+}
+
+#[test]
+fn only_edit_notes_on_top_are_stripped() {
+    let edited = "# kaguya: kept, it's not on top\n[backup]\nkeep_versions = 0\n";
+    let noted = add_edit_notes(edited, &["Invalid:", "line 1\nline 2"]);
+    assert_eq!(
+        noted,
+        format!(
+            "# kaguya: Invalid:\n# kaguya: line 1\n# kaguya: line 2\n# kaguya: end of notes\n{}",
+            edited
+        )
+    );
+    assert_eq!(strip_edit_notes(&noted), edited);
+    assert_eq!(strip_edit_notes(edited), edited);
+}
+
+#[test]
+fn game_ids_must_be_directory_names() {
+    for id in ["game-a", "game_b.2", "ゲーム"] {
+        assert!(is_valid_game_id(id), "{}", id);
+    }
+    for id in ["", ".hidden", "..", "a/b", "a b", "a\\b"] {
+        assert!(!is_valid_game_id(id), "{}", id);
+    }
+}
+
+#[test]
+fn check_reports_every_problem_of_the_games() {
+    let config = TestConfig::new("config-check", BACKUP_TABLE);
+    for dir in [
+        "saves/a/save",
+        "saves/other/save",
+        "saves/d",
+        "vault/inside",
+    ] {
+        create_dir_all(config.path(dir)).unwrap();
+    }
+    let game = |id: &str, paths: &[PathBuf]| {
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|path| format!("\"{}\"", path.display()))
+            .collect();
+        format!(
+            "\n[[games]]\nid = \"{}\"\nname = \"{}\"\npaths = [{}]\n",
+            id,
+            id,
+            paths.join(", ")
+        )
+    };
+    let content = [
+        BACKUP_TABLE.to_string(),
+        // The same file name twice in one game
+        game(
+            "a",
+            &[config.path("saves/a/save"), config.path("saves/other/save")],
+        ),
+        // A path of another game, and a directory holding one
+        game("b", &[config.path("saves/a/save")]),
+        game("c", &[config.path("saves/a")]),
+        // Inside the vault, and containing it without a file name
+        game("d", &[config.path("saves/d"), config.path("vault/inside")]),
+        game("e", &[PathBuf::from("/")]),
+        game(".f", &[config.path("saves/missing")]),
+        game("g", &[]),
+    ]
+    .concat();
+    config.write(VAULT_CONFIG_FILE, &content);
+
+    let issues = config.check();
+    let expected = [
+        ("a", IssueLevel::Error, "has the same file name as"),
+        ("b", IssueLevel::Error, "is also a path of game 'a'"),
+        ("c", IssueLevel::Warning, "overlaps"),
+        ("d", IssueLevel::Error, "is inside the vault"),
+        ("e", IssueLevel::Error, "has no file name"),
+        ("e", IssueLevel::Error, "contains the vault"),
+        (".f", IssueLevel::Error, "ID is used as a directory name"),
+        (".f", IssueLevel::Warning, "doesn't exist"),
+        ("g", IssueLevel::Warning, "No paths configured"),
+    ];
+    for (id, level, message) in expected {
+        assert!(
+            issues.iter().any(|(game_id, issue_level, issue_message)| {
+                game_id.as_deref() == Some(id)
+                    && *issue_level == level
+                    && issue_message.contains(message)
+            }),
+            "no {:?} '{}' for '{}' in {:?}",
+            level,
+            message,
+            id,
+            issues
+        );
+    }
+    // Every file could be read, and shared paths are reported by the later game only
+    assert_eq!(
+        issues.iter().filter(|(id, _, _)| id.is_none()).count(),
+        0,
+        "{:?}",
+        issues
+    );
+    assert_eq!(
+        issues
+            .iter()
+            .filter(|(id, _, _)| id.as_deref() == Some("a"))
+            .count(),
+        1,
+        "{:?}",
+        issues
+    );
+    // The directory of 'c' holds the path of every earlier game
+    assert!(
+        issues
+            .iter()
+            .any(|(id, level, message)| id.as_deref() == Some("c")
+                && *level == IssueLevel::Warning
+                && message.contains("game 'b'")),
+        "{:?}",
+        issues
+    );
+}
+
+#[test]
+fn check_reports_files_that_cant_be_read() {
+    let config = TestConfig::new(
+        "config-check-files",
+        &format!(
+            "include = [\"missing.toml\", \"broken.toml\"]\n{}\n[[games]]\nid = \"a\"\nname = \"A\"\npaths = []\n",
+            BACKUP_TABLE
+        ),
+    );
+    config.write("broken.toml", "[[games]\nid = \"b\"\n");
+    config.write("games.d/c.toml", "id = \"c\"\n");
+
+    let issues = config.check();
+    let unreadable: Vec<&String> = issues
+        .iter()
+        .filter(|(id, _, _)| id.is_none())
+        .map(|(_, level, message)| {
+            assert_eq!(*level, IssueLevel::Error);
+            message
+        })
+        .collect();
+    assert_eq!(unreadable.len(), 3, "{:?}", issues);
+    for file in ["missing.toml", "broken.toml", "c.toml"] {
+        assert!(
+            unreadable.iter().any(|message| message.contains(file)),
+            "'{}' not reported: {:?}",
+            file,
+            issues
+        );
+    }
+    // The games of the files that could be read are still checked
+    assert!(
+        issues.iter().any(|(id, _, _)| id.as_deref() == Some("a")),
+        "{:?}",
+        issues
+    );
+
+    // Nothing at all can be checked without 'vault.toml'
+    config.write(VAULT_CONFIG_FILE, "[backup");
+    let issues = config.check();
+    assert_eq!(issues.len(), 1, "{:?}", issues);
+    assert!(issues[0].0.is_none());
+}
@@ -0,0 +1,124 @@
+//! Format of encrypted archives and of the wrapped vault key.
+
+use kaguya::{
+    fs_utils::crypto::{
+        CHUNK_SIZE, DataKey, DecryptingReader, EncryptingWriter, HEADER_LEN, KeyEnvelope, TAG_LEN,
+    },
+    models::KaguyaError,
+};
+use std::io::{self, Read, Write};
+
+// Encrypt `plain` with `key`, written in uneven pieces
+fn encrypt(plain: &[u8], key: &DataKey) -> Vec<u8> {
+    let mut writer = EncryptingWriter::new(Vec::new(), Some(key)).unwrap();
+    for piece in plain.chunks(7919) {
+        writer.write_all(piece).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+// Decrypt `sealed` with `key`, read in uneven pieces
+fn decrypt(sealed: &[u8], key: &DataKey) -> io::Result<Vec<u8>> {
+    let mut reader = DecryptingReader::new(sealed, Some(key));
+    let mut plain = Vec::new();
+    let mut buf = [0; 4099];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(plain),
+            len => plain.extend_from_slice(&buf[..len]),
+        }
+    }
+}
+
+fn content(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+// Range of the sealed chunk `index` in an encrypted file
+fn chunk(index: usize) -> std::ops::Range<usize> {
+    let start = HEADER_LEN + index * (CHUNK_SIZE + TAG_LEN);
+    start..start + CHUNK_SIZE + TAG_LEN
+}
+
+#[test]
+fn round_trip_across_chunk_boundaries() {
+    let key = DataKey::generate();
+    for len in [
+        0,
+        1,
+        CHUNK_SIZE - 1,
+        CHUNK_SIZE,
+        CHUNK_SIZE + 1,
+        3 * CHUNK_SIZE,
+        3 * CHUNK_SIZE + 5,
+    ] {
+        let plain = content(len);
+        let sealed = encrypt(&plain, &key);
+
+        // Every chunk is followed by its tag, and the last one may be empty
+        assert_eq!(
+            sealed.len(),
+            HEADER_LEN + len + (len / CHUNK_SIZE + 1) * TAG_LEN,
+            "{} bytes",
+            len
+        );
+        assert_eq!(decrypt(&sealed, &key).unwrap(), plain, "{} bytes", len);
+    }
+}
+
+#[test]
+fn truncated_archives_are_rejected() {
+    let key = DataKey::generate();
+    let sealed = encrypt(&content(2 * CHUNK_SIZE + 100), &key);
+
+    // Cut inside the last chunk, at a chunk boundary, and right after the header
+    for len in [sealed.len() - 1, chunk(1).end, chunk(0).end, HEADER_LEN] {
+        assert!(decrypt(&sealed[..len], &key).is_err(), "{} bytes", len);
+    }
+
+    // A whole chunk followed only by the empty last chunk would be valid, so drop the empty one
+    let sealed = encrypt(&content(CHUNK_SIZE), &key);
+    assert!(decrypt(&sealed[..chunk(0).end], &key).is_err());
+}
+
+#[test]
+fn reordered_chunks_are_rejected() {
+    let key = DataKey::generate();
+    let sealed = encrypt(&content(3 * CHUNK_SIZE + 100), &key);
+
+    let mut reordered = sealed.clone();
+    reordered[chunk(0)].copy_from_slice(&sealed[chunk(1)]);
+    reordered[chunk(1)].copy_from_slice(&sealed[chunk(0)]);
+
+    let error = decrypt(&reordered, &key).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn flipped_tag_bits_are_rejected() {
+    let key = DataKey::generate();
+    let sealed = encrypt(&content(2 * CHUNK_SIZE + 100), &key);
+
+    // The tag of a full chunk, and of the last one
+    for index in [chunk(0).end - 1, sealed.len() - 1] {
+        let mut tampered = sealed.clone();
+        tampered[index] ^= 0x01;
+        let error = decrypt(&tampered, &key).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData, "byte {}", index);
+    }
+}
+
+#[test]
+fn wrong_keys_and_passphrases_are_rejected() {
+    let key = DataKey::generate();
+    let sealed = encrypt(&content(CHUNK_SIZE + 100), &key);
+    assert!(decrypt(&sealed, &DataKey::generate()).is_err());
+    assert!(decrypt(&sealed, &key).is_ok());
+
+    let envelope = KeyEnvelope::seal(&key, "right passphrase").unwrap();
+    assert!(matches!(
+        envelope.open("wrong passphrase"),
+        Err(KaguyaError::WrongPassphrase)
+    ));
+    assert_eq!(envelope.open("right passphrase").unwrap(), key);
+}
@@ -0,0 +1,426 @@
+//! Foreign keys, cascades, migrations and the file index of the vault database.
+
+use filetime::{FileTime, set_file_mtime};
+use kaguya::{
+    db_manager::{
+        DbManager,
+        sqlite::{
+            DbManagerBackupExt, DbManagerFileIndexExt, DbManagerGameExt, DbManagerMetaExt,
+            DbManagerMigrationExt, DbManagerSyncExt,
+            migration::{MIGRATIONS, MigrationStep, SCHEMA_VERSION},
+        },
+    },
+    fs_utils::hash::{FileIndex, calculate_entry_checksum, calculate_entry_checksum_cached},
+    models::{
+        AddGameRequest, DB_FILE, GameConfig, KEY_SCHEMA_VERSION, KaguyaError, VaultConfig,
+        db::{Backup, BackupFile, BackupFileEntry, DeltaLink},
+    },
+};
+use std::{
+    fs::{create_dir_all, metadata, read_dir, write},
+    path::PathBuf,
+};
+
+mod common;
+use common::TestDir;
+
+fn db_path(dir: &TestDir) -> PathBuf {
+    dir.join(DB_FILE)
+}
+
+fn open_migrated(dir: &TestDir) -> DbManager {
+    let mut db = DbManager::open(&db_path(dir)).unwrap();
+    db.migrate(false).unwrap();
+    db
+}
+
+// A database with the schema of an older kaguya, up to migration `version`
+fn open_at_version(dir: &TestDir, version: u32) -> DbManager {
+    let db = DbManager::open(&db_path(dir)).unwrap();
+    for migration in MIGRATIONS.iter().filter(|m| m.version <= version) {
+        let MigrationStep::Sql(sql) = migration.step else {
+            panic!("migration {} is not SQL", migration.version);
+        };
+        db.conn.execute_batch(sql).unwrap();
+    }
+    db
+}
+
+fn vault_config(ids: &[&str]) -> VaultConfig {
+    let games = ids
+        .iter()
+        .map(|id| {
+            GameConfig::from(AddGameRequest {
+                id: id.to_string(),
+                name: None,
+                paths: Some(vec![PathBuf::from(format!("/saves/{}", id))]),
+                comment: None,
+            })
+        })
+        .collect();
+    VaultConfig {
+        games,
+        ..Default::default()
+    }
+}
+
+fn backup_file(original_path: &str, archive_path: &str) -> BackupFile {
+    BackupFile {
+        id: 0,
+        backup_id: 0,
+        original_path: original_path.to_string(),
+        archive_path: archive_path.to_string(),
+        size_bytes: 1,
+        checksum: "checksum".to_string(),
+        source_checksum: None,
+        codec: Some("delta".to_string()),
+        original_size_bytes: None,
+    }
+}
+
+fn entry() -> BackupFileEntry {
+    BackupFileEntry {
+        rel_path: String::new(),
+        mtime_ns: 0,
+        mode: 0o644,
+        hash: None,
+    }
+}
+
+// Record a version of a game with an event, a delta against `base_file_id` if given.
+// Returns the backup ID.
+fn record_backup(
+    db: &mut DbManager,
+    external_id: &str,
+    version: &str,
+    base_file_id: Option<i64>,
+) -> i64 {
+    let game_id = db.get_game_id_with_external_id(external_id).unwrap();
+    let backup_id = db
+        .insert_backup(&Backup {
+            id: 0,
+            game_id,
+            version: version.to_string(),
+            timestamp: "0".to_string(),
+        })
+        .unwrap();
+    let link = DeltaLink {
+        base_file_id,
+        depth: base_file_id.map_or(0, |_| 1),
+        keyframe_interval: 4,
+    };
+    let file = backup_file(
+        &format!("/saves/{}", external_id),
+        &format!("/vault/{}/{}", external_id, version),
+    );
+    db.insert_backup_file(backup_id, vec![(file, vec![entry()], Some(link))])
+        .unwrap();
+    db.conn
+        .execute(
+            "INSERT INTO event (event_type, game_id, backup_id, timestamp) VALUES ('backup', ?1, ?2, '0')",
+            (game_id, backup_id),
+        )
+        .unwrap();
+    backup_id
+}
+
+// Record two versions of a game, the second one a delta against the first.
+// Returns the backup IDs.
+fn record_backups(db: &mut DbManager, external_id: &str) -> (i64, i64) {
+    let first = record_backup(db, external_id, "v1", None);
+    let base_id = db.get_backup_files(first).unwrap()[0].id;
+    let second = record_backup(db, external_id, "v2", Some(base_id));
+    (first, second)
+}
+
+fn count(db: &DbManager, table: &str) -> i64 {
+    db.conn
+        .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+}
+
+#[test]
+fn every_connection_enforces_foreign_keys() {
+    let dir = TestDir::new("pragmas");
+    open_migrated(&dir);
+
+    // A second connection to an existing database is configured the same
+    let db = DbManager::open(&db_path(&dir)).unwrap();
+    let foreign_keys: bool = db
+        .conn
+        .query_row("PRAGMA foreign_keys", [], |row| row.get(0))
+        .unwrap();
+    let journal_mode: String = db
+        .conn
+        .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+        .unwrap();
+    let busy_timeout: i64 = db
+        .conn
+        .query_row("PRAGMA busy_timeout", [], |row| row.get(0))
+        .unwrap();
+
+    assert!(foreign_keys);
+    assert_eq!(journal_mode, "wal");
+    assert!(busy_timeout > 0);
+}
+
+#[test]
+fn removing_a_game_keeps_its_records() {
+    let dir = TestDir::new("remove-game");
+    let mut db = open_migrated(&dir);
+    db.upsert_games_from_config(&vault_config(&["kept", "removed"]))
+        .unwrap();
+    record_backups(&mut db, "kept");
+    record_backups(&mut db, "removed");
+
+    let removed = db.mark_removed_games(&vault_config(&["kept"])).unwrap();
+
+    assert_eq!(removed, vec!["removed".to_string()]);
+    assert!(db.get_db_game("removed").unwrap().removed_at.is_some());
+    assert!(db.get_db_game("kept").unwrap().removed_at.is_none());
+    assert_eq!(count(&db, "game"), 2);
+    assert_eq!(count(&db, "backup"), 4);
+    assert_eq!(count(&db, "backup_file"), 4);
+    assert_eq!(count(&db, "event"), 4);
+
+    // Marked once, and back in use once configured again
+    assert!(
+        db.mark_removed_games(&vault_config(&["kept"]))
+            .unwrap()
+            .is_empty()
+    );
+    db.upsert_games_from_config(&vault_config(&["kept", "removed"]))
+        .unwrap();
+    assert!(db.get_db_game("removed").unwrap().removed_at.is_none());
+}
+
+#[test]
+fn purging_a_game_requires_deleting_its_backups_first() {
+    let dir = TestDir::new("purge-game");
+    let mut db = open_migrated(&dir);
+    db.upsert_games_from_config(&vault_config(&["game"]))
+        .unwrap();
+    let (first, second) = record_backups(&mut db, "game");
+    let game_id = db.get_game_id_with_external_id("game").unwrap();
+
+    assert!(db.delete_game(game_id).is_err());
+    assert_eq!(count(&db, "backup"), 2);
+
+    for backup_id in [second, first] {
+        db.delete_backup(backup_id).unwrap();
+    }
+    db.delete_game(game_id).unwrap();
+    for table in ["game", "game_path", "backup", "backup_file", "event"] {
+        assert_eq!(count(&db, table), 0, "rows left in '{}'", table);
+    }
+}
+
+#[test]
+fn deleting_a_backup_cascades_to_its_files() {
+    let dir = TestDir::new("delete-backup");
+    let mut db = open_migrated(&dir);
+    db.upsert_games_from_config(&vault_config(&["game"]))
+        .unwrap();
+    let (_, second) = record_backups(&mut db, "game");
+
+    db.conn
+        .execute("DELETE FROM backup WHERE id = ?1", [second])
+        .unwrap();
+
+    assert_eq!(count(&db, "backup"), 1);
+    assert_eq!(count(&db, "backup_file"), 1);
+    assert_eq!(count(&db, "backup_file_entry"), 1);
+    assert_eq!(count(&db, "backup_file_delta"), 1);
+    assert_eq!(count(&db, "event"), 1);
+}
+
+#[test]
+fn delta_base_cannot_be_deleted_while_referenced() {
+    let dir = TestDir::new("delta-base");
+    let mut db = open_migrated(&dir);
+    db.upsert_games_from_config(&vault_config(&["game"]))
+        .unwrap();
+    let (first, _) = record_backups(&mut db, "game");
+    let base_id = db.get_backup_files(first).unwrap()[0].id;
+
+    assert!(db.delete_backup_file(base_id).is_err());
+    assert!(db.delete_backup(first).is_err());
+    assert_eq!(count(&db, "backup_file"), 2);
+}
+
+#[test]
+fn migration_removes_orphans_of_older_databases() {
+    let dir = TestDir::new("orphans");
+    {
+        // Schema version 8, written without foreign keys like older kaguya did
+        let db = open_at_version(&dir, 8);
+        db.conn.pragma_update(None, "foreign_keys", false).unwrap();
+        db.conn
+            .execute_batch(
+                "INSERT INTO game (id, external_id, name, created_at, updated_at)
+                    VALUES (1, 'game', 'game', '0', '0');
+                INSERT INTO game_path (game_id, original_path) VALUES (1, '/saves/game'), (2, '/saves/gone');
+                INSERT INTO backup (id, game_id, version, timestamp) VALUES (1, 1, 'v1', '0'), (2, 2, 'v1', '0');
+                INSERT INTO backup_file (id, backup_id, original_path, archive_path, size_bytes, checksum)
+                    VALUES (1, 1, '/saves/game', 'a', 1, 'c'), (2, 2, '/saves/gone', 'b', 1, 'c');
+                INSERT INTO backup_file_entry (backup_file_id, rel_path, mtime_ns, mode) VALUES (1, '', 0, 0), (2, '', 0, 0);
+                INSERT INTO backup_file_delta (backup_file_id, base_file_id, depth, keyframe_interval)
+                    VALUES (1, NULL, 0, 4), (2, NULL, 0, 4);
+                INSERT INTO event (event_type, game_id, backup_id, timestamp) VALUES ('backup', 1, 1, '0'), ('backup', 2, 2, '0');",
+            )
+            .unwrap();
+    }
+
+    let mut db = DbManager::open(&db_path(&dir)).unwrap();
+    let report = db.migrate(false).unwrap();
+
+    assert_eq!(report.from, Some(8));
+    assert!(report.backup_path.is_some_and(|path| path.is_file()));
+    assert_eq!(db.schema_version().unwrap(), Some(SCHEMA_VERSION));
+    for table in [
+        "game_path",
+        "backup",
+        "backup_file",
+        "backup_file_entry",
+        "backup_file_delta",
+        "event",
+    ] {
+        assert_eq!(count(&db, table), 1, "orphans left in '{}'", table);
+    }
+}
+
+#[test]
+fn dry_run_migrations_change_nothing() {
+    let dir = TestDir::new("migrate-dry-run");
+    let mut db = open_at_version(&dir, 8);
+
+    let report = db.migrate(true).unwrap();
+    assert_eq!(report.from, Some(8));
+    assert_eq!(
+        report.applied.iter().map(|m| m.version).collect::<Vec<_>>(),
+        (9..=SCHEMA_VERSION).collect::<Vec<_>>()
+    );
+    assert!(report.backup_path.is_none());
+    assert_eq!(db.schema_version().unwrap(), Some(8));
+    assert!(!read_dir(dir.path()).unwrap().any(|entry| {
+        entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .ends_with(".bak")
+    }));
+
+    // Nothing is left to apply once migrated
+    db.migrate(false).unwrap();
+    assert!(db.migrate(true).unwrap().applied.is_empty());
+}
+
+#[test]
+fn databases_of_newer_kaguya_versions_are_refused() {
+    let dir = TestDir::new("schema-too-new");
+    let db = open_migrated(&dir);
+    db.update_meta_value(KEY_SCHEMA_VERSION, &(SCHEMA_VERSION + 1).to_string())
+        .unwrap();
+    drop(db);
+
+    let mut db = DbManager::open(&db_path(&dir)).unwrap();
+    for dry_run in [true, false] {
+        assert!(matches!(
+            db.migrate(dry_run),
+            Err(KaguyaError::SchemaTooNew(version, SCHEMA_VERSION)) if version == SCHEMA_VERSION + 1
+        ));
+    }
+    assert!(matches!(
+        DbManager::new(&db_path(&dir), &dir.join("vault.toml")),
+        Err(KaguyaError::SchemaTooNew(..))
+    ));
+    assert_eq!(db.schema_version().unwrap(), Some(SCHEMA_VERSION + 1));
+}
+
+#[test]
+fn unreadable_vault_configs_fail_to_sync() {
+    let dir = TestDir::new("sync-errors");
+    let vault_config_path = dir.join("vault.toml");
+    let mut db = open_migrated(&dir);
+
+    // Nothing to sync without a vault config
+    db.sync(&vault_config_path, false).unwrap();
+    db.sync(&vault_config_path, true).unwrap();
+
+    write(
+        &vault_config_path,
+        "[backup]\nauto_prune = false\nkeep_versions = 0\ncompression = \"tar.gz\"\n\n[[games]]\nid = \"game\"\nname = \"Game\"\npaths = [\"/saves/game\"]\n",
+    )
+    .unwrap();
+    db.sync(&vault_config_path, false).unwrap();
+    assert!(db.get_db_game("game").is_ok());
+
+    for broken in [
+        "include = [\"missing.toml\"]\n[backup]\nauto_prune = false\nkeep_versions = 0\ncompression = \"tar.gz\"\n",
+        "[backup\n",
+    ] {
+        write(&vault_config_path, broken).unwrap();
+        for force in [false, true] {
+            assert!(
+                db.sync(&vault_config_path, force).is_err(),
+                "synced {:?}",
+                broken
+            );
+        }
+    }
+    // The database is left as it was
+    assert!(db.get_db_game("game").unwrap().removed_at.is_none());
+}
+
+#[test]
+fn file_index_reuses_hashes_of_unchanged_files_only() {
+    let dir = TestDir::new("db-file-index");
+    let mut db = open_migrated(&dir);
+    let saves = dir.join("saves");
+    let save_path = saves.join("save.dat");
+    create_dir_all(&saves).unwrap();
+    write(&save_path, "version 1").unwrap();
+    write(saves.join("options.ini"), "volume = 80").unwrap();
+
+    let (checksum, index) =
+        calculate_entry_checksum_cached(&saves, &FileIndex::new(), false).unwrap();
+    assert_eq!(checksum, calculate_entry_checksum(&saves).unwrap());
+    db.replace_file_index(&saves, &index).unwrap();
+    assert_eq!(db.get_file_index(&saves).unwrap(), index);
+
+    // Hashes of the index are replaced by a marker, so a reused one shows in the result
+    let cached = |db: &DbManager, paranoid: bool| {
+        let mut index = db.get_file_index(&saves).unwrap();
+        for entry in index.values_mut() {
+            entry.hash = "cached".to_string();
+        }
+        let (_, index) = calculate_entry_checksum_cached(&saves, &index, paranoid).unwrap();
+        let mut cached: Vec<_> = index
+            .into_iter()
+            .filter(|(_, entry)| entry.hash == "cached")
+            .map(|(rel_path, _)| rel_path)
+            .collect();
+        cached.sort();
+        cached
+    };
+    assert_eq!(cached(&db, false), vec!["options.ini", "save.dat"]);
+    assert_eq!(cached(&db, true), Vec::<String>::new());
+
+    // A different size with the same mtime, then the same size with another mtime
+    let mtime = FileTime::from_last_modification_time(&metadata(&save_path).unwrap());
+    write(&save_path, "version 10").unwrap();
+    set_file_mtime(&save_path, mtime).unwrap();
+    assert_eq!(cached(&db, false), vec!["options.ini"]);
+
+    let (_, index) = calculate_entry_checksum_cached(&saves, &index, false).unwrap();
+    db.replace_file_index(&saves, &index).unwrap();
+    write(&save_path, "version 20").unwrap();
+    set_file_mtime(
+        &save_path,
+        FileTime::from_unix_time(mtime.unix_seconds() + 5, 0),
+    )
+    .unwrap();
+    assert_eq!(cached(&db, false), vec!["options.ini"]);
+}
@@ -0,0 +1,109 @@
+//! The vault lock, between holders of this process and with the database opened under it.
+
+use kaguya::{
+    db_manager::DbManager,
+    fs_utils::lock::{LockMode, VaultLock},
+    models::{DB_FILE, KaguyaError, LOCK_FILE, VAULT_CONFIG_FILE},
+};
+use std::{
+    fs::{read_to_string, write},
+    process,
+};
+
+mod common;
+use common::TestDir;
+
+// A PID above the kernel limit, never running
+const STALE_PID: u32 = 99_999_999;
+
+fn lock(dir: &TestDir, mode: LockMode) -> Result<VaultLock, KaguyaError> {
+    VaultLock::acquire(&dir.path(), mode)
+}
+
+fn open_db(dir: &TestDir) -> Result<DbManager, KaguyaError> {
+    DbManager::new(&dir.join(DB_FILE), &dir.join(VAULT_CONFIG_FILE))
+}
+
+// Message of the error of a lock that is busy
+fn busy(result: Result<VaultLock, KaguyaError>) -> String {
+    match result {
+        Err(e @ KaguyaError::VaultLocked(_)) => e.to_string(),
+        other => panic!("lock not refused: {:?}", other),
+    }
+}
+
+#[test]
+fn exclusive_locks_exclude_every_other_holder() {
+    let dir = TestDir::new("lock-modes");
+
+    let first = lock(&dir, LockMode::Shared).unwrap();
+    let second = lock(&dir, LockMode::Shared).unwrap();
+    busy(lock(&dir, LockMode::Exclusive));
+    drop(first);
+    busy(lock(&dir, LockMode::Exclusive));
+    drop(second);
+
+    let exclusive = lock(&dir, LockMode::Exclusive).unwrap();
+    busy(lock(&dir, LockMode::Shared));
+    busy(lock(&dir, LockMode::Exclusive));
+    drop(exclusive);
+    lock(&dir, LockMode::Shared).unwrap();
+}
+
+#[test]
+fn busy_locks_name_their_running_holders() {
+    let dir = TestDir::new("lock-holders");
+    let lock_path = dir.join(LOCK_FILE);
+    write(&lock_path, format!("{} kaguya vault backup\n", STALE_PID)).unwrap();
+
+    // The holder is named with its command line, the process that is gone is not
+    let shared = lock(&dir, LockMode::Shared).unwrap();
+    let message = busy(lock(&dir, LockMode::Exclusive));
+    assert!(
+        message.contains(&format!("PID {} (", process::id())),
+        "{}",
+        message
+    );
+    assert!(!message.contains(&STALE_PID.to_string()), "{}", message);
+    drop(shared);
+
+    // The next exclusive holder clears the stale entry, and its own once released
+    let exclusive = lock(&dir, LockMode::Exclusive).unwrap();
+    let holders = read_to_string(&lock_path).unwrap();
+    assert!(holders.starts_with(&format!("{} ", process::id())));
+    assert_eq!(holders.lines().count(), 1);
+    drop(exclusive);
+    assert_eq!(read_to_string(&lock_path).unwrap(), "");
+}
+
+#[test]
+fn databases_are_migrated_under_the_exclusive_lock() {
+    let dir = TestDir::new("lock-database");
+
+    // A new database has to be created, not while the vault is shared
+    let shared = lock(&dir, LockMode::Shared).unwrap();
+    assert!(matches!(open_db(&dir), Err(KaguyaError::VaultLocked(_))));
+    drop(shared);
+    open_db(&dir).unwrap();
+
+    // Once it is up to date, opening it only reads it
+    let shared = lock(&dir, LockMode::Shared).unwrap();
+    open_db(&dir).unwrap();
+    drop(shared);
+    let exclusive = lock(&dir, LockMode::Exclusive).unwrap();
+    assert!(matches!(open_db(&dir), Err(KaguyaError::VaultLocked(_))));
+    drop(exclusive);
+
+    // A changed vault config is synced, not while the vault is shared either
+    write(
+        dir.join(VAULT_CONFIG_FILE),
+        "[backup]\nauto_prune = false\nkeep_versions = 0\ncompression = \"tar.gz\"\n",
+    )
+    .unwrap();
+    let shared = lock(&dir, LockMode::Shared).unwrap();
+    assert!(matches!(open_db(&dir), Err(KaguyaError::VaultLocked(_))));
+    drop(shared);
+    open_db(&dir).unwrap();
+    let _shared = lock(&dir, LockMode::Shared).unwrap();
+    open_db(&dir).unwrap();
+}
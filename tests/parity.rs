@@ -0,0 +1,121 @@
+//! Repairing damaged files from their parity files.
+
+use kaguya::{
+    fs_utils::parity::{parity_path, repair_with_parity, scan_parity, write_parity},
+    models::KaguyaError,
+};
+use std::{
+    fs::{read, write},
+    path::PathBuf,
+};
+
+mod common;
+use common::TestDir;
+
+// A file of 128 shards of 256 bytes, a single stripe: 10% parity is 13 parity shards
+const FILE_SIZE: usize = 128 * 256;
+const SHARD_SIZE: usize = 256;
+const PARITY_PERCENT: u32 = 10;
+const PARITY_SHARDS: usize = 13;
+
+// A protected file in a fresh directory, removed when dropped
+struct TestFile {
+    _dir: TestDir,
+    path: PathBuf,
+    content: Vec<u8>,
+}
+
+impl TestFile {
+    fn new(name: &str) -> Self {
+        let dir = TestDir::new(name);
+        let path = dir.join("saves.tar.gz");
+        let content: Vec<u8> = (0..FILE_SIZE).map(|i| (i * 7 % 253) as u8).collect();
+        write(&path, &content).unwrap();
+        write_parity(&path, PARITY_PERCENT).unwrap();
+        Self {
+            _dir: dir,
+            path,
+            content,
+        }
+    }
+
+    // Flip a byte in each of the data shards `shards`
+    fn damage_shards(&self, shards: impl IntoIterator<Item = usize>) {
+        let mut content = read(&self.path).unwrap();
+        for shard in shards {
+            content[shard * SHARD_SIZE + 17] ^= 0xff;
+        }
+        write(&self.path, content).unwrap();
+    }
+}
+
+#[test]
+fn damage_within_parity_is_repaired_exactly() {
+    for damaged in [1, PARITY_SHARDS / 2, PARITY_SHARDS] {
+        let file = TestFile::new(&format!("parity-repair-{}", damaged));
+        // Spread over the file, the first and last shards included
+        file.damage_shards((0..damaged).map(|i| i * 127 / (damaged.max(2) - 1)));
+
+        let scan = scan_parity(&file.path).unwrap();
+        assert_eq!(scan.damaged_shards, damaged);
+        assert!(scan.is_repairable());
+
+        let repaired = repair_with_parity(&file.path).unwrap();
+        assert_eq!(repaired.damaged_shards, damaged);
+        assert!(
+            read(&file.path).unwrap() == file.content,
+            "{} shards",
+            damaged
+        );
+        assert!(scan_parity(&file.path).unwrap().is_intact());
+    }
+}
+
+#[test]
+fn damage_beyond_parity_is_unrepairable() {
+    let file = TestFile::new("parity-unrepairable");
+    file.damage_shards(0..=PARITY_SHARDS);
+    let damaged = read(&file.path).unwrap();
+
+    let scan = scan_parity(&file.path).unwrap();
+    assert_eq!(scan.damaged_shards, PARITY_SHARDS + 1);
+    assert_eq!(scan.unrecoverable_stripes, 1);
+    assert!(!scan.is_repairable());
+
+    // The file is left as it was
+    assert!(matches!(
+        repair_with_parity(&file.path),
+        Err(KaguyaError::Parity(_))
+    ));
+    assert!(read(&file.path).unwrap() == damaged);
+}
+
+#[test]
+fn damaged_parity_files_are_detected() {
+    let file = TestFile::new("parity-damaged");
+    let parity = parity_path(&file.path);
+    let intact = read(&parity).unwrap();
+
+    // The last bytes of the parity file belong to the last parity shard
+    let mut damaged = intact.clone();
+    *damaged.last_mut().unwrap() ^= 0xff;
+    write(&parity, &damaged).unwrap();
+
+    let scan = scan_parity(&file.path).unwrap();
+    assert_eq!(scan.damaged_parity, 1);
+    assert!(!scan.file_damaged());
+
+    // Repairing writes the parity again, and leaves the file untouched
+    repair_with_parity(&file.path).unwrap();
+    assert!(scan_parity(&file.path).unwrap().is_intact());
+    assert!(read(&file.path).unwrap() == file.content);
+
+    // A damaged header can't be trusted at all
+    let mut damaged = intact;
+    damaged[10] ^= 0xff;
+    write(&parity, &damaged).unwrap();
+    assert!(matches!(
+        scan_parity(&file.path),
+        Err(KaguyaError::Parity(_))
+    ));
+}
@@ -0,0 +1,760 @@
+//! Backups, prunes and restores of a whole vault, run like the CLI runs them.
+
+use kaguya::{
+    cli::AppContext,
+    core::{ConfigService, VaultService},
+    db_manager::{
+        DbManager,
+        sqlite::{DbManagerBackupExt, DbManagerGameExt, DbManagerMetaExt},
+    },
+    fs_utils::signature::create_signing_key,
+    models::{
+        BACKUP_DIR, BackupRequest, DB_FILE, KEY_ENCRYPTION, KEY_ENVELOPE_FILE, KaguyaError,
+        OBJECTS_DIR, PathStatusKind, RmGameRequest, VAULT_CONFIG_FILE,
+        db::BackupFile,
+        requests::{
+            CheckRequest, ExportRequest, PruneRequest, ReindexRequest, RestoreRequest,
+            StatusRequest,
+        },
+    },
+    utils::time::get_time_string,
+};
+use std::{
+    fs::{
+        File, create_dir_all, read, read_dir, read_to_string, remove_dir_all, remove_file, write,
+    },
+    io::Read,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+use zip::ZipArchive;
+
+mod common;
+use common::TestDir;
+
+// A vault with a single game 'game' backing up the directory `saves`, removed when dropped
+struct TestVault {
+    dir: TestDir,
+    context: AppContext,
+}
+
+impl TestVault {
+    // `backup` is the '[backup]' table of the vault config
+    fn new(name: &str, backup: &str) -> Self {
+        let dir = TestDir::new(name);
+        let vault_dir = dir.join("vault");
+        create_dir_all(&vault_dir).unwrap();
+        create_dir_all(dir.join("saves")).unwrap();
+
+        let context = AppContext {
+            global_config_path: dir.join("config.toml"),
+            vault_config_path: vault_dir.join(VAULT_CONFIG_FILE),
+            backup_dir: vault_dir.join(BACKUP_DIR),
+            objects_dir: vault_dir.join(OBJECTS_DIR),
+            db_path: vault_dir.join(DB_FILE),
+            vault_dir,
+            dry_run: false,
+        };
+        let vault = Self { dir, context };
+        vault.configure(backup);
+        vault
+    }
+
+    fn saves(&self) -> PathBuf {
+        self.dir.join("saves")
+    }
+
+    // Write the vault config with `backup` added to its '[backup]' table
+    fn configure(&self, backup: &str) {
+        let content = format!(
+            "[backup]\nauto_prune = false\nkeep_versions = 0\ncompression = \"tar.gz\"\n{}\n\n[[games]]\nid = \"game\"\nname = \"Game\"\npaths = [\"{}\"]\n",
+            backup,
+            self.saves().display()
+        );
+        write(&self.context.vault_config_path, content).unwrap();
+    }
+
+    // Encrypt new archives, with the passphrase in a key file
+    fn encrypt(&self) {
+        let key_file = self.dir.join("passphrase");
+        write(&key_file, "passphrase of the test\n").unwrap();
+        self.configure(&format!(
+            "encryption = true\nkey_file = \"{}\"",
+            key_file.display()
+        ));
+    }
+
+    // A service of a new run, opening the database like the CLI does
+    fn service(&self) -> VaultService {
+        let db = DbManager::new(&self.context.db_path, &self.context.vault_config_path).unwrap();
+        VaultService::new(self.context.clone(), db)
+    }
+
+    fn db(&self) -> DbManager {
+        DbManager::open(&self.context.db_path).unwrap()
+    }
+
+    // Back up the saves as a new version, returning its name.
+    // Versions are named by the second, so this waits for the next one.
+    fn backup(&self) -> String {
+        wait_for_next_second();
+        self.run_backup(false).unwrap();
+        self.versions().pop().unwrap()
+    }
+
+    // Back up the saves right away, like 'kaguya vault backup [--force]'
+    fn run_backup(&self, force: bool) -> Result<(), KaguyaError> {
+        self.service().backup(BackupRequest {
+            id: None,
+            paths: None,
+            force,
+            paranoid: false,
+            jobs: None,
+        })
+    }
+
+    fn versions(&self) -> Vec<String> {
+        let db = self.db();
+        let game_id = db.get_game_id_with_external_id("game").unwrap();
+        db.get_backups(game_id)
+            .unwrap()
+            .into_iter()
+            .map(|backup| backup.version)
+            .collect()
+    }
+
+    // Every recorded backup file as (version, timestamp, file), without its record IDs
+    fn records(&self) -> Vec<(String, String, BackupFile)> {
+        let db = self.db();
+        let game_id = db.get_game_id_with_external_id("game").unwrap();
+        db.get_backups(game_id)
+            .unwrap()
+            .into_iter()
+            .flat_map(|backup| {
+                db.get_backup_files(backup.id)
+                    .unwrap()
+                    .into_iter()
+                    .map(move |file| {
+                        let file = BackupFile {
+                            id: 0,
+                            backup_id: 0,
+                            ..file
+                        };
+                        (backup.version.clone(), backup.timestamp.clone(), file)
+                    })
+            })
+            .collect()
+    }
+
+    // Depth in its delta chain of the file of every version, oldest first
+    fn delta_depths(&self) -> Vec<u32> {
+        let db = self.db();
+        let game_id = db.get_game_id_with_external_id("game").unwrap();
+        db.get_backups(game_id)
+            .unwrap()
+            .into_iter()
+            .map(|backup| {
+                let file = db.get_backup_files(backup.id).unwrap().remove(0);
+                db.get_delta_link(file.id).unwrap().unwrap().depth
+            })
+            .collect()
+    }
+
+    // Number of files in the object store
+    fn objects(&self) -> usize {
+        fn count(dir: &Path) -> usize {
+            read_dir(dir)
+                .unwrap()
+                .map(|entry| {
+                    let path = entry.unwrap().path();
+                    if path.is_dir() { count(&path) } else { 1 }
+                })
+                .sum()
+        }
+        count(&self.context.objects_dir)
+    }
+
+    fn prune(&self, version: &str) {
+        self.service()
+            .prune(&PruneRequest {
+                id: Some("game".to_string()),
+                version: Some(version.to_string()),
+                purge: false,
+            })
+            .unwrap();
+    }
+
+    // Problems found by 'vault check --verify-signatures', as (version, problem)
+    fn verify_signatures(&self) -> Result<Vec<(String, String)>, KaguyaError> {
+        self.check(true)
+    }
+
+    // Problems found by 'vault check', as (version, problem)
+    fn check(&self, verify_signatures: bool) -> Result<Vec<(String, String)>, KaguyaError> {
+        Ok(self
+            .service()
+            .check(&CheckRequest {
+                id: None,
+                verify_signatures,
+            })?
+            .into_iter()
+            .filter_map(|check| Some((check.version, check.problem?)))
+            .collect())
+    }
+
+    fn restore(&self, version: &str) {
+        self.service()
+            .restore(&RestoreRequest {
+                id: "game".to_string(),
+                version: Some(version.to_string()),
+                paths: None,
+            })
+            .unwrap();
+    }
+}
+
+// Wait until the version name of a backup changes
+fn wait_for_next_second() {
+    let version = get_time_string();
+    while get_time_string() == version {
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// Pseudo-random save content, the same for the same seed
+fn save_content(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn pruning_delta_bases_keeps_later_versions_restorable() {
+    let vault = TestVault::new("delta-prune", "storage = \"delta\"");
+    let save_path = vault.saves().join("save.bin");
+
+    // Each version changes a few bytes of the previous one
+    let mut content = save_content(1, 256 * 1024);
+    let mut versions = Vec::new();
+    for round in 0..5 {
+        let offset = round * 40 * 1024;
+        content[offset..offset + 64].copy_from_slice(&save_content(round as u64 + 2, 64));
+        write(&save_path, &content).unwrap();
+        versions.push((vault.backup(), content.clone()));
+    }
+    assert_eq!(vault.versions().len(), 5);
+    assert_eq!(vault.delta_depths(), vec![0, 1, 2, 3, 4]);
+
+    // The keyframe every other version is a delta against, then a version in the middle
+    vault.prune(&versions[0].0);
+    vault.prune(&versions[2].0);
+    versions.remove(2);
+    versions.remove(0);
+    assert_eq!(
+        vault.versions(),
+        versions.iter().map(|(v, _)| v.clone()).collect::<Vec<_>>()
+    );
+    assert_eq!(vault.delta_depths(), vec![0, 1, 2]);
+
+    for (version, expected) in &versions {
+        write(&save_path, b"overwritten").unwrap();
+        vault.restore(version);
+        assert!(
+            read(&save_path).unwrap() == *expected,
+            "version {} restored with different content",
+            version
+        );
+    }
+}
+
+#[test]
+fn removed_signatures_are_reported() {
+    let vault = TestVault::new("signatures", "");
+    let save_path = vault.saves().join("save.dat");
+    let signing_key = vault.dir.join("signing.key");
+
+    // Signing is enabled after the first version
+    write(&save_path, b"version 1").unwrap();
+    let unsigned = vault.backup();
+    vault.configure(&format!("signing_key = \"{}\"", signing_key.display()));
+    let mut signed = Vec::new();
+    for round in 2..=3 {
+        write(&save_path, format!("version {}", round)).unwrap();
+        signed.push(vault.backup());
+    }
+    assert_eq!(vault.verify_signatures().unwrap(), vec![]);
+
+    // Whoever can write to the vault replaces the latest archive, along with its checksum,
+    // and removes every signature
+    let db = vault.db();
+    let files: Vec<_> = signed
+        .iter()
+        .map(|version| {
+            let backup = db
+                .get_backups(db.get_game_id_with_external_id("game").unwrap())
+                .unwrap()
+                .into_iter()
+                .find(|backup| &backup.version == version)
+                .unwrap();
+            db.get_backup_files(backup.id).unwrap().remove(0)
+        })
+        .collect();
+    write(
+        &files[1].archive_path,
+        read(&files[0].archive_path).unwrap(),
+    )
+    .unwrap();
+    db.conn
+        .execute(
+            "UPDATE backup_file SET checksum = ?1 WHERE id = ?2",
+            (&files[0].checksum, files[1].id),
+        )
+        .unwrap();
+    let game_dir = vault.context.backup_dir.join("game");
+    for version in read_dir(&game_dir).unwrap() {
+        remove_file(version.unwrap().path().join("kaguya-manifest.toml.sig")).ok();
+    }
+
+    // Every version since signing was enabled is reported, the one before it is not
+    let problems = vault.verify_signatures().unwrap();
+    for version in &signed {
+        assert!(
+            problems.iter().any(|(v, _)| v == version),
+            "version {} not reported: {:?}",
+            version,
+            problems
+        );
+    }
+    assert!(!problems.iter().any(|(v, _)| *v == unsigned));
+
+    // Nor can the vault be verified, or signed again, with another key
+    let other_key = vault.dir.join("other.key");
+    create_signing_key(&other_key).unwrap();
+    vault.configure(&format!("signing_key = \"{}\"", other_key.display()));
+    assert!(matches!(
+        vault.verify_signatures(),
+        Err(KaguyaError::Signature(_))
+    ));
+    assert!(matches!(
+        vault.run_backup(false),
+        Err(KaguyaError::Signature(_))
+    ));
+}
+
+#[test]
+fn removed_games_keep_their_objects_until_purged() {
+    let vault = TestVault::new("removed-game", "storage = \"objects\"");
+    let save_path = vault.saves().join("save.dat");
+    write(&save_path, save_content(1, 4096)).unwrap();
+    let version = vault.backup();
+    let objects = vault.objects();
+    assert!(objects > 0);
+
+    ConfigService::new(vault.context.clone())
+        .rm_game(&RmGameRequest {
+            id: "game".to_string(),
+            purge: false,
+        })
+        .unwrap();
+    vault
+        .service()
+        .prune(&PruneRequest {
+            id: None,
+            version: None,
+            purge: false,
+        })
+        .unwrap();
+
+    // The backup is still recorded, and its objects still stored
+    assert_eq!(vault.versions(), vec![version]);
+    assert_eq!(vault.objects(), objects);
+
+    // Even once the database lost track of it
+    remove_file(&vault.context.db_path).unwrap();
+    vault
+        .service()
+        .prune(&PruneRequest {
+            id: None,
+            version: None,
+            purge: false,
+        })
+        .unwrap();
+    assert_eq!(vault.objects(), objects);
+}
+
+#[test]
+fn purging_a_removed_game_deletes_its_backups() {
+    let vault = TestVault::new("purged-game", "storage = \"objects\"");
+    write(vault.saves().join("save.dat"), save_content(2, 4096)).unwrap();
+    vault.backup();
+
+    ConfigService::new(vault.context.clone())
+        .rm_game(&RmGameRequest {
+            id: "game".to_string(),
+            purge: true,
+        })
+        .unwrap();
+
+    assert_eq!(vault.objects(), 0);
+    assert!(!vault.context.backup_dir.join("game").exists());
+    assert!(vault.db().get_game_id_with_external_id("game").is_err());
+}
+
+#[test]
+fn backups_within_a_second_get_versions_of_their_own() {
+    let vault = TestVault::new("same-second", "");
+    let save_path = vault.saves().join("save.dat");
+
+    // Both runs start within the same second
+    wait_for_next_second();
+    for round in 1..=2 {
+        write(&save_path, format!("version {}", round)).unwrap();
+        vault.run_backup(false).unwrap();
+    }
+
+    let versions = vault.versions();
+    assert_eq!(versions.len(), 2);
+    assert_ne!(versions[0], versions[1]);
+    assert_eq!(vault.check(false).unwrap(), vec![]);
+    for (round, version) in versions.iter().enumerate() {
+        vault.restore(version);
+        assert_eq!(
+            read(&save_path).unwrap(),
+            format!("version {}", round + 1).into_bytes()
+        );
+    }
+}
+
+#[test]
+fn encryption_keys_outlive_the_database() {
+    let vault = TestVault::new("encryption-key", "");
+    vault.encrypt();
+    let save_path = vault.saves().join("save.dat");
+    let key_path = vault.context.vault_dir.join(KEY_ENVELOPE_FILE);
+
+    write(&save_path, b"version 1").unwrap();
+    vault.backup();
+    let envelope = read_to_string(&key_path).unwrap();
+    assert_eq!(vault.db().get_meta_value(KEY_ENCRYPTION).unwrap(), envelope);
+
+    // A new database takes the key over, and later archives are encrypted with it
+    remove_file(&vault.context.db_path).unwrap();
+    write(&save_path, b"version 2").unwrap();
+    let version = vault.backup();
+    assert_eq!(read_to_string(&key_path).unwrap(), envelope);
+    assert_eq!(vault.db().get_meta_value(KEY_ENCRYPTION).unwrap(), envelope);
+    write(&save_path, b"overwritten").unwrap();
+    vault.restore(&version);
+    assert_eq!(read(&save_path).unwrap(), b"version 2");
+
+    // Without any copy of the key, no other key is created for the encrypted archives
+    remove_file(&vault.context.db_path).unwrap();
+    remove_file(&key_path).unwrap();
+    write(&save_path, b"version 3").unwrap();
+    assert!(matches!(
+        vault.run_backup(false),
+        Err(KaguyaError::Encryption(_))
+    ));
+    assert!(!key_path.exists());
+}
+
+#[test]
+fn reindexing_a_lost_database_restores_every_version() {
+    let vault = TestVault::new("reindex", "");
+    vault.encrypt();
+    let save_path = vault.saves().join("save.dat");
+    let mut versions = Vec::new();
+    for round in 1..=3 {
+        let content = save_content(round, 8192);
+        write(&save_path, &content).unwrap();
+        versions.push((vault.backup(), content));
+    }
+    let records = vault.records();
+    assert_eq!(records.len(), 3);
+
+    remove_file(&vault.context.db_path).unwrap();
+    let report = vault
+        .service()
+        .reindex(&ReindexRequest { id: None })
+        .unwrap();
+
+    assert_eq!(report.games.len(), 1);
+    assert_eq!(report.games[0].versions, 3);
+    assert_eq!(report.games[0].from_manifests, 3);
+    assert!(report.unattributed.is_empty() && report.mismatched.is_empty());
+    assert_eq!(vault.records(), records);
+    assert_eq!(vault.check(false).unwrap(), vec![]);
+    for (version, content) in &versions {
+        write(&save_path, b"overwritten").unwrap();
+        vault.restore(version);
+        assert!(
+            read(&save_path).unwrap() == *content,
+            "version {} restored with different content",
+            version
+        );
+    }
+}
+
+#[test]
+fn objects_are_shared_across_versions_until_pruned() {
+    let vault = TestVault::new("objects-dedup", "storage = \"objects\"");
+    let (kept, changed) = (
+        vault.saves().join("kept.dat"),
+        vault.saves().join("changed.dat"),
+    );
+    write(&kept, save_content(1, 4096)).unwrap();
+    write(&changed, save_content(2, 4096)).unwrap();
+    let first = vault.backup();
+    assert_eq!(vault.objects(), 2);
+
+    // Only the changed file is stored again
+    write(&changed, save_content(3, 4096)).unwrap();
+    let second = vault.backup();
+    assert_eq!(vault.objects(), 3);
+
+    // Pruning the first version collects its object no other version uses,
+    // and objects an interrupted run left half-written
+    write(
+        vault.context.objects_dir.join(".kaguya-object-leftover"),
+        b"partial",
+    )
+    .unwrap();
+    vault.prune(&first);
+    assert_eq!(vault.versions(), vec![second.clone()]);
+    assert_eq!(vault.objects(), 2);
+
+    remove_file(&kept).unwrap();
+    write(&changed, b"overwritten").unwrap();
+    vault.restore(&second);
+    assert_eq!(read(&kept).unwrap(), save_content(1, 4096));
+    assert_eq!(read(&changed).unwrap(), save_content(3, 4096));
+}
+
+#[test]
+fn chunked_files_store_only_changed_chunks() {
+    let vault = TestVault::new("chunks", "storage = \"chunks\"");
+    let save_path = vault.saves().join("save.bin");
+    let mut content = save_content(4, 1024 * 1024);
+    write(&save_path, &content).unwrap();
+    let first = vault.backup();
+    let chunks = vault.objects();
+    assert!(chunks > 4, "{} chunks", chunks);
+
+    // A few bytes in the middle change a chunk or two
+    content[512 * 1024..512 * 1024 + 16].copy_from_slice(&[0; 16]);
+    write(&save_path, &content).unwrap();
+    let second = vault.backup();
+    let added = vault.objects() - chunks;
+    assert!((1..=3).contains(&added), "{} chunks added", added);
+
+    for (version, expected) in [(first, save_content(4, 1024 * 1024)), (second, content)] {
+        write(&save_path, b"overwritten").unwrap();
+        vault.restore(&version);
+        assert!(
+            read(&save_path).unwrap() == expected,
+            "version {} reassembled with different content",
+            version
+        );
+    }
+}
+
+#[test]
+fn mirrored_saves_named_like_object_manifests_are_not_read() {
+    let vault = TestVault::new("mirror-manifest-name", "");
+    let save_path = vault.saves().join("save.manifest.toml");
+    write(&save_path, "not [an object manifest").unwrap();
+    write(
+        &vault.context.vault_config_path,
+        format!(
+            "{}storage = \"mirror\"\n\n[[games]]\nid = \"game\"\nname = \"Game\"\npaths = [\"{}\"]\n",
+            "[backup]\nauto_prune = false\nkeep_versions = 0\ncompression = \"tar.gz\"\n",
+            save_path.display()
+        ),
+    )
+    .unwrap();
+
+    let version = vault.backup();
+    assert!(
+        vault
+            .context
+            .backup_dir
+            .join("game")
+            .join(&version)
+            .join("save.manifest.toml")
+            .is_file()
+    );
+    vault
+        .service()
+        .prune(&PruneRequest {
+            id: None,
+            version: None,
+            purge: false,
+        })
+        .unwrap();
+}
+
+#[test]
+fn versions_are_exported_as_plain_zip_archives() {
+    let vault = TestVault::new("export-zip", "");
+    vault.encrypt();
+    write(vault.saves().join("save.dat"), b"exported save").unwrap();
+    write(vault.saves().join("screenshot.png"), save_content(1, 4096)).unwrap();
+    let version = vault.backup();
+
+    let output = vault.dir.join("export.zip");
+    vault
+        .service()
+        .export(&ExportRequest {
+            id: "game".to_string(),
+            version: None,
+            format: "zip".to_string(),
+            output: Some(output.clone()),
+        })
+        .unwrap();
+
+    // Readable without the vault key, with every path under the name of the version
+    let mut zip = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+    let mut content = |name: &str| {
+        let mut content = Vec::new();
+        zip.by_name(&format!("game-{}/saves/{}", version, name))
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(content("save.dat"), b"exported save");
+    assert!(content("screenshot.png") == save_content(1, 4096));
+}
+
+#[test]
+fn saves_too_large_for_deltas_are_archived() {
+    let vault = TestVault::new(
+        "delta-max-size",
+        "storage = \"delta\"\ndelta_max_size_mb = 1",
+    );
+    let save_path = vault.saves().join("save.bin");
+    let codecs = |vault: &TestVault| {
+        vault
+            .records()
+            .into_iter()
+            .map(|(_, _, file)| file.codec.unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // A save growing past the limit is archived as a whole (random content in a plain tar),
+    // and a delta chain starts
+    // over once it shrinks again
+    let mut versions = Vec::new();
+    for (seed, len) in [(1, 512 * 1024), (2, 2 * 1024 * 1024), (3, 512 * 1024)] {
+        write(&save_path, save_content(seed, len)).unwrap();
+        versions.push((vault.backup(), save_content(seed, len)));
+    }
+    assert_eq!(codecs(&vault), vec!["delta", "tar", "delta"]);
+    assert_eq!(vault.check(false).unwrap(), vec![]);
+
+    for (version, expected) in &versions {
+        write(&save_path, b"overwritten").unwrap();
+        vault.restore(version);
+        assert!(read(&save_path).unwrap() == *expected);
+    }
+
+    // A limit beyond what a zstd window spans is refused
+    vault.configure("storage = \"delta\"\ndelta_max_size_mb = 4096");
+    write(&save_path, b"changed").unwrap();
+    assert!(matches!(
+        vault.run_backup(false),
+        Err(KaguyaError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn unchanged_saves_are_backed_up_again_only_when_forced_or_stored_differently() {
+    let vault = TestVault::new("skip-unchanged", "");
+    write(vault.saves().join("save.dat"), b"unchanged save").unwrap();
+    vault.backup();
+
+    let backup_again = |force: bool| {
+        let versions = vault.versions().len();
+        wait_for_next_second();
+        vault.run_backup(force).unwrap();
+        vault.versions().len() > versions
+    };
+    assert!(!backup_again(false));
+    assert!(backup_again(true));
+
+    // A change of the archive format, storage mode or encryption stores it anew, once
+    let config = read_to_string(&vault.context.vault_config_path).unwrap();
+    let key_file = vault.dir.join("passphrase");
+    write(&key_file, "passphrase of the test\n").unwrap();
+    for backup in [
+        "compression = \"tar.zst\"".to_string(),
+        "compression = \"tar.zst\"\nstorage = \"objects\"".to_string(),
+        format!(
+            "compression = \"tar.zst\"\nencryption = true\nkey_file = \"{}\"",
+            key_file.display()
+        ),
+    ] {
+        let content = config.replace("compression = \"tar.gz\"", &backup);
+        write(&vault.context.vault_config_path, content).unwrap();
+        assert!(backup_again(false), "{}", backup);
+        assert!(!backup_again(false), "{}", backup);
+    }
+}
+
+#[test]
+fn status_compares_saves_with_their_latest_backup() {
+    let vault = TestVault::new("status", "");
+    let save_path = vault.saves().join("save.dat");
+    let status = |paranoid: bool| {
+        let mut games = vault
+            .service()
+            .status(&StatusRequest { id: None, paranoid })
+            .unwrap();
+        assert_eq!(games.len(), 1);
+        games.remove(0).paths.remove(0).kind
+    };
+
+    write(&save_path, b"version 1").unwrap();
+    assert_eq!(status(false), PathStatusKind::NeverBackedUp);
+
+    let version = vault.backup();
+    assert_eq!(
+        status(false),
+        PathStatusKind::UpToDate {
+            version: version.clone()
+        }
+    );
+
+    write(&save_path, b"version 2").unwrap();
+    assert_eq!(
+        status(true),
+        PathStatusKind::Modified {
+            version: version.clone()
+        }
+    );
+    write(&save_path, b"version 1").unwrap();
+    assert_eq!(
+        status(true),
+        PathStatusKind::UpToDate {
+            version: version.clone()
+        }
+    );
+
+    // Backups of older kaguya versions don't record the checksum of their source
+    vault
+        .db()
+        .conn
+        .execute("UPDATE backup_file SET source_checksum = NULL", ())
+        .unwrap();
+    assert_eq!(status(false), PathStatusKind::Unknown { version });
+
+    remove_dir_all(vault.saves()).unwrap();
+    assert_eq!(status(false), PathStatusKind::Missing);
+}